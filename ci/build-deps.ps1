@@ -13,6 +13,13 @@ function Write-BeginStep($invocation)
     Write-Output ""
 }
 
+function Set-BuildInfo($version)
+{
+    # Reported by `sqelf --version`
+    $env:SQELF_VERSION = $version
+    $env:SQELF_COMMIT = $(git rev-parse --short HEAD)
+}
+
 function Get-SemVer($shortver)
 {
     # This script originally (c) 2016 Serilog Contributors - license Apache 2.0
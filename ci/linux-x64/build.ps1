@@ -7,6 +7,8 @@ Push-Location "$PSScriptRoot/../../"
 
 . "./ci/build-deps.ps1"
 
+Set-BuildInfo (Get-SemVer $shortver)
+
 function Invoke-SmokeTest($protocol) {
     Write-BeginStep $MYINVOCATION
 
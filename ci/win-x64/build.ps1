@@ -7,6 +7,8 @@ Push-Location "$PSScriptRoot/../../"
 
 . "./ci/build-deps.ps1"
 
+Set-BuildInfo (Get-SemVer $shortver)
+
 Initialize-Filesystem
 Invoke-WindowsBuild
 Invoke-WindowsTests
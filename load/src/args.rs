@@ -0,0 +1,207 @@
+use std::{
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::Error;
+
+use sqelf::{
+    send::Compression,
+    server::Bind,
+};
+
+pub const USAGE: &str = "\
+Send GELF messages to a server at a target rate and report on throughput.
+
+USAGE:
+    sqelf-load [FLAGS]
+
+FLAGS:
+    --address <URL>              The server to send to, like `tcp://127.0.0.1:12201` [default: udp://127.0.0.1:12201]
+    --tls                        Connect over TLS. Only supported for TCP
+    --server-name <NAME>         The name to verify the server's certificate against [default: the address host]
+    --ca-path <PATH>             A `.pem` file of certificates to trust instead of the OS roots
+    --rate <EVENTS>              The target number of events per second across all connections. `0` is unlimited [default: 1000]
+    --duration-secs <SECS>       The time to send events for [default: 10]
+    --connections <N>            The number of concurrent connections or sockets [default: 1]
+    --size <BYTES>               The approximate size of each uncompressed event [default: 256]
+    --compression <KIND>         The compression to apply to UDP messages: `none`, `gzip`, or `zlib` [default: none]
+    --max-datagram-size <BYTES>  The size to chunk UDP messages at [default: 8192]
+    --metrics <ADDR>             The server's HTTP address, like `127.0.0.1:8080`, to count received events from
+    --output <PATH>              A file the server's output is written to, to count received events from
+    --settle-secs <SECS>         The time to wait for the server to catch up before counting [default: 5]
+    --help                       Print this message
+";
+
+/**
+The flags that take a value.
+*/
+const VALUE_FLAGS: &[&str] = &[
+    "--address",
+    "--server-name",
+    "--ca-path",
+    "--rate",
+    "--duration-secs",
+    "--connections",
+    "--size",
+    "--compression",
+    "--max-datagram-size",
+    "--metrics",
+    "--output",
+    "--settle-secs",
+];
+
+/**
+The parsed command-line arguments.
+*/
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub bind: Bind,
+    pub tls: bool,
+    pub server_name: Option<String>,
+    pub ca_path: Option<String>,
+    pub rate: u64,
+    pub duration: Duration,
+    pub connections: usize,
+    pub size: usize,
+    pub compression: Compression,
+    pub max_datagram_size: usize,
+    pub metrics: Option<String>,
+    pub output: Option<String>,
+    pub settle: Duration,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            bind: "udp://127.0.0.1:12201"
+                .parse()
+                .expect("invalid default address"),
+            tls: false,
+            server_name: None,
+            ca_path: None,
+            rate: 1000,
+            duration: Duration::from_secs(10), // 10 seconds
+            connections: 1,
+            size: 256,
+            compression: Compression::None,
+            max_datagram_size: 8192, // 8kiB
+            metrics: None,
+            output: None,
+            settle: Duration::from_secs(5), // 5 seconds
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            // Boolean flags don't take a value
+            match flag.as_str() {
+                "--tls" => {
+                    parsed.tls = true;
+                    continue;
+                }
+                "--help" | "-h" => {
+                    parsed.help = true;
+                    continue;
+                }
+                _ => (),
+            }
+
+            if !VALUE_FLAGS.contains(&flag.as_str()) {
+                bail!("unknown argument `{}`", flag);
+            }
+
+            let value = value
+                .or_else(|| args.next())
+                .ok_or_else(|| anyhow!("missing a value for `{}`", flag))?;
+
+            match flag.as_str() {
+                "--address" => parsed.bind = parse(&flag, &value)?,
+                "--server-name" => parsed.server_name = Some(value),
+                "--ca-path" => parsed.ca_path = Some(value),
+                "--rate" => parsed.rate = parse(&flag, &value)?,
+                "--duration-secs" => parsed.duration = Duration::from_secs(parse(&flag, &value)?),
+                "--connections" => parsed.connections = parse(&flag, &value)?,
+                "--size" => parsed.size = parse(&flag, &value)?,
+                "--compression" => parsed.compression = parse(&flag, &value)?,
+                "--max-datagram-size" => parsed.max_datagram_size = parse(&flag, &value)?,
+                "--metrics" => parsed.metrics = Some(value),
+                "--output" => parsed.output = Some(value),
+                "--settle-secs" => parsed.settle = Duration::from_secs(parse(&flag, &value)?),
+                _ => unreachable!("unhandled argument `{}`", flag),
+            }
+        }
+
+        if parsed.connections == 0 {
+            bail!("`--connections` must be greater than 0");
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn parse<T>(flag: &str, v: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    Error: From<T::Err>,
+{
+    T::from_str(v)
+        .map_err(|e| Error::from(e).context(format!("invalid value `{}` for `{}`", v, flag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqelf::server::Protocol;
+
+    fn args(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = args(&[
+            "--address",
+            "tcp://127.0.0.1:12202",
+            "--tls",
+            "--rate=500",
+            "--compression",
+            "GZIP",
+            "--connections",
+            "4",
+        ])
+        .expect("failed to parse args");
+
+        assert!(matches!(args.bind.protocol, Protocol::Tcp));
+        assert_eq!("127.0.0.1:12202", args.bind.addr);
+        assert!(args.tls);
+        assert_eq!(500, args.rate);
+        assert_eq!(Compression::Gzip, args.compression);
+        assert_eq!(4, args.connections);
+    }
+
+    #[test]
+    fn invalid_args_are_named() {
+        let err = args(&["--rate", "fast"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("`--rate`"));
+
+        let err = args(&["--nope"]).unwrap_err();
+        assert_eq!("unknown argument `--nope`", format!("{:#}", err));
+
+        assert!(args(&["--connections", "0"]).is_err());
+        assert!(args(&["--rate"]).is_err());
+    }
+}
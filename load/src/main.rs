@@ -0,0 +1,510 @@
+/*!
+A load generator for benchmarking a GELF server.
+
+Events are sent at a target rate over a number of concurrent connections.
+Once sending finishes, the number of events the server received is counted
+from either its metrics endpoint or its output so lost events can be reported.
+*/
+
+#[macro_use]
+extern crate anyhow;
+
+mod args;
+
+use std::{
+    env,
+    fs::File,
+    io::{
+        BufRead,
+        BufReader,
+        Read,
+        Write,
+    },
+    net::TcpStream,
+    process,
+    sync::{
+        atomic::{
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use anyhow::Error;
+
+use sqelf::{
+    send::{
+        tcp,
+        udp,
+    },
+    server::Protocol,
+};
+
+use self::args::{
+    Args,
+    USAGE,
+};
+
+/**
+The metric the server increments for each message it receives.
+*/
+const RECEIVED_METRIC: &str = "sqelf_server_receive_ok_total";
+
+/**
+The interval to report progress at while sending.
+*/
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {:#}\n", err);
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if args.help {
+        print!("{}", USAGE);
+        return;
+    }
+
+    if let Err(err) = run(args) {
+        eprintln!("error: {:#}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let args = Arc::new(args);
+
+    // A unique id for this run so its events can be found in the server's output
+    let run_id = format!("{:016x}", rand::random::<u64>());
+
+    let counter = Counter::new(&args, &run_id)?;
+    let before = counter.as_ref().map(Counter::count).transpose()?;
+
+    let tls = if args.tls {
+        if let Protocol::Udp = args.bind.protocol {
+            bail!("TLS is not supported when the protocol is UDP")
+        }
+
+        Some(tls_config(&args)?)
+    } else {
+        None
+    };
+
+    let totals = Arc::new(Totals::default());
+
+    // Each connection gets an equal share of the target rate
+    let rate = args.rate as f64 / args.connections as f64;
+
+    let start = Instant::now();
+    let deadline = start + args.duration;
+
+    let workers = (0..args.connections)
+        .map(|worker| {
+            let mut client = Client::connect(&args, tls.clone())?;
+            let msg = message(&run_id, worker, args.size);
+
+            let args = args.clone();
+            let totals = totals.clone();
+
+            Ok(thread::spawn(move || {
+                send(&args, &mut client, &msg, rate, start, deadline, &totals);
+                totals.finished.fetch_add(1, Ordering::Relaxed);
+
+                Instant::now()
+            }))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // Report progress until all the workers have finished
+    let mut last = (start, 0);
+    while totals.finished.load(Ordering::Relaxed) < workers.len() {
+        thread::sleep(PROGRESS_INTERVAL);
+
+        let now = Instant::now();
+        let sent = totals.sent.load(Ordering::Relaxed);
+
+        eprintln!(
+            "{:>4}s: sent {} events ({:.0}/s)",
+            (now - start).as_secs(),
+            sent,
+            per_sec(sent - last.1, now - last.0)
+        );
+
+        last = (now, sent);
+    }
+
+    // The run ends when the last worker finishes sending
+    let mut end = start;
+    for worker in workers {
+        end = end.max(worker.join().map_err(|_| anyhow!("a worker panicked"))?);
+    }
+
+    let elapsed = end - start;
+    let sent = totals.sent.load(Ordering::Relaxed);
+    let bytes = totals.bytes.load(Ordering::Relaxed);
+    let errors = totals.errors.load(Ordering::Relaxed);
+
+    println!(
+        "sent:     {} events ({} bytes) in {:.2?}",
+        sent, bytes, elapsed
+    );
+    println!(
+        "rate:     {:.0} events/s ({:.2} MiB/s)",
+        per_sec(sent, elapsed),
+        per_sec(bytes, elapsed) / (1024.0 * 1024.0)
+    );
+    println!("errors:   {}", errors);
+
+    if let (Some(counter), Some(before)) = (counter, before) {
+        let received = counter.settle(before, sent, args.settle)?;
+        let lost = sent.saturating_sub(received);
+
+        println!("received: {}", received);
+        println!(
+            "lost:     {} ({:.2}%)",
+            lost,
+            if sent > 0 {
+                lost as f64 / sent as f64 * 100.0
+            } else {
+                0.0
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/**
+Send a message repeatedly at a target rate until the deadline.
+
+A `rate` of `0` sends as fast as possible.
+*/
+fn send(
+    args: &Args,
+    client: &mut Client,
+    msg: &[u8],
+    rate: f64,
+    start: Instant,
+    deadline: Instant,
+    totals: &Totals,
+) {
+    let interval = if rate > 0.0 {
+        Some(Duration::from_secs_f64(1.0 / rate))
+    } else {
+        None
+    };
+
+    let mut sent = 0u64;
+
+    loop {
+        let now = Instant::now();
+
+        if now >= deadline {
+            return;
+        }
+
+        // Wait until the next event is due
+        if let Some(interval) = interval {
+            let due = start + interval.mul_f64(sent as f64);
+
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        sent += 1;
+
+        // NOTE: We don't use `?` here because we never want to carry results
+        // We always want to match them and deal with error cases directly
+        match client.send(msg) {
+            Ok(()) => {
+                totals.sent.fetch_add(1, Ordering::Relaxed);
+                totals.bytes.fetch_add(msg.len() as u64, Ordering::Relaxed);
+            }
+            Err(err) => {
+                // Only report the first error to avoid flooding the output
+                if totals.errors.fetch_add(1, Ordering::Relaxed) == 0 {
+                    eprintln!("failed to send an event: {:#}", err);
+                }
+
+                // The connection may have been closed, so try open a new one
+                if let Client::Tcp(..) = client {
+                    match Client::connect(args, client.tls()) {
+                        Ok(reconnected) => *client = reconnected,
+                        Err(_) => thread::sleep(Duration::from_millis(100)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+Build a GELF message padded to approximately the given size.
+*/
+fn message(run_id: &str, worker: usize, size: usize) -> Vec<u8> {
+    let msg = |short_message: &str| {
+        serde_json::to_vec(&serde_json::json!({
+            "version": "1.1",
+            "host": "sqelf-load",
+            "short_message": short_message,
+            "_load_run": run_id,
+            "_load_worker": worker,
+        }))
+        .expect("infallible JSON")
+    };
+
+    let unpadded = msg("").len();
+
+    msg(&"x".repeat(size.saturating_sub(unpadded)))
+}
+
+fn per_sec(n: u64, elapsed: Duration) -> f64 {
+    if elapsed.as_secs_f64() > 0.0 {
+        n as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    sent: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+    finished: AtomicUsize,
+}
+
+enum Client {
+    Udp(udp::Client),
+    Tcp(tcp::Client, Option<Arc<rustls::ClientConfig>>),
+}
+
+impl Client {
+    fn connect(args: &Args, tls: Option<Arc<rustls::ClientConfig>>) -> Result<Self, Error> {
+        let addr = args.bind.addr.as_str();
+
+        match args.bind.protocol {
+            Protocol::Udp => Ok(Client::Udp(udp::Client::connect(
+                addr,
+                udp::Config {
+                    compression: args.compression,
+                    max_datagram_size: args.max_datagram_size,
+                },
+            )?)),
+            Protocol::Tcp => {
+                let client = match tls {
+                    Some(ref tls) => {
+                        tcp::Client::connect_tls(addr, &server_name(args), tls.clone())?
+                    }
+                    None => tcp::Client::connect(addr)?,
+                };
+
+                Ok(Client::Tcp(client, tls))
+            }
+        }
+    }
+
+    fn tls(&self) -> Option<Arc<rustls::ClientConfig>> {
+        match self {
+            Client::Tcp(_, tls) => tls.clone(),
+            Client::Udp(_) => None,
+        }
+    }
+
+    fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        match self {
+            Client::Udp(client) => client.send(msg),
+            Client::Tcp(client, _) => client.send(msg),
+        }
+    }
+}
+
+/**
+The name to verify the server's certificate against.
+
+If it's not given then the host of the address is used.
+*/
+fn server_name(args: &Args) -> String {
+    match args.server_name {
+        Some(ref server_name) => server_name.clone(),
+        None => {
+            let addr = args.bind.addr.as_str();
+
+            addr.rsplit_once(':')
+                .map(|(host, _)| host)
+                .unwrap_or(addr)
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned()
+        }
+    }
+}
+
+fn tls_config(args: &Args) -> Result<Arc<rustls::ClientConfig>, Error> {
+    let mut root_store = rustls::RootCertStore::empty();
+
+    match args.ca_path {
+        Some(ref ca_path) => {
+            let mut read =
+                BufReader::new(File::open(ca_path).map_err(|e| {
+                    Error::from(e).context(format!("failed to open `{}`", ca_path))
+                })?);
+
+            for cert in rustls_pemfile::certs(&mut read) {
+                root_store.add(cert?)?;
+            }
+        }
+        None => {
+            for cert in rustls_native_certs::load_native_certs()? {
+                root_store.add(cert.0.into())?;
+            }
+        }
+    }
+
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+/**
+Counts the events received by the server.
+*/
+enum Counter {
+    /**
+    Read the number of received messages from the server's metrics endpoint.
+    */
+    Metrics(String),
+    /**
+    Count the lines in the server's output that belong to this run.
+    */
+    Output { path: String, run_id: String },
+}
+
+impl Counter {
+    fn new(args: &Args, run_id: &str) -> Result<Option<Self>, Error> {
+        match (&args.metrics, &args.output) {
+            (Some(_), Some(_)) => bail!("only one of `--metrics` or `--output` can be used"),
+            (Some(metrics), None) => Ok(Some(Counter::Metrics(metrics.clone()))),
+            (None, Some(output)) => Ok(Some(Counter::Output {
+                path: output.clone(),
+                run_id: run_id.to_owned(),
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn count(&self) -> Result<u64, Error> {
+        match self {
+            Counter::Metrics(addr) => {
+                let body = get(addr, "/metrics")?;
+
+                body.lines()
+                    .find_map(|line| line.strip_prefix(RECEIVED_METRIC)?.trim().parse().ok())
+                    .ok_or_else(|| {
+                        anyhow!("the server's metrics don't include `{}`", RECEIVED_METRIC)
+                    })
+            }
+            Counter::Output { path, run_id } => {
+                let read = match File::open(path) {
+                    Ok(file) => BufReader::new(file),
+                    // The output may not exist until the server writes to it
+                    Err(_) => return Ok(0),
+                };
+
+                let mut count = 0;
+                for line in read.lines() {
+                    if line?.contains(run_id.as_str()) {
+                        count += 1;
+                    }
+                }
+
+                Ok(count)
+            }
+        }
+    }
+
+    /**
+    Wait for the server to finish receiving events, then count them.
+
+    Counting stops early if every sent event has been received.
+    */
+    fn settle(&self, before: u64, sent: u64, timeout: Duration) -> Result<u64, Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let received = self.count()?.saturating_sub(before);
+
+            if received >= sent || Instant::now() >= deadline {
+                return Ok(received);
+            }
+
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
+
+/**
+Make a simple HTTP GET request and return the response body.
+*/
+fn get(addr: &str, path: &str) -> Result<String, Error> {
+    let mut stream = TcpStream::connect(addr)
+        .map_err(|e| Error::from(e).context(format!("failed to connect to `{}`", addr)))?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nhost: {}\r\nconnection: close\r\n\r\n",
+        path, addr
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) if head.split(' ').nth(1) == Some("200") => Ok(body.to_owned()),
+        _ => bail!("unexpected response from `{}{}`", addr, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_padded() {
+        let msg = message("run", 0, 512);
+
+        assert_eq!(512, msg.len());
+
+        // Messages larger than the target size aren't truncated
+        let msg = message("run", 0, 1);
+        let value: serde_json::Value = serde_json::from_slice(&msg).expect("invalid JSON");
+
+        assert_eq!("", value["short_message"]);
+    }
+
+    #[test]
+    fn server_name_defaults_to_host() {
+        let args = Args::parse(vec![
+            "--address".to_owned(),
+            "tcp://localhost:12201".to_owned(),
+        ])
+        .expect("failed to parse args");
+
+        assert_eq!("localhost", server_name(&args));
+    }
+}
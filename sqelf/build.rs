@@ -0,0 +1,47 @@
+use std::{
+    env,
+    process::Command,
+};
+
+/**
+Set the version and commit that `sqelf --version` reports.
+
+CI sets `SQELF_VERSION` and `SQELF_COMMIT` explicitly. Other builds
+read the commit from `git`, and fall back to `unknown` without it.
+*/
+fn main() {
+    println!("cargo:rerun-if-env-changed=SQELF_VERSION");
+    println!("cargo:rerun-if-env-changed=SQELF_COMMIT");
+
+    if let Ok(version) = env::var("SQELF_VERSION") {
+        println!("cargo:rustc-env=SQELF_VERSION={}", version);
+    }
+
+    let commit = match env::var("SQELF_COMMIT") {
+        Ok(commit) if !commit.is_empty() => commit,
+        _ => git_commit().unwrap_or_else(|| "unknown".to_owned()),
+    };
+
+    println!("cargo:rustc-env=SQELF_COMMIT={}", commit);
+}
+
+fn git_commit() -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        Some(stdout.trim().to_owned()).filter(|stdout| !stdout.is_empty())
+    };
+
+    // Build again when the checked out commit changes
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs", git_dir);
+    }
+
+    git(&["rev-parse", "--short", "HEAD"])
+}
@@ -0,0 +1,301 @@
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        BufRead,
+        BufReader,
+        LineWriter,
+        Write,
+    },
+    net::SocketAddr,
+    path::Path,
+};
+
+use base64::{
+    engine::general_purpose::STANDARD as BASE64,
+    Engine as _,
+};
+use bytes::Bytes;
+use chrono::{
+    DateTime,
+    Utc,
+};
+
+use crate::{
+    diagnostics::*,
+    import::Summary,
+    receive::Message,
+    Error,
+};
+
+/**
+A single datagram or TCP frame as it arrived on the wire.
+
+Captures are newline-delimited JSON, with one record per line.
+The payload is base64 encoded so that chunked and compressed
+messages are preserved exactly.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /**
+    When the payload was received.
+    */
+    pub timestamp: DateTime<Utc>,
+    /**
+    The address of the client that sent the payload, if it's known.
+    */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    /**
+    The protocol the payload was received over, either `udp` or `tcp`.
+    */
+    pub protocol: String,
+    /**
+    The base64 encoded payload.
+    */
+    pub payload: String,
+}
+
+impl Record {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        peer: Option<SocketAddr>,
+        protocol: &str,
+        payload: &[u8],
+    ) -> Self {
+        Record {
+            timestamp,
+            peer: peer.map(|peer| peer.to_string()),
+            protocol: protocol.to_owned(),
+            payload: BASE64.encode(payload),
+        }
+    }
+
+    /**
+    Decode the raw payload.
+    */
+    pub fn payload(&self) -> Result<Bytes, Error> {
+        Ok(Bytes::from(BASE64.decode(&self.payload)?))
+    }
+}
+
+/**
+Appends raw payloads to a capture file.
+*/
+pub struct Writer(LineWriter<File>);
+
+impl Writer {
+    /**
+    Open a capture file for appending, creating it if it doesn't exist.
+    */
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                Error::from(e).context(format!("failed to open capture file `{}`", path.display()))
+            })?;
+
+        Ok(Writer(LineWriter::new(file)))
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.0, record)?;
+        self.0.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+/**
+Open a capture file to replay.
+*/
+pub fn open(path: impl AsRef<Path>) -> Result<impl BufRead, Error> {
+    let path = path.as_ref();
+
+    let file = File::open(path).map_err(|e| {
+        Error::from(e).context(format!("failed to open capture file `{}`", path.display()))
+    })?;
+
+    Ok(BufReader::new(file))
+}
+
+/**
+Replay a capture through the receive and process functions.
+
+Payloads are replayed in the order they were captured. Chunks are reassembled
+just like they would be from the network, so a chunk that completes a message
+counts as one processed event.
+*/
+pub fn replay(
+    read: impl BufRead,
+    receive: impl FnMut(Bytes) -> Result<Option<Message>, Error>,
+    process: impl FnMut(Message) -> Result<(), Error>,
+) -> Result<Summary, Error> {
+    replay_lines(
+        read,
+        |line| Ok(Some(serde_json::from_str(line)?)),
+        receive,
+        process,
+    )
+}
+
+/**
+Replay newline-delimited records through the receive and process functions.
+
+Each line is parsed into a capture record. A line that parses to `None`
+is counted as skipped instead of being replayed.
+*/
+pub(crate) fn replay_lines(
+    read: impl BufRead,
+    mut parse: impl FnMut(&str) -> Result<Option<Record>, Error>,
+    mut receive: impl FnMut(Bytes) -> Result<Option<Message>, Error>,
+    mut process: impl FnMut(Message) -> Result<(), Error>,
+) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
+
+    for (index, line) in read.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = match parse(&line)
+            .map_err(|e| e.context(format!("invalid record on line {}", index + 1)))?
+        {
+            Some(record) => record,
+            None => {
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        // NOTE: We don't use `?` here because we never want to carry results
+        // We always want to match them and deal with error cases directly
+        let processed = match record.payload().and_then(&mut receive) {
+            Ok(Some(msg)) => {
+                // Processing sees the original peer, just like it would from the network
+                let msg = match record.peer.as_deref().map(str::parse) {
+                    Some(Ok(peer)) => msg.with_peer(peer),
+                    _ => msg,
+                };
+
+                process(msg)
+            }
+            // A chunk of a message that isn't complete yet
+            Ok(None) => continue,
+            Err(err) => Err(err),
+        };
+
+        match processed {
+            Ok(()) => {
+                summary.processed += 1;
+            }
+            Err(err) => {
+                summary.failed += 1;
+
+                emit_err_with(
+                    err.as_ref(),
+                    "GELF replay failed",
+                    ReplayProperties {
+                        line: index + 1,
+                        peer: record.peer.as_deref(),
+                        protocol: &record.protocol,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[derive(Serialize)]
+struct ReplayProperties<'a> {
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<&'a str>,
+    protocol: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        process,
+        receive,
+    };
+
+    #[test]
+    fn record_roundtrip() {
+        let record = Record::new(
+            Utc::now(),
+            Some("127.0.0.1:12201".parse().unwrap()),
+            "udp",
+            b"\x1e\x0f\x00binary",
+        );
+
+        let json = serde_json::to_string(&record).expect("failed to serialize");
+        let read: Record = serde_json::from_str(&json).expect("failed to deserialize");
+
+        assert_eq!(record, read);
+        assert_eq!(&b"\x1e\x0f\x00binary"[..], &*read.payload().unwrap());
+    }
+
+    #[test]
+    fn replay_chunked() {
+        let mut capture = Vec::new();
+
+        let chunks: [&[u8]; 2] = [
+            b"\x1e\x0f\x00\x00\x00\x00\x00\x00\x00\x01\x00\x02{\"host\":\"a\",",
+            b"\x1e\x0f\x00\x00\x00\x00\x00\x00\x00\x01\x01\x02\"short_message\":\"1\"}",
+        ];
+
+        for chunk in &chunks {
+            let record = Record::new(Utc::now(), None, "udp", chunk);
+
+            serde_json::to_writer(&mut capture, &record).unwrap();
+            capture.push(b'\n');
+        }
+
+        capture.extend_from_slice(b"\n");
+
+        let record = Record::new(Utc::now(), None, "tcp", b"not json");
+        serde_json::to_writer(&mut capture, &record).unwrap();
+
+        let mut receive = receive::build(receive::Config::default());
+        let process = process::build(process::Config::default());
+
+        let mut events = Vec::new();
+
+        let summary = replay(
+            &capture[..],
+            |src| receive.decode(src),
+            |msg| {
+                process.with_clef(msg, |clef| {
+                    events.push(serde_json::to_value(clef)?);
+
+                    Ok(())
+                })
+            },
+        )
+        .expect("failed to replay");
+
+        assert_eq!(
+            Summary {
+                processed: 1,
+                failed: 1,
+                skipped: 0,
+            },
+            summary
+        );
+        assert_eq!("1", events[0]["@m"]);
+    }
+}
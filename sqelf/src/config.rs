@@ -1,5 +1,7 @@
 use std::{
     env,
+    fs,
+    path::Path,
     str::FromStr,
 };
 
@@ -12,7 +14,8 @@ use crate::{
     Error,
 };
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub receive: receive::Config,
     pub process: process::Config,
@@ -21,58 +24,636 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, Error> {
-        let mut config = Config::default();
-        let is_seq_app = is_seq_app();
+    /**
+    Read configuration from a TOML or JSON file.
 
-        let bind_address_var = if is_seq_app {
-            "SEQ_APP_SETTING_GELFADDRESS"
-        } else {
-            "GELF_ADDRESS"
+    The format is determined by the file's extension.
+    Any settings that aren't in the file use their default values.
+    */
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let config = Config::read_file(path)?;
+
+        config
+            .validate(is_seq_app())
+            .map_err(|e| e.context(format!("invalid config file `{}`", path.display())))?;
+
+        Ok(config)
+    }
+
+    /**
+    Read configuration from a TOML or JSON file without validating it.
+
+    Values in the file may still be overridden by the environment or arguments.
+    */
+    fn read_file(path: &Path) -> Result<Self, Error> {
+        let read = || -> Result<Self, Error> {
+            let format = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("toml") => Format::Toml,
+                Some("json") => Format::Json,
+                _ => bail!("expected a `.toml` or `.json` file"),
+            };
+
+            Config::from_str(&fs::read_to_string(path)?, format)
         };
-        read_environment(&mut config.server.bind, bind_address_var)?;
 
-        let enable_diagnostics = if is_seq_app {
-            "SEQ_APP_SETTING_ENABLEDIAGNOSTICS"
-        } else {
-            "GELF_ENABLE_DIAGNOSTICS"
+        read().map_err(|e| e.context(format!("invalid config file `{}`", path.display())))
+    }
+
+    fn from_str(contents: &str, format: Format) -> Result<Self, Error> {
+        let mut config: Config = match format {
+            Format::Toml => toml::from_str(contents)?,
+            Format::Json => serde_json::from_str(contents)?,
         };
-        if is_truthy(enable_diagnostics)? {
-            config.diagnostics.min_level = diagnostics::Level::Debug;
-            config.process.include_raw_payload = true;
+
+        if let Some(ref mut certificate) = config.server.certificate {
+            if certificate.private_key_path.is_empty() {
+                certificate.private_key_path = certificate.path.clone();
+            }
         }
 
-        let certificate_path_var = if is_seq_app {
-            "SEQ_APP_SETTING_CERTIFICATEPATH"
-        } else {
-            "GELF_CERTIFICATE_PATH"
+        Ok(config)
+    }
+
+    /**
+    Read configuration from the environment.
+
+    If the `GELF_CONFIG_PATH` variable is set then configuration
+    is first read from that file. Any other environment variables
+    override the values in the file.
+    */
+    pub fn from_env() -> Result<Self, Error> {
+        Config::from_env_and_args(Vec::<String>::new())
+    }
+
+    /**
+    Read configuration from the environment and command-line arguments.
+
+    Arguments are flags like `--tcp-max-size-bytes 1024` that mirror
+    the `GELF_*` environment variables. Arguments override the environment,
+    which overrides any configuration file. A configuration file can be given
+    by the `--config` argument or the `GELF_CONFIG_PATH` variable.
+    */
+    pub fn from_env_and_args(
+        args: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, Error> {
+        let is_seq_app = is_seq_app();
+        let args = Args::parse(args)?;
+        let env = Environment { is_seq_app };
+
+        let config_path = match args.config_path {
+            Some(ref config_path) => Some(config_path.clone()),
+            None => env.var("GELF_CONFIG_PATH")?,
         };
-        let certificate_private_key_path_var = if is_seq_app {
-            "SEQ_APP_SETTING_CERTIFICATEPRIVATEKEYPATH"
-        } else {
-            "GELF_CERTIFICATE_PRIVATE_KEY_PATH"
+
+        let config = match config_path {
+            // NOTE: The file is validated along with any overrides
+            Some(config_path) => Config::read_file(config_path.as_ref())?,
+            None => Config::default(),
         };
 
-        if is_present(certificate_path_var)? {
+        config.with_overrides(&env, &args, is_seq_app)
+    }
+
+    /**
+    Override settings from the environment and then arguments, and validate the result.
+    */
+    fn with_overrides(
+        mut self,
+        env: &impl Source,
+        args: &impl Source,
+        is_seq_app: bool,
+    ) -> Result<Self, Error> {
+        self.read(env)?;
+        self.read(args)?;
+
+        self.validate(is_seq_app)?;
+
+        Ok(self)
+    }
+
+    /**
+    Override any settings that have values in the given source.
+    */
+    fn read(&mut self, source: &impl Source) -> Result<(), Error> {
+        read_setting(&mut self.server.bind, BIND_ADDRESS, source)?;
+
+        if let Some(http_bind) = source.value(HTTP_ADDRESS)? {
+            self.server.http_bind = Some(http_bind);
+        }
+
+        if let Some(capture_path) = source.value(CAPTURE_PATH)? {
+            self.server.capture_path = Some(capture_path);
+        }
+
+        if let Some(dead_letter_path) = source.value(DEAD_LETTER_PATH)? {
+            self.server.dead_letter_path = Some(dead_letter_path);
+        }
+
+        if let Some(enable_diagnostics) = is_truthy(ENABLE_DIAGNOSTICS, source)? {
+            self.diagnostics.min_level = if enable_diagnostics {
+                diagnostics::Level::Debug
+            } else {
+                diagnostics::Config::default().min_level
+            };
+
+            // NOTE: Enabling diagnostics has always included raw payloads too
+            // This is overridden by `INCLUDE_RAW_PAYLOAD` if it's also set
+            if enable_diagnostics {
+                self.process.include_raw_payload = true;
+            }
+        }
+
+        read_setting(&mut self.diagnostics.min_level, DIAGNOSTICS_LEVEL, source)?;
+
+        if let Some(include_payload_preview) = is_truthy(INCLUDE_PAYLOAD_PREVIEW, source)? {
+            self.diagnostics.include_payload_preview = include_payload_preview;
+        }
+
+        if let Some(include_raw_payload) = is_truthy(INCLUDE_RAW_PAYLOAD, source)? {
+            self.process.include_raw_payload = include_raw_payload;
+        }
+
+        if let Some(emit_invalid_as_errors) = is_truthy(EMIT_INVALID_AS_ERRORS, source)? {
+            self.process.emit_invalid_as_errors = emit_invalid_as_errors;
+        }
+
+        if let Some(strict) = is_truthy(STRICT, source)? {
+            self.process.strict = strict;
+        }
+
+        read_setting(&mut self.process.levels, LEVELS, source)?;
+
+        if let Some(normalize_embedded_levels) = is_truthy(NORMALIZE_EMBEDDED_LEVELS, source)? {
+            self.process.normalize_embedded_levels = normalize_embedded_levels;
+        }
+
+        if let Some(path) = source.value(CERTIFICATE_PATH)? {
             let mut certificate = Certificate {
-                path: String::new(),
+                path,
                 private_key_path: String::new(),
             };
 
-            read_environment(&mut certificate.path, certificate_path_var)?;
-            read_environment(
+            read_setting(
                 &mut certificate.private_key_path,
-                certificate_private_key_path_var,
+                CERTIFICATE_PRIVATE_KEY_PATH,
+                source,
             )?;
 
             if certificate.private_key_path.is_empty() {
                 certificate.private_key_path = certificate.path.clone();
             }
 
-            config.server.certificate = Some(certificate);
+            self.server.certificate = Some(certificate);
         }
 
-        Ok(config)
+        read_setting(
+            &mut self.receive.incomplete_capacity,
+            INCOMPLETE_CAPACITY,
+            source,
+        )?;
+        read_setting(
+            &mut self.receive.max_chunks_per_message,
+            MAX_CHUNKS_PER_MESSAGE,
+            source,
+        )?;
+        read_setting(
+            &mut self.receive.incomplete_timeout_ms,
+            INCOMPLETE_TIMEOUT_MS,
+            source,
+        )?;
+
+        read_setting(
+            &mut self.server.tcp_idle_timeout_secs,
+            TCP_IDLE_TIMEOUT_SECS,
+            source,
+        )?;
+        read_setting(
+            &mut self.server.tcp_frame_timeout_secs,
+            TCP_FRAME_TIMEOUT_SECS,
+            source,
+        )?;
+        read_setting(
+            &mut self.server.tcp_min_bytes_per_sec,
+            TCP_MIN_BYTES_PER_SEC,
+            source,
+        )?;
+        #[allow(deprecated)]
+        read_setting(
+            &mut self.server.tcp_keep_alive_secs,
+            TCP_KEEP_ALIVE_SECS,
+            source,
+        )?;
+        if let Some(tcp_os_keep_alive_secs) = source.value(TCP_OS_KEEP_ALIVE_SECS)? {
+            self.server.tcp_os_keep_alive_secs = Some(parse(
+                TCP_OS_KEEP_ALIVE_SECS,
+                source,
+                &tcp_os_keep_alive_secs,
+            )?);
+        }
+        read_setting(
+            &mut self.server.tcp_max_size_bytes,
+            TCP_MAX_SIZE_BYTES,
+            source,
+        )?;
+        read_setting(
+            &mut self.server.shutdown_timeout_secs,
+            SHUTDOWN_TIMEOUT_SECS,
+            source,
+        )?;
+        if let Some(worker_threads) = source.value(WORKER_THREADS)? {
+            self.server.worker_threads = Some(parse(WORKER_THREADS, source, &worker_threads)?);
+        }
+        read_setting(&mut self.server.max_in_flight, MAX_IN_FLIGHT, source)?;
+        read_setting(
+            &mut self.server.dead_letter_max_file_bytes,
+            DEAD_LETTER_MAX_FILE_BYTES,
+            source,
+        )?;
+        read_setting(
+            &mut self.server.dead_letter_max_files,
+            DEAD_LETTER_MAX_FILES,
+            source,
+        )?;
+
+        read_setting(
+            &mut self.diagnostics.metrics_interval_ms,
+            METRICS_INTERVAL_MS,
+            source,
+        )?;
+        read_setting(
+            &mut self.diagnostics.error_summary_interval_ms,
+            ERROR_SUMMARY_INTERVAL_MS,
+            source,
+        )?;
+        read_setting(
+            &mut self.diagnostics.max_errors_per_interval,
+            MAX_ERRORS_PER_INTERVAL,
+            source,
+        )?;
+
+        Ok(())
+    }
+
+    /**
+    Check that all settings have sensible values.
+    */
+    fn validate(&self, is_seq_app: bool) -> Result<(), Error> {
+        let check = |valid: bool, setting: Setting, expected: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(setting.invalid(is_seq_app, expected))
+            }
+        };
+
+        check(
+            self.receive.incomplete_capacity > 0,
+            INCOMPLETE_CAPACITY,
+            "expected a value greater than 0",
+        )?;
+        check(
+            (1..=128).contains(&self.receive.max_chunks_per_message),
+            MAX_CHUNKS_PER_MESSAGE,
+            "expected a value between 1 and 128",
+        )?;
+        check(
+            self.receive.incomplete_timeout_ms > 0,
+            INCOMPLETE_TIMEOUT_MS,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.tcp_idle_timeout_secs > 0,
+            TCP_IDLE_TIMEOUT_SECS,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.tcp_frame_timeout_secs > 0,
+            TCP_FRAME_TIMEOUT_SECS,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.tcp_os_keep_alive_secs != Some(0),
+            TCP_OS_KEEP_ALIVE_SECS,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.tcp_max_size_bytes > 0,
+            TCP_MAX_SIZE_BYTES,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.max_in_flight > 0,
+            MAX_IN_FLIGHT,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.server.dead_letter_max_file_bytes > 0,
+            DEAD_LETTER_MAX_FILE_BYTES,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.diagnostics.metrics_interval_ms > 0,
+            METRICS_INTERVAL_MS,
+            "expected a value greater than 0",
+        )?;
+        check(
+            self.diagnostics.error_summary_interval_ms > 0,
+            ERROR_SUMMARY_INTERVAL_MS,
+            "expected a value greater than 0",
+        )?;
+
+        Ok(())
+    }
+}
+
+/**
+Whether a command-line argument is a flag that's followed by a separate value.
+
+This is `false` for flags given with `=`, like `--config=gelf.toml`, and for boolean flags.
+*/
+pub fn flag_takes_value(arg: &str) -> bool {
+    if arg.contains('=') {
+        return false;
+    }
+
+    arg == "--config"
+        || SETTINGS
+            .iter()
+            .any(|setting| !setting.is_flag && setting.flag() == arg)
+}
+
+/**
+Usage information for the command-line flags that mirror settings.
+*/
+pub fn flags_usage() -> String {
+    let mut usage = String::from(
+        "    --config <PATH>\n        The path to a `.toml` or `.json` configuration file\n",
+    );
+
+    for setting in SETTINGS {
+        if setting.is_flag {
+            usage.push_str(&format!("    {}\n", setting.flag()));
+        } else {
+            usage.push_str(&format!("    {} <VALUE>\n", setting.flag()));
+        }
+
+        usage.push_str(&format!("        {}\n", setting.description));
+    }
+
+    usage
+}
+
+/**
+A setting that can be configured through the environment.
+
+Each setting has a `GELF_*` environment variable for containers,
+an equivalent `SEQ_APP_SETTING_*` variable when running as a Seq App,
+a command-line flag, and a key in the configuration file.
+*/
+#[derive(Debug, Clone, Copy)]
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    seq_app: &'static str,
+    is_flag: bool,
+    description: &'static str,
+}
+
+impl Setting {
+    fn var(&self, is_seq_app: bool) -> &'static str {
+        if is_seq_app {
+            self.seq_app
+        } else {
+            self.env
+        }
+    }
+
+    /**
+    The command-line flag for the setting.
+
+    Flags are derived from the `GELF_*` environment variable,
+    so `GELF_TCP_MAX_SIZE_BYTES` becomes `--tcp-max-size-bytes`.
+    */
+    fn flag(&self) -> String {
+        format!(
+            "--{}",
+            self.env
+                .trim_start_matches("GELF_")
+                .to_ascii_lowercase()
+                .replace('_', "-")
+        )
+    }
+
+    fn invalid(&self, is_seq_app: bool, reason: impl std::fmt::Display) -> Error {
+        anyhow!(
+            "invalid value for `{}` (`{}` in the config file): {}",
+            self.var(is_seq_app),
+            self.key,
+            reason
+        )
+    }
+}
+
+macro_rules! settings {
+    ($($name:ident: $key:literal, $env:literal, $seq_app:literal, $kind:ident, $description:literal;)*) => {
+        $(
+            const $name: Setting = Setting {
+                key: $key,
+                env: $env,
+                seq_app: $seq_app,
+                is_flag: settings!(@is_flag $kind),
+                description: $description,
+            };
+        )*
+
+        const SETTINGS: &[Setting] = &[$($name),*];
+    };
+    (@is_flag flag) => { true };
+    (@is_flag value) => { false };
+}
+
+settings! {
+    BIND_ADDRESS: "server.bind", "GELF_ADDRESS", "SEQ_APP_SETTING_GELFADDRESS", value,
+        "The address to bind the GELF server to, like `tcp://0.0.0.0:12201`";
+    HTTP_ADDRESS: "server.http_bind", "GELF_HTTP_ADDRESS", "SEQ_APP_SETTING_HTTPADDRESS", value,
+        "The address to bind an HTTP server for health probes and metrics to";
+    ENABLE_DIAGNOSTICS: "diagnostics.min_level", "GELF_ENABLE_DIAGNOSTICS", "SEQ_APP_SETTING_ENABLEDIAGNOSTICS", flag,
+        "Enable diagnostic logs and metrics, and include raw payloads unless `GELF_INCLUDE_RAW_PAYLOAD` is set";
+    DIAGNOSTICS_LEVEL: "diagnostics.min_level", "GELF_DIAGNOSTICS_LEVEL", "SEQ_APP_SETTING_DIAGNOSTICSLEVEL", value,
+        "The minimum level of diagnostic logs to write";
+    INCLUDE_RAW_PAYLOAD: "process.include_raw_payload", "GELF_INCLUDE_RAW_PAYLOAD", "SEQ_APP_SETTING_INCLUDERAWPAYLOAD", flag,
+        "Include the raw GELF payload on each event";
+    INCLUDE_PAYLOAD_PREVIEW: "diagnostics.include_payload_preview", "GELF_INCLUDE_PAYLOAD_PREVIEW", "SEQ_APP_SETTING_INCLUDEPAYLOADPREVIEW", flag,
+        "Include a preview of the payload in diagnostics about messages that fail";
+    EMIT_INVALID_AS_ERRORS: "process.emit_invalid_as_errors", "GELF_EMIT_INVALID_AS_ERRORS", "SEQ_APP_SETTING_EMITINVALIDASERRORS", flag,
+        "Write payloads that can't be parsed as events with the `Error` level instead of dropping them";
+    STRICT: "process.strict", "GELF_STRICT", "SEQ_APP_SETTING_STRICT", flag,
+        "Reject events that don't conform to the GELF 1.1 specification";
+    LEVELS: "process.levels", "GELF_LEVELS", "SEQ_APP_SETTING_LEVELS", value,
+        "The names to give GELF levels, as `syslog` or `seq` followed by any `level=Name` pairs";
+    NORMALIZE_EMBEDDED_LEVELS: "process.normalize_embedded_levels", "GELF_NORMALIZE_EMBEDDED_LEVELS", "SEQ_APP_SETTING_NORMALIZEEMBEDDEDLEVELS", flag,
+        "Map string levels in embedded JSON through the same names as GELF levels";
+    CERTIFICATE_PATH: "server.certificate.path", "GELF_CERTIFICATE_PATH", "SEQ_APP_SETTING_CERTIFICATEPATH", value,
+        "The path to a `.pem` file containing a certificate";
+    CERTIFICATE_PRIVATE_KEY_PATH: "server.certificate.private_key_path", "GELF_CERTIFICATE_PRIVATE_KEY_PATH", "SEQ_APP_SETTING_CERTIFICATEPRIVATEKEYPATH", value,
+        "The path to a `.pem` file containing a PKCS8 private key for the certificate";
+    INCOMPLETE_CAPACITY: "receive.incomplete_capacity", "GELF_INCOMPLETE_CAPACITY", "SEQ_APP_SETTING_INCOMPLETECAPACITY", value,
+        "The maximum number of incomplete chunked messages to buffer";
+    MAX_CHUNKS_PER_MESSAGE: "receive.max_chunks_per_message", "GELF_MAX_CHUNKS_PER_MESSAGE", "SEQ_APP_SETTING_MAXCHUNKSPERMESSAGE", value,
+        "The maximum number of chunks in a single chunked message";
+    INCOMPLETE_TIMEOUT_MS: "receive.incomplete_timeout_ms", "GELF_INCOMPLETE_TIMEOUT_MS", "SEQ_APP_SETTING_INCOMPLETETIMEOUTMS", value,
+        "The time allowed for all chunks in a chunked message to arrive";
+    TCP_IDLE_TIMEOUT_SECS: "server.tcp_idle_timeout_secs", "GELF_TCP_IDLE_TIMEOUT_SECS", "SEQ_APP_SETTING_TCPIDLETIMEOUTSECS", value,
+        "The time a TCP connection can be idle for before it's closed";
+    TCP_FRAME_TIMEOUT_SECS: "server.tcp_frame_timeout_secs", "GELF_TCP_FRAME_TIMEOUT_SECS", "SEQ_APP_SETTING_TCPFRAMETIMEOUTSECS", value,
+        "The time allowed to receive a single message over TCP";
+    TCP_MIN_BYTES_PER_SEC: "server.tcp_min_bytes_per_sec", "GELF_TCP_MIN_BYTES_PER_SEC", "SEQ_APP_SETTING_TCPMINBYTESPERSEC", value,
+        "The minimum rate to receive a message over TCP at";
+    TCP_KEEP_ALIVE_SECS: "server.tcp_keep_alive_secs", "GELF_TCP_KEEP_ALIVE_SECS", "SEQ_APP_SETTING_TCPKEEPALIVESECS", value,
+        "Deprecated. An alias for the TCP idle timeout that takes priority over it when set";
+    TCP_OS_KEEP_ALIVE_SECS: "server.tcp_os_keep_alive_secs", "GELF_TCP_OS_KEEP_ALIVE_SECS", "SEQ_APP_SETTING_TCPOSKEEPALIVESECS", value,
+        "The idle time before sending TCP keepalive probes";
+    TCP_MAX_SIZE_BYTES: "server.tcp_max_size_bytes", "GELF_TCP_MAX_SIZE_BYTES", "SEQ_APP_SETTING_TCPMAXSIZEBYTES", value,
+        "The maximum size of a single message received over TCP";
+    SHUTDOWN_TIMEOUT_SECS: "server.shutdown_timeout_secs", "GELF_SHUTDOWN_TIMEOUT_SECS", "SEQ_APP_SETTING_SHUTDOWNTIMEOUTSECS", value,
+        "The time allowed to drain in-flight messages on shutdown";
+    WORKER_THREADS: "server.worker_threads", "GELF_WORKER_THREADS", "SEQ_APP_SETTING_WORKERTHREADS", value,
+        "The number of threads to process events on. `0` uses a single thread";
+    MAX_IN_FLIGHT: "server.max_in_flight", "GELF_MAX_IN_FLIGHT", "SEQ_APP_SETTING_MAXINFLIGHT", value,
+        "The maximum number of events to process concurrently";
+    CAPTURE_PATH: "server.capture_path", "GELF_CAPTURE_PATH", "SEQ_APP_SETTING_CAPTUREPATH", value,
+        "The path to a file to capture raw traffic to for later replay";
+    DEAD_LETTER_PATH: "server.dead_letter_path", "GELF_DEAD_LETTER_PATH", "SEQ_APP_SETTING_DEADLETTERPATH", value,
+        "The path to a directory to write messages that fail to be received or processed to";
+    DEAD_LETTER_MAX_FILE_BYTES: "server.dead_letter_max_file_bytes", "GELF_DEAD_LETTER_MAX_FILE_BYTES", "SEQ_APP_SETTING_DEADLETTERMAXFILEBYTES", value,
+        "The size a dead-letter file can grow to before a new one is started";
+    DEAD_LETTER_MAX_FILES: "server.dead_letter_max_files", "GELF_DEAD_LETTER_MAX_FILES", "SEQ_APP_SETTING_DEADLETTERMAXFILES", value,
+        "The maximum number of dead-letter files to keep. `0` keeps all files";
+    METRICS_INTERVAL_MS: "diagnostics.metrics_interval_ms", "GELF_METRICS_INTERVAL_MS", "SEQ_APP_SETTING_METRICSINTERVALMS", value,
+        "The interval to write metrics at when diagnostics are enabled";
+    ERROR_SUMMARY_INTERVAL_MS: "diagnostics.error_summary_interval_ms", "GELF_ERROR_SUMMARY_INTERVAL_MS", "SEQ_APP_SETTING_ERRORSUMMARYINTERVALMS", value,
+        "The interval to summarize repeated errors at";
+    MAX_ERRORS_PER_INTERVAL: "diagnostics.max_errors_per_interval", "GELF_MAX_ERRORS_PER_INTERVAL", "SEQ_APP_SETTING_MAXERRORSPERINTERVAL", value,
+        "The number of repeated errors to write before summarizing them";
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Toml,
+    Json,
+}
+
+/**
+Somewhere the values of settings can be read from.
+*/
+trait Source {
+    /**
+    Get the value of a setting, if it's been set.
+    */
+    fn value(&self, setting: Setting) -> Result<Option<String>, Error>;
+
+    /**
+    The name of a setting in this source to report in errors.
+    */
+    fn name(&self, setting: Setting) -> String;
+}
+
+/**
+Settings read from environment variables.
+*/
+struct Environment {
+    is_seq_app: bool,
+}
+
+impl Environment {
+    fn var(&self, name: &str) -> Result<Option<String>, Error> {
+        match env::var(name) {
+            // The environment variable exists, but is empty
+            Ok(ref v) if v.is_empty() => Ok(None),
+            // The environment variable does not exist
+            Err(env::VarError::NotPresent) => Ok(None),
+            // The environment variable is invalid
+            Err(e) => Err(Error::from(e).context(format!("invalid value for `{}`", name))),
+            // The environment variable has a value
+            Ok(v) => Ok(Some(v)),
+        }
+    }
+}
+
+impl Source for Environment {
+    fn value(&self, setting: Setting) -> Result<Option<String>, Error> {
+        self.var(setting.var(self.is_seq_app))
+    }
+
+    fn name(&self, setting: Setting) -> String {
+        setting.var(self.is_seq_app).to_owned()
+    }
+}
+
+/**
+Settings read from command-line flags.
+*/
+#[derive(Debug, Default)]
+struct Args {
+    config_path: Option<String>,
+    values: Vec<(&'static str, String)>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = impl Into<String>>) -> Result<Self, Error> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            if flag == "--config" {
+                let value = value
+                    .or_else(|| args.next())
+                    .ok_or_else(|| anyhow!("missing a value for `--config`"))?;
+
+                parsed.config_path = Some(value);
+                continue;
+            }
+
+            let setting = SETTINGS
+                .iter()
+                .find(|setting| setting.flag() == flag)
+                .ok_or_else(|| anyhow!("unknown argument `{}`", flag))?;
+
+            let value = match value {
+                Some(value) => value,
+                None if setting.is_flag => "true".to_owned(),
+                None => args
+                    .next()
+                    .ok_or_else(|| anyhow!("missing a value for `{}`", flag))?,
+            };
+
+            parsed.values.push((setting.env, value));
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl Source for Args {
+    fn value(&self, setting: Setting) -> Result<Option<String>, Error> {
+        // NOTE: If a flag is given multiple times then the last one wins
+        Ok(self
+            .values
+            .iter()
+            .rev()
+            .find(|(env, value)| *env == setting.env && !value.is_empty())
+            .map(|(_, value)| value.clone()))
+    }
+
+    fn name(&self, setting: Setting) -> String {
+        setting.flag()
     }
 }
 
@@ -80,43 +661,393 @@ pub fn is_seq_app() -> bool {
     env::var("SEQ_APP_ID").is_ok()
 }
 
-fn is_present(name: impl AsRef<str>) -> Result<bool, Error> {
-    match env::var(name.as_ref()) {
-        Ok(ref v) if !v.is_empty() => Ok(true),
-        Ok(_) | Err(env::VarError::NotPresent) => Ok(false),
-        Err(e) => Err(e)?,
+fn is_truthy(setting: Setting, source: &impl Source) -> Result<Option<bool>, Error> {
+    const TRUTHY: &[&str] = &["true", "1", "yes", "on"];
+    const FALSEY: &[&str] = &["false", "0", "no", "off"];
+
+    let is = |spellings: &[&str], v: &str| {
+        spellings
+            .iter()
+            .any(|spelling| v.trim().eq_ignore_ascii_case(spelling))
+    };
+
+    match source.value(setting)? {
+        // The setting contains a truthy value
+        Some(ref v) if is(TRUTHY, v) => Ok(Some(true)),
+        // The setting contains a falsey value
+        Some(ref v) if is(FALSEY, v) => Ok(Some(false)),
+        // The setting contains some other value
+        Some(v) => Err(anyhow!(
+            "invalid value `{}` for `{}`: expected `True` or `False`",
+            v,
+            source.name(setting)
+        )),
+        // The setting is not set
+        None => Ok(None),
     }
 }
 
-fn is_truthy(name: impl AsRef<str>) -> Result<bool, Error> {
-    match env::var(name.as_ref()) {
-        // The environment variable contains a truthy value
-        Ok(ref v) if v == "True" || v == "true" => Ok(true),
-        // The environment variable is not set or doesn't contain
-        // a truthy value
-        Ok(_) | Err(env::VarError::NotPresent) => Ok(false),
-        // The environment variable is invalid
-        Err(e) => Err(e)?,
+fn read_setting<T>(into: &mut T, setting: Setting, source: &impl Source) -> Result<(), Error>
+where
+    T: FromStr,
+    Error: From<T::Err>,
+{
+    if let Some(v) = source.value(setting)? {
+        *into = parse(setting, source, &v)?;
     }
+
+    Ok(())
 }
 
-fn read_environment<T>(into: &mut T, name: impl AsRef<str>) -> Result<(), Error>
+fn parse<T>(setting: Setting, source: &impl Source, v: &str) -> Result<T, Error>
 where
     T: FromStr,
     Error: From<T::Err>,
 {
-    match env::var(name.as_ref()) {
-        // The environment variable exists, but is empty
-        Ok(ref v) if v == "" => return Ok(()),
-        // The environment variable does not exist
-        Err(env::VarError::NotPresent) => return Ok(()),
-        // The environment variable is invalid
-        Err(e) => Err(e)?,
-        // The environment variable has a value
-        Ok(v) => {
-            *into = T::from_str(&v)?;
-
-            Ok(())
+    T::from_str(v).map_err(|e| {
+        Error::from(e).context(format!(
+            "invalid value `{}` for `{}`",
+            v,
+            source.name(setting)
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+    A fixed set of environment variables.
+    */
+    struct Vars(&'static [(&'static str, &'static str)]);
+
+    impl Source for Vars {
+        fn value(&self, setting: Setting) -> Result<Option<String>, Error> {
+            Ok(self
+                .0
+                .iter()
+                .find(|(var, _)| *var == setting.env)
+                .map(|(_, value)| (*value).to_owned()))
+        }
+
+        fn name(&self, setting: Setting) -> String {
+            setting.env.to_owned()
+        }
+    }
+
+    #[test]
+    fn read_toml() {
+        let config = Config::from_str(
+            r#"
+            [server]
+            bind = "tcp://0.0.0.0:12202"
+            tcp_max_size_bytes = 1024
+
+            [server.certificate]
+            path = "cert.pem"
+
+            [receive]
+            max_chunks_per_message = 8
+
+            [process]
+            include_raw_payload = true
+            levels = "seq,7=Verbose"
+
+            [diagnostics]
+            min_level = "Warning"
+            "#,
+            Format::Toml,
+        )
+        .expect("failed to read config");
+
+        assert_eq!("0.0.0.0:12202", config.server.bind.addr);
+        assert!(matches!(config.server.bind.protocol, server::Protocol::Tcp));
+        assert_eq!(1024, config.server.tcp_max_size_bytes);
+        assert_eq!(
+            "cert.pem",
+            config.server.certificate.as_ref().unwrap().private_key_path
+        );
+        assert_eq!(8, config.receive.max_chunks_per_message);
+        assert!(config.process.include_raw_payload);
+        assert_eq!("Verbose", config.process.levels.name(7));
+        assert_eq!(diagnostics::Level::Warning, config.diagnostics.min_level);
+
+        // Anything not in the file uses its default value
+        assert_eq!(
+            receive::Config::default().incomplete_capacity,
+            config.receive.incomplete_capacity
+        );
+    }
+
+    #[test]
+    fn read_json() {
+        let config = Config::from_str(
+            r#"{
+                "server": {
+                    "bind": "udp://0.0.0.0:12202"
+                },
+                "diagnostics": {
+                    "min_level": "Debug"
+                }
+            }"#,
+            Format::Json,
+        )
+        .expect("failed to read config");
+
+        assert_eq!("0.0.0.0:12202", config.server.bind.addr);
+        assert_eq!(diagnostics::Level::Debug, config.diagnostics.min_level);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = Config::from_str(
+            r#"
+            [server]
+            tcp_max_size = 1024
+            "#,
+            Format::Toml,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `tcp_max_size`"));
+
+        let err = Config::from_str(r#"{ "recieve": {} }"#, Format::Json).unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `recieve`"));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let err = Config::from_str(
+            r#"
+            [diagnostics]
+            min_level = "Loud"
+            "#,
+            Format::Toml,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("expected `VERBOSE`"));
+    }
+
+    #[test]
+    fn flags_can_be_turned_off() {
+        let mut config = Config::from_str(
+            r#"
+            [process]
+            include_raw_payload = true
+            strict = true
+            "#,
+            Format::Toml,
+        )
+        .expect("failed to read config");
+
+        let args = Args::parse(vec!["--include-raw-payload=False", "--strict=false"])
+            .expect("failed to parse args");
+        config.read(&args).expect("failed to read args");
+
+        assert!(!config.process.include_raw_payload);
+        assert!(!config.process.strict);
+
+        // Flags that aren't set leave the value alone
+        let mut config = Config::default();
+        config.process.strict = true;
+        config
+            .read(&Args::parse(Vec::<String>::new()).expect("failed to parse args"))
+            .expect("failed to read args");
+
+        assert!(config.process.strict);
+    }
+
+    #[test]
+    fn flags_accept_common_spellings() {
+        for (value, expected) in [
+            ("True", true),
+            ("1", true),
+            ("yes", true),
+            ("ON", true),
+            ("False ", false),
+            ("0", false),
+            ("No", false),
+            ("off", false),
+        ] {
+            let args =
+                Args::parse(vec![format!("--strict={}", value)]).expect("failed to parse args");
+
+            assert_eq!(
+                Some(expected),
+                is_truthy(STRICT, &args).expect("failed to read flag"),
+                "{:?}",
+                value
+            );
         }
+
+        let args = Args::parse(vec!["--strict=maybe"]).expect("failed to parse args");
+        assert!(is_truthy(STRICT, &args).is_err());
+    }
+
+    #[test]
+    fn enable_diagnostics_includes_raw_payloads() {
+        let mut config = Config::default();
+        config
+            .read(&Args::parse(vec!["--enable-diagnostics"]).expect("failed to parse args"))
+            .expect("failed to read args");
+
+        assert_eq!(diagnostics::Level::Debug, config.diagnostics.min_level);
+        assert!(config.process.include_raw_payload);
+
+        // Raw payloads can still be turned off explicitly
+        let mut config = Config::default();
+        config
+            .read(
+                &Args::parse(vec!["--enable-diagnostics", "--include-raw-payload=False"])
+                    .expect("failed to parse args"),
+            )
+            .expect("failed to read args");
+
+        assert_eq!(diagnostics::Level::Debug, config.diagnostics.min_level);
+        assert!(!config.process.include_raw_payload);
+    }
+
+    #[test]
+    fn file_values_are_validated_after_overrides() {
+        let file = || {
+            Config::from_str("[server]\ntcp_max_size_bytes = 0\n", Format::Toml)
+                .expect("failed to read config")
+        };
+
+        let no_args = Args::parse(Vec::<String>::new()).expect("failed to parse args");
+
+        let overridden = file()
+            .with_overrides(
+                &Vars(&[("GELF_TCP_MAX_SIZE_BYTES", "1024")]),
+                &no_args,
+                false,
+            )
+            .expect("failed to read config");
+
+        assert_eq!(1024, overridden.server.tcp_max_size_bytes);
+
+        let overridden = file()
+            .with_overrides(
+                &Vars(&[]),
+                &Args::parse(vec!["--tcp-max-size-bytes", "2048"]).expect("failed to parse args"),
+                false,
+            )
+            .expect("failed to read config");
+
+        assert_eq!(2048, overridden.server.tcp_max_size_bytes);
+
+        let err = file()
+            .with_overrides(&Vars(&[]), &no_args, false)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("`GELF_TCP_MAX_SIZE_BYTES`"));
+    }
+
+    #[test]
+    fn read_args() {
+        let args = Args::parse(vec![
+            "--address",
+            "tcp://0.0.0.0:12202",
+            "--tcp-max-size-bytes=1024",
+            "--include-raw-payload",
+            "--tcp-os-keep-alive-secs",
+            "30",
+            "--config",
+            "gelf.toml",
+        ])
+        .expect("failed to parse args");
+
+        let mut config = Config::default();
+        config.read(&args).expect("failed to read args");
+
+        assert_eq!(Some("gelf.toml"), args.config_path.as_deref());
+        assert_eq!("0.0.0.0:12202", config.server.bind.addr);
+        assert_eq!(1024, config.server.tcp_max_size_bytes);
+        assert_eq!(Some(30), config.server.tcp_os_keep_alive_secs);
+        assert!(config.process.include_raw_payload);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn tcp_keep_alive_secs_is_still_accepted() {
+        let config = Config::from_str(
+            r#"
+            [server]
+            tcp_keep_alive_secs = 120
+            "#,
+            Format::Toml,
+        )
+        .expect("failed to read config");
+
+        assert_eq!(120, config.server.tcp_keep_alive_secs);
+
+        let args = Args::parse(vec!["--tcp-keep-alive-secs", "60"]).expect("failed to parse args");
+
+        let mut config = Config::default();
+        config.read(&args).expect("failed to read args");
+
+        assert_eq!(60, config.server.tcp_keep_alive_secs);
+    }
+
+    #[test]
+    fn flags_that_take_values() {
+        assert!(flag_takes_value("--config"));
+        assert!(flag_takes_value("--tcp-max-size-bytes"));
+        assert!(!flag_takes_value("--tcp-max-size-bytes=1024"));
+        assert!(!flag_takes_value("--strict"));
+        assert!(!flag_takes_value("--fail-on-error"));
+    }
+
+    #[test]
+    fn invalid_args_are_named() {
+        let err = Args::parse(vec!["--tcp-max-size"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown argument `--tcp-max-size`"));
+
+        let err = Args::parse(vec!["--tcp-max-size-bytes"]).unwrap_err();
+        assert!(err.to_string().contains("missing a value"));
+
+        let args = Args::parse(vec!["--tcp-max-size-bytes", "lots"]).expect("failed to parse args");
+
+        let err = Config::default().read(&args).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("invalid value `lots` for `--tcp-max-size-bytes`"));
+    }
+
+    #[test]
+    fn flags_are_derived_from_environment_variables() {
+        assert_eq!("--address", BIND_ADDRESS.flag());
+        assert_eq!("--tcp-max-size-bytes", TCP_MAX_SIZE_BYTES.flag());
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        Config::default()
+            .validate(false)
+            .expect("default config is invalid");
+    }
+
+    #[test]
+    fn invalid_settings_are_named() {
+        let mut config = Config::default();
+        config.receive.max_chunks_per_message = 0;
+
+        let err = config.validate(false).unwrap_err().to_string();
+        assert!(err.contains("`GELF_MAX_CHUNKS_PER_MESSAGE`"));
+        assert!(err.contains("`receive.max_chunks_per_message`"));
+
+        let err = config.validate(true).unwrap_err().to_string();
+        assert!(err.contains("`SEQ_APP_SETTING_MAXCHUNKSPERMESSAGE`"));
+
+        let mut config = Config::default();
+        config.server.tcp_os_keep_alive_secs = Some(0);
+
+        let err = config.validate(false).unwrap_err().to_string();
+        assert!(err.contains("`GELF_TCP_OS_KEEP_ALIVE_SECS`"));
     }
 }
@@ -0,0 +1,460 @@
+/*!
+Keep messages that couldn't be received or processed so they can be resubmitted.
+
+Dead-letter files are newline-delimited JSON, like captures. Each record has
+the same fields as a capture record, along with the stage that failed and its
+error, so dead-letter files can be replayed the same way as a capture.
+
+A chunk that fails to be received is written on its own, without the other
+chunks of its message, so it's kept for inspection but can't be resubmitted.
+*/
+
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        BufRead,
+        LineWriter,
+        Write,
+    },
+    net::SocketAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use bytes::Bytes;
+use chrono::{
+    DateTime,
+    Utc,
+};
+
+use crate::{
+    capture,
+    import::Summary,
+    receive::{
+        self,
+        Message,
+    },
+    Error,
+};
+
+/**
+The prefix of dead-letter file names.
+*/
+const FILE_PREFIX: &str = "dead-letter-";
+
+/**
+The extension of dead-letter file names.
+*/
+const FILE_EXTENSION: &str = "jsonl";
+
+/**
+The stage a message failed at.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /**
+    The payload couldn't be decoded into a GELF message.
+    */
+    Receive,
+    /**
+    The GELF message couldn't be processed.
+    */
+    Process,
+}
+
+/**
+A message that couldn't be received or processed.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /**
+    The raw payload, along with when and where it was received from.
+    */
+    #[serde(flatten)]
+    pub raw: capture::Record,
+    /**
+    The stage the message failed at.
+    */
+    pub stage: Stage,
+    /**
+    The error the message failed with.
+    */
+    pub error: String,
+    /**
+    Whether the payload can be resubmitted on its own.
+
+    This is `false` for a chunk that failed to be received, because the other
+    chunks of its message aren't kept alongside it.
+    */
+    #[serde(default = "default_replayable")]
+    pub replayable: bool,
+}
+
+fn default_replayable() -> bool {
+    true
+}
+
+impl Record {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        peer: Option<SocketAddr>,
+        protocol: &str,
+        stage: Stage,
+        error: &Error,
+        payload: &[u8],
+    ) -> Self {
+        Record {
+            raw: capture::Record::new(timestamp, peer, protocol, payload),
+            stage,
+            error: format!("{:#}", error),
+            replayable: !(stage == Stage::Receive && receive::is_partial_chunk(payload)),
+        }
+    }
+}
+
+/**
+Resubmit a dead-letter file through the receive and process functions.
+
+Records that can't be resubmitted on their own are skipped.
+*/
+pub fn resubmit(
+    read: impl BufRead,
+    receive: impl FnMut(Bytes) -> Result<Option<Message>, Error>,
+    process: impl FnMut(Message) -> Result<(), Error>,
+) -> Result<Summary, Error> {
+    capture::replay_lines(
+        read,
+        |line| {
+            let record: Record = serde_json::from_str(line)?;
+
+            Ok(if record.replayable {
+                Some(record.raw)
+            } else {
+                None
+            })
+        },
+        receive,
+        process,
+    )
+}
+
+/**
+Writes dead-letter records to a directory, rotating files by size.
+*/
+pub struct Writer {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    file: Option<LineWriter<File>>,
+    file_bytes: u64,
+}
+
+impl Writer {
+    /**
+    Open a dead-letter directory, creating it if it doesn't exist.
+
+    A new file isn't created until the first record is written, so starting
+    the server doesn't leave empty files behind. Once a file reaches
+    `max_file_bytes` a new one is started. Only the newest `max_files` files
+    are kept. A value of `0` keeps all files.
+    */
+    pub fn open(
+        dir: impl AsRef<Path>,
+        max_file_bytes: u64,
+        max_files: usize,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_owned();
+
+        fs::create_dir_all(&dir).map_err(|e| {
+            Error::from(e).context(format!(
+                "failed to create dead-letter directory `{}`",
+                dir.display()
+            ))
+        })?;
+
+        Ok(Writer {
+            dir,
+            max_file_bytes,
+            max_files,
+            file: None,
+            file_bytes: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        // NOTE: A record is never split across files, so a single
+        // record larger than the maximum gets a file of its own
+        if self.file_bytes > 0 && self.file_bytes + line.len() as u64 > self.max_file_bytes {
+            if let Some(mut file) = self.file.take() {
+                file.flush()?;
+            }
+        }
+
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => {
+                self.file_bytes = 0;
+                self.file
+                    .get_or_insert(LineWriter::new(create_file(&self.dir)?))
+            }
+        };
+
+        file.write_all(&line)?;
+
+        // A new file is only counted once it has a record in it
+        if self.file_bytes == 0 {
+            self.prune()?;
+        }
+
+        self.file_bytes += line.len() as u64;
+
+        Ok(())
+    }
+
+    /**
+    Remove the oldest files beyond the maximum number to keep.
+
+    Empty files aren't counted, so they never push out files with records in them.
+    */
+    fn prune(&self) -> Result<(), Error> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+
+        let mut files = files(&self.dir)?;
+        files.retain(|path| fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false));
+
+        if files.len() > self.max_files {
+            for path in &files[..files.len() - self.max_files] {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+Create a new dead-letter file.
+
+Files are named by when they were created so they sort oldest first.
+*/
+fn create_file(dir: &Path) -> Result<File, Error> {
+    let mut nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    loop {
+        let path = dir.join(format!("{}{:024}.{}", FILE_PREFIX, nanos, FILE_EXTENSION));
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok(file),
+            // Another file was created at the same instant
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => nanos += 1,
+            Err(err) => {
+                return Err(Error::from(err).context(format!(
+                    "failed to create dead-letter file `{}`",
+                    path.display()
+                )))
+            }
+        }
+    }
+}
+
+/**
+List the dead-letter files in a directory, oldest first.
+*/
+pub fn files(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+    let dir = dir.as_ref();
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(|e| {
+        Error::from(e).context(format!(
+            "failed to read dead-letter directory `{}`",
+            dir.display()
+        ))
+    })? {
+        let path = entry?.path();
+
+        let is_dead_letter = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with(FILE_PREFIX))
+            .unwrap_or(false)
+            && path.extension().and_then(|ext| ext.to_str()) == Some(FILE_EXTENSION);
+
+        if is_dead_letter {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        process,
+        test_util::TempDir,
+    };
+
+    fn record(payload: &[u8]) -> Record {
+        Record::new(
+            Utc::now(),
+            Some(([127, 0, 0, 1], 12201).into()),
+            "udp",
+            Stage::Process,
+            &anyhow!("failed"),
+            payload,
+        )
+    }
+
+    #[test]
+    fn files_are_rotated_and_pruned() {
+        let temp = TempDir::new("dead-letter");
+        let dir = temp.path();
+
+        let mut writer = Writer::open(dir, 256, 2).expect("failed to open writer");
+
+        for _ in 0..6 {
+            writer.write(&record(&[b'a'; 64])).expect("failed to write");
+        }
+
+        let files = files(dir).expect("failed to list files");
+
+        assert_eq!(2, files.len());
+    }
+
+    #[test]
+    fn opening_doesnt_create_or_prune_files() {
+        let temp = TempDir::new("dead-letter");
+        let dir = temp.path();
+
+        let mut writer = Writer::open(dir, 1024 * 1024, 2).expect("failed to open writer");
+        writer.write(&record(b"a")).expect("failed to write");
+        drop(writer);
+
+        // Restarting without any failures doesn't push out the file with a record in it
+        for _ in 0..5 {
+            drop(Writer::open(dir, 1024 * 1024, 2).expect("failed to open writer"));
+        }
+
+        let files = files(dir).expect("failed to list files");
+
+        assert_eq!(1, files.len());
+    }
+
+    #[test]
+    fn records_can_be_replayed() {
+        let temp = TempDir::new("dead-letter");
+        let dir = temp.path();
+
+        let mut writer = Writer::open(dir, 1024 * 1024, 0).expect("failed to open writer");
+        writer
+            .write(&record(br#"{"host":"foo","short_message":"bar"}"#))
+            .expect("failed to write");
+        drop(writer);
+
+        let files = files(dir).expect("failed to list files");
+        assert_eq!(1, files.len());
+
+        let read = capture::open(&files[0]).expect("failed to open file");
+
+        let mut receive = receive::build(receive::Config::default());
+        let process = process::build(process::Config::default());
+
+        let mut received = Vec::new();
+        let summary = capture::replay(
+            read,
+            |src: Bytes| receive.decode(src),
+            |msg| {
+                process.with_clef(msg, |clef| {
+                    received.push(serde_json::to_value(clef)?);
+
+                    Ok(())
+                })
+            },
+        )
+        .expect("failed to replay");
+
+        assert_eq!(1, summary.processed);
+        assert_eq!("bar", received[0]["@m"]);
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let record = record(b"not json");
+
+        let json = serde_json::to_value(&record).expect("failed to serialize");
+        assert_eq!("process", json["stage"]);
+        assert_eq!("failed", json["error"]);
+        assert_eq!("udp", json["protocol"]);
+
+        let read: Record = serde_json::from_value(json).expect("failed to deserialize");
+        assert_eq!(record, read);
+    }
+
+    #[test]
+    fn partial_chunks_are_skipped_when_resubmitting() {
+        let failed = |stage, payload: &[u8]| {
+            Record::new(Utc::now(), None, "udp", stage, &anyhow!("failed"), payload)
+        };
+
+        // The first of two chunks, which can't be received without the second
+        let mut chunk = vec![0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 0, 2];
+        chunk.extend_from_slice(br#"{"host":"foo","#);
+
+        let partial = failed(Stage::Receive, &chunk);
+        assert!(!partial.replayable);
+
+        let whole = failed(Stage::Process, br#"{"host":"foo","short_message":"bar"}"#);
+        assert!(whole.replayable);
+
+        let mut lines = Vec::new();
+        for record in &[partial, whole] {
+            serde_json::to_writer(&mut lines, record).expect("failed to serialize");
+            lines.push(b'\n');
+        }
+
+        let mut receive = receive::build(receive::Config::default());
+
+        let summary = resubmit(&lines[..], |src| receive.decode(src), |_| Ok(()))
+            .expect("failed to resubmit");
+
+        assert_eq!(1, summary.processed);
+        assert_eq!(0, summary.failed);
+        assert_eq!(1, summary.skipped);
+    }
+
+    #[test]
+    fn records_without_replayable_are_replayable() {
+        let json = serde_json::json!({
+            "timestamp": "2026-01-01T00:00:00Z",
+            "protocol": "udp",
+            "payload": "",
+            "stage": "receive",
+            "error": "failed",
+        });
+
+        let record: Record = serde_json::from_value(json).expect("failed to deserialize");
+        assert!(record.replayable);
+    }
+}
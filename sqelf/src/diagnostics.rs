@@ -4,11 +4,15 @@ use chrono::{
     Utc,
 };
 use std::{
+    cmp,
     collections::HashMap,
+    convert::TryFrom,
     ops::Drop,
     str::FromStr,
     sync::{
         atomic::{
+            AtomicBool,
+            AtomicU64,
             AtomicUsize,
             Ordering,
         },
@@ -16,19 +20,25 @@ use std::{
         Mutex,
     },
     thread,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 pub(crate) static MIN_LEVEL: MinLevel = MinLevel(AtomicUsize::new(0));
+static PAYLOAD_PREVIEW: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     static ref DIAGNOSTICS: Mutex<Option<Diagnostics>> = Mutex::new(None);
+    static ref ERRORS: Mutex<Option<ErrorLimiter>> = Mutex::new(None);
 }
 
 /**
 Diagnostics configuration.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /**
     The interval to sample metrics at.
@@ -38,13 +48,35 @@ pub struct Config {
     The minimum self log level to emit.
     */
     pub min_level: Level,
+    /**
+    The interval to summarize suppressed errors at.
+    */
+    pub error_summary_interval_ms: u64,
+    /**
+    The maximum number of errors with the same template and kind
+    to emit individually within each summary interval.
+
+    Any more are suppressed and counted in a summary instead.
+    Setting this to `0` disables rate limiting.
+    */
+    pub max_errors_per_interval: usize,
+    /**
+    Whether to include a preview of the payload in diagnostics
+    about messages that fail to be received or processed.
+
+    Payloads may contain sensitive data, so previews aren't included by default.
+    */
+    pub include_payload_preview: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            metrics_interval_ms: 1 * 1000 * 60, // 1 minute
+            metrics_interval_ms: 1000 * 60, // 1 minute
             min_level: Level::Error,
+            error_summary_interval_ms: 1000 * 60, // 1 minute
+            max_errors_per_interval: 10,
+            include_payload_preview: false,
         }
     }
 }
@@ -61,32 +93,53 @@ pub fn init(config: Config) {
     }
 
     MIN_LEVEL.set(config.min_level);
+    PAYLOAD_PREVIEW.store(config.include_payload_preview, Ordering::Relaxed);
 
-    // Only set up metrics if the minimum level is Debug
-    let metrics = if MIN_LEVEL.includes(Level::Debug) {
-        // NOTE: Diagnostics use a regular thread instead of `tokio`
-        // So that we can monitor metrics independently of the `tokio`
-        // runtime.
-        let (tx, rx) = mpsc::channel();
-        let metrics_timeout = Duration::from_millis(config.metrics_interval_ms);
-        let handle = thread::spawn(move || loop {
-            match rx.recv_timeout(metrics_timeout) {
-                Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => {
-                    emit_metrics();
-                    return;
-                }
-                Err(mpsc::RecvTimeoutError::Timeout) => {
-                    emit_metrics();
+    if config.max_errors_per_interval > 0 {
+        *ERRORS.lock().expect("failed to lock errors") =
+            Some(ErrorLimiter::new(config.max_errors_per_interval));
+    }
+
+    // NOTE: Diagnostics use a regular thread instead of `tokio`
+    // So that we can monitor metrics independently of the `tokio`
+    // runtime.
+    let (tx, rx) = mpsc::channel();
+    let handle = {
+        // NOTE: Metrics are only emitted if the minimum level is Debug
+        // The interval always runs so the level can be changed at runtime
+        let mut metrics = Interval::new(config.metrics_interval_ms);
+        let mut errors = Interval::new(config.error_summary_interval_ms);
+
+        thread::spawn(move || {
+            let mut previous = PreviousMetrics::default();
+
+            loop {
+                let next = cmp::min(metrics.next, errors.next);
+
+                match rx.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                    Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        emit_metrics(&mut previous);
+                        emit_error_summaries(errors.interval);
+
+                        return;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if metrics.tick() {
+                            emit_metrics(&mut previous);
+                        }
+
+                        if errors.tick() {
+                            emit_error_summaries(errors.interval);
+                        }
+                    }
                 }
             }
-        });
-
-        Some((tx, handle))
-    } else {
-        None
+        })
     };
 
-    *diagnostics = Some(Diagnostics { metrics });
+    *diagnostics = Some(Diagnostics {
+        worker: Some((tx, handle)),
+    });
 }
 
 /**
@@ -96,19 +149,47 @@ pub fn stop() -> Result<(), Error> {
     let mut diagnostics = DIAGNOSTICS.lock().expect("failed to lock diagnostics");
 
     if let Some(mut diagnostics) = diagnostics.take() {
-        diagnostics.stop_metrics()?;
+        diagnostics.stop_worker()?;
     }
 
+    *ERRORS.lock().expect("failed to lock errors") = None;
+
     Ok(())
 }
 
+/**
+Change the minimum self log level while diagnostics are running.
+
+Diagnostics are process-wide, so this affects every running server.
+*/
+pub fn set_min_level(min_level: Level) {
+    MIN_LEVEL.set(min_level);
+}
+
+/**
+Change the maximum number of errors with the same template and kind
+to emit individually within each summary interval.
+
+Diagnostics are process-wide, so this affects every running server.
+Setting this to `0` disables rate limiting.
+*/
+pub fn set_max_errors_per_interval(max_errors_per_interval: usize) {
+    let mut errors = ERRORS.lock().expect("failed to lock errors");
+
+    match (&mut *errors, max_errors_per_interval) {
+        (_, 0) => *errors = None,
+        (Some(errors), max_per_interval) => errors.max_per_interval = max_per_interval,
+        (None, max_per_interval) => *errors = Some(ErrorLimiter::new(max_per_interval)),
+    }
+}
+
 struct Diagnostics {
-    metrics: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+    worker: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
 }
 
 impl Diagnostics {
-    fn stop_metrics(&mut self) -> Result<(), Error> {
-        if let Some((tx, handle)) = self.metrics.take() {
+    fn stop_worker(&mut self) -> Result<(), Error> {
+        if let Some((tx, handle)) = self.worker.take() {
             tx.send(())?;
 
             handle
@@ -120,17 +201,56 @@ impl Diagnostics {
     }
 }
 
+/**
+A periodic deadline for the diagnostics thread.
+*/
+struct Interval {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    fn new(interval_ms: u64) -> Self {
+        let interval = Duration::from_millis(interval_ms);
+
+        Interval {
+            interval,
+            next: Instant::now() + interval,
+        }
+    }
+
+    fn tick(&mut self) -> bool {
+        let now = Instant::now();
+
+        if now >= self.next {
+            self.next = now + self.interval;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 impl Drop for Diagnostics {
     fn drop(&mut self) {
-        if let Some((tx, _)) = self.metrics.take() {
+        if let Some((tx, _)) = self.worker.take() {
             let _ = tx.send(());
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/**
+The level of a diagnostic event.
+
+Levels are ordered from the most to the least verbose.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
 pub enum Level {
+    Verbose,
     Debug,
+    Information,
+    Warning,
     Error,
 }
 
@@ -138,25 +258,54 @@ impl FromStr for Level {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
+        match s.to_ascii_uppercase().as_str() {
+            "VERBOSE" | "TRACE" => Ok(Level::Verbose),
             "DEBUG" => Ok(Level::Debug),
+            "INFORMATION" | "INFO" => Ok(Level::Information),
+            "WARNING" | "WARN" => Ok(Level::Warning),
             "ERROR" => Ok(Level::Error),
-            _ => Err(anyhow!("expected `DEBUG` or `ERROR`")),
+            _ => Err(anyhow!(
+                "expected `VERBOSE`, `DEBUG`, `INFORMATION`, `WARNING` or `ERROR`"
+            )),
         }
     }
 }
 
+impl TryFrom<String> for Level {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
 impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Verbose => "VERBOSE",
+            Level::Debug => "DEBUG",
+            Level::Information => "INFORMATION",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR",
+        }
+    }
+
     fn to_usize(self) -> usize {
         match self {
-            Level::Debug => 0,
-            Level::Error => 1,
+            Level::Verbose => 0,
+            Level::Debug => 1,
+            Level::Information => 2,
+            Level::Warning => 3,
+            Level::Error => 4,
         }
     }
 
     fn from_usize(v: usize) -> Self {
         match v {
-            0 => Level::Debug,
+            0 => Level::Verbose,
+            1 => Level::Debug,
+            2 => Level::Information,
+            3 => Level::Warning,
             _ => Level::Error,
         }
     }
@@ -199,31 +348,114 @@ impl<'a> DiagnosticEvent<'a> {
 }
 
 pub fn emit(message_template: &'static str) {
-    if MIN_LEVEL.includes(Level::Debug) {
-        let evt = DiagnosticEvent::new("DEBUG", None, &message_template, None);
-        let json = serde_json::to_string(&evt).expect("infallible JSON");
-        eprintln!("{}", json);
-    }
+    emit_with(message_template, ());
+}
+
+/**
+Emit a debug event with some contextual properties.
+
+The properties are serialized as a map and added to the event alongside its message.
+*/
+pub fn emit_with(message_template: &'static str, properties: impl serde::Serialize) {
+    emit_event(Level::Debug, None, message_template, properties);
+}
+
+/**
+Emit a verbose event with some contextual properties.
+*/
+pub fn emit_verbose_with(message_template: &'static str, properties: impl serde::Serialize) {
+    emit_event(Level::Verbose, None, message_template, properties);
+}
+
+pub fn emit_info(message_template: &'static str) {
+    emit_event(Level::Information, None, message_template, ());
+}
+
+pub fn emit_warn(message_template: &'static str) {
+    emit_event(Level::Warning, None, message_template, ());
+}
+
+/**
+Emit a warning event with some contextual properties.
+*/
+pub fn emit_warn_with(message_template: &'static str, properties: impl serde::Serialize) {
+    emit_event(Level::Warning, None, message_template, properties);
+}
+
+pub fn emit_warn_err(error: &(dyn std::error::Error + 'static), message_template: &'static str) {
+    emit_event(Level::Warning, Some(error), message_template, ());
 }
 
 pub fn emit_debug_err(error: &(dyn std::error::Error + 'static), message_template: &'static str) {
-    if MIN_LEVEL.includes(Level::Debug) {
-        let err_str = format_error(error);
-        let evt = DiagnosticEvent::new("DEBUG", Some(&err_str), &message_template, None);
-        let json = serde_json::to_string(&evt).expect("infallible JSON");
-        eprintln!("{}", json);
-    }
+    emit_debug_err_with(error, message_template, ());
+}
+
+/**
+Emit a debug event for an error with some contextual properties.
+*/
+pub fn emit_debug_err_with(
+    error: &(dyn std::error::Error + 'static),
+    message_template: &'static str,
+    properties: impl serde::Serialize,
+) {
+    emit_event(Level::Debug, Some(error), message_template, properties);
 }
 
 pub fn emit_err(error: &(dyn std::error::Error + 'static), message_template: &'static str) {
-    if MIN_LEVEL.includes(Level::Error) {
-        let err_str = format_error(error);
-        let evt = DiagnosticEvent::new("ERROR", Some(&err_str), &message_template, None);
+    emit_err_with(error, message_template, ());
+}
+
+/**
+Emit an error event with some contextual properties.
+*/
+pub fn emit_err_with(
+    error: &(dyn std::error::Error + 'static),
+    message_template: &'static str,
+    properties: impl serde::Serialize,
+) {
+    emit_event(Level::Error, Some(error), message_template, properties);
+}
+
+fn emit_event(
+    level: Level,
+    error: Option<&(dyn std::error::Error + 'static)>,
+    message_template: &'static str,
+    properties: impl serde::Serialize,
+) {
+    if MIN_LEVEL.includes(level) {
+        let err_str = error.map(format_error);
+        let additional = to_additional(properties);
+
+        // Warnings and errors are rate limited so a misbehaving
+        // client can't flood the log with the same event
+        if level >= Level::Warning {
+            if let Some(ref mut errors) = *ERRORS.lock().expect("failed to lock errors") {
+                if !errors.try_emit(level, message_template, err_str.as_deref(), &additional) {
+                    return;
+                }
+            }
+        }
+
+        let evt = DiagnosticEvent::new(
+            level.as_str(),
+            err_str.as_deref(),
+            message_template,
+            additional,
+        );
         let json = serde_json::to_string(&evt).expect("infallible JSON");
         eprintln!("{}", json);
     }
 }
 
+fn to_additional(properties: impl serde::Serialize) -> Option<serde_json::Value> {
+    // NOTE: Only maps can be flattened into the event
+    // Anything else, like `()`, is treated as no properties
+    match serde_json::to_value(properties) {
+        Ok(properties @ serde_json::Value::Object(_)) => Some(properties),
+        _ => None,
+    }
+}
+
 fn format_error(error: &(dyn std::error::Error + 'static)) -> String {
     use std::fmt::Write as _;
 
@@ -236,30 +468,238 @@ fn format_error(error: &(dyn std::error::Error + 'static)) -> String {
     s
 }
 
-fn emit_metrics() {
+/**
+The maximum number of distinct kinds of events to track within an interval.
+
+Events beyond this are tracked together by their template alone.
+*/
+const MAX_ERROR_KINDS: usize = 1024;
+
+/**
+Deduplicates and rate limits warning and error events.
+
+Events are grouped by their template and kind. The kind of an event is
+its error message with any numbers removed, so errors like `expected ident at line 1 column 2`
+and `expected ident at line 1 column 5` are considered the same.
+*/
+struct ErrorLimiter {
+    max_per_interval: usize,
+    by_kind: HashMap<(&'static str, Option<String>), ErrorCount>,
+}
+
+struct ErrorCount {
+    level: Level,
+    total: usize,
+    sample: Option<Sampled>,
+}
+
+struct Sampled {
+    err: Option<String>,
+    properties: Option<serde_json::Value>,
+}
+
+impl ErrorLimiter {
+    fn new(max_per_interval: usize) -> Self {
+        ErrorLimiter {
+            max_per_interval,
+            by_kind: HashMap::new(),
+        }
+    }
+
+    /**
+    Record an event, returning whether or not it should be emitted.
+    */
+    fn try_emit(
+        &mut self,
+        level: Level,
+        message_template: &'static str,
+        err: Option<&str>,
+        properties: &Option<serde_json::Value>,
+    ) -> bool {
+        let mut key = (
+            message_template,
+            Some(err.map(error_kind).unwrap_or_default()),
+        );
+
+        if self.by_kind.len() >= MAX_ERROR_KINDS && !self.by_kind.contains_key(&key) {
+            key.1 = None;
+        }
+
+        let count = self.by_kind.entry(key).or_insert(ErrorCount {
+            level,
+            total: 0,
+            sample: None,
+        });
+
+        count.total += 1;
+
+        // Keep the first event in the interval as a sample for the summary
+        if count.sample.is_none() {
+            count.sample = Some(Sampled {
+                err: err.map(ToOwned::to_owned),
+                properties: properties.clone(),
+            });
+        }
+
+        count.total <= self.max_per_interval
+    }
+
+    /**
+    Take the events that were suppressed since the last summary.
+    */
+    fn take_suppressed(&mut self) -> Vec<ErrorSummary> {
+        let max_per_interval = self.max_per_interval;
+
+        self.by_kind
+            .drain()
+            .filter(|(_, count)| count.total > max_per_interval)
+            .filter_map(|((message_template, _), count)| {
+                let sample = count.sample?;
+
+                Some(ErrorSummary {
+                    level: count.level,
+                    message_template,
+                    total: count.total,
+                    sample,
+                })
+            })
+            .collect()
+    }
+}
+
+struct ErrorSummary {
+    level: Level,
+    message_template: &'static str,
+    total: usize,
+    sample: Sampled,
+}
+
+fn error_kind(err: &str) -> String {
+    let mut kind = String::with_capacity(err.len());
+    let mut in_number = false;
+
+    for c in err.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                kind.push('#');
+            }
+
+            in_number = true;
+        } else {
+            kind.push(c);
+            in_number = false;
+        }
+    }
+
+    kind
+}
+
+fn emit_error_summaries(interval: Duration) {
+    let summaries = match *ERRORS.lock().expect("failed to lock errors") {
+        Some(ref mut errors) => errors.take_suppressed(),
+        None => return,
+    };
+
+    for summary in summaries {
+        let evt = DiagnosticEvent::new(
+            summary.level.as_str(),
+            summary.sample.err.as_deref(),
+            "{message} {count} times in the last {interval_secs} seconds",
+            Some(serde_json::json!({
+                "message": summary.message_template,
+                "count": summary.total,
+                "interval_secs": interval.as_secs(),
+                "sample": summary.sample.properties,
+            })),
+        );
+        let json = serde_json::to_string(&evt).expect("infallible JSON");
+
+        eprintln!("{}", json);
+    }
+}
+
+/**
+The values of cumulative metrics when they were last emitted.
+*/
+#[derive(Default)]
+struct PreviousMetrics {
+    counters: HashMap<(&'static str, &'static str), usize>,
+    histograms: HashMap<(&'static str, &'static str), HistogramSample>,
+}
+
+impl PreviousMetrics {
+    /**
+    Replace cumulative counters and histograms in a snapshot with their change since the last one.
+    */
+    fn delta(&mut self, mut snapshot: Snapshot) -> Snapshot {
+        for sample in &mut snapshot.counters {
+            let previous = self
+                .counters
+                .insert((sample.module, sample.name), sample.value)
+                .unwrap_or(0);
+
+            sample.value = sample.value.saturating_sub(previous);
+        }
+
+        for sample in &mut snapshot.histograms {
+            if let Some(previous) = self
+                .histograms
+                .insert((sample.module, sample.name), sample.value.clone())
+            {
+                sample.value = sample.value.since(&previous);
+            }
+        }
+
+        snapshot
+    }
+}
+
+fn emit_metrics(previous: &mut PreviousMetrics) {
+    // NOTE: Metrics are sampled even when they aren't emitted
+    // so changing the level at runtime doesn't emit a large delta
+    let snapshot = previous.delta(metrics());
+
     if MIN_LEVEL.includes(Level::Debug) {
-        #[derive(Serialize)]
+        #[derive(Serialize, Default)]
         struct EmitMetrics {
-            receive: HashMap<&'static str, usize>,
-            process: HashMap<&'static str, usize>,
-            server: HashMap<&'static str, usize>,
+            receive: HashMap<&'static str, serde_json::Value>,
+            process: HashMap<&'static str, serde_json::Value>,
+            server: HashMap<&'static str, serde_json::Value>,
         }
 
-        let mut metrics = EmitMetrics {
-            receive: HashMap::new(),
-            process: HashMap::new(),
-            server: HashMap::new(),
-        };
+        impl EmitMetrics {
+            fn module(&mut self, module: &str) -> &mut HashMap<&'static str, serde_json::Value> {
+                match module {
+                    "receive" => &mut self.receive,
+                    "process" => &mut self.process,
+                    _ => &mut self.server,
+                }
+            }
 
-        let receive = METRICS.receive.take();
-        let process = METRICS.process.take();
-        let server = METRICS.server.take();
+            fn insert(&mut self, sample: Sample<impl serde::Serialize>) {
+                let value = serde_json::to_value(sample.value).expect("infallible JSON");
 
-        metrics.receive.extend(receive.as_ref().iter().cloned());
-        metrics.process.extend(process.as_ref().iter().cloned());
-        metrics.server.extend(server.as_ref().iter().cloned());
+                self.module(sample.module).insert(sample.name, value);
+            }
+        }
+
+        let mut emit = EmitMetrics::default();
+
+        // Counters and histograms are emitted as the change since they were
+        // last sampled, and gauges are emitted as their current value
+        for sample in snapshot.counters {
+            emit.insert(sample);
+        }
+
+        for sample in snapshot.gauges {
+            emit.insert(sample);
+        }
 
-        let metrics = serde_json::to_value(metrics).expect("infallible JSON");
+        for sample in snapshot.histograms {
+            emit.insert(sample);
+        }
+
+        let metrics = serde_json::to_value(emit).expect("infallible JSON");
 
         let evt = DiagnosticEvent::new(
             "DEBUG",
@@ -273,6 +713,65 @@ fn emit_metrics() {
     }
 }
 
+/**
+Get a snapshot of the current value of all metrics.
+
+Metrics are always collected, regardless of the minimum self log level.
+Counters and histograms are cumulative from when the process started.
+*/
+pub fn metrics() -> Snapshot {
+    METRICS.snapshot()
+}
+
+/**
+Render the current value of all metrics in the Prometheus text format.
+*/
+pub fn render_prometheus() -> String {
+    use std::fmt::Write as _;
+
+    let snapshot = metrics();
+    let mut rendered = String::new();
+
+    for sample in snapshot.counters {
+        let name = format!("sqelf_{}_{}_total", sample.module, sample.name);
+
+        let _ = writeln!(&mut rendered, "# TYPE {} counter", name);
+        let _ = writeln!(&mut rendered, "{} {}", name, sample.value);
+    }
+
+    for sample in snapshot.gauges {
+        let name = format!("sqelf_{}_{}", sample.module, sample.name);
+
+        let _ = writeln!(&mut rendered, "# TYPE {} gauge", name);
+        let _ = writeln!(&mut rendered, "{} {}", name, sample.value);
+    }
+
+    for sample in snapshot.histograms {
+        let name = format!("sqelf_{}_{}", sample.module, sample.name);
+
+        let _ = writeln!(&mut rendered, "# TYPE {} histogram", name);
+        for (le, count) in &sample.value.buckets {
+            let _ = writeln!(&mut rendered, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        let _ = writeln!(
+            &mut rendered,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            name, sample.value.count
+        );
+        let _ = writeln!(&mut rendered, "{}_sum {}", name, sample.value.sum);
+        let _ = writeln!(&mut rendered, "{}_count {}", name, sample.value.count);
+    }
+
+    rendered
+}
+
+/**
+Whether diagnostics should include a preview of message payloads.
+*/
+pub(crate) fn includes_payload_preview() -> bool {
+    PAYLOAD_PREVIEW.load(Ordering::Relaxed)
+}
+
 pub(crate) struct MinLevel(AtomicUsize);
 
 impl MinLevel {
@@ -303,21 +802,238 @@ pub(crate) static METRICS: Metrics = Metrics {
     _private: (),
 };
 
+impl Metrics {
+    fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot {
+            counters: Vec::new(),
+            gauges: Vec::new(),
+            histograms: Vec::new(),
+        };
+
+        self.receive.sample("receive", &mut snapshot);
+
+        // NOTE: Incomplete messages are counted by the receivers holding them
+        snapshot.gauges.push(Sample {
+            module: "receive",
+            name: "msg_incomplete",
+            value: crate::receive::incomplete_messages(),
+        });
+
+        self.process.sample("process", &mut snapshot);
+        self.server.sample("server", &mut snapshot);
+
+        snapshot
+    }
+}
+
+/**
+A point-in-time sample of all metrics.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    /**
+    Counters that only ever increase.
+    */
+    pub counters: Vec<Sample<usize>>,
+    /**
+    Gauges that can increase and decrease.
+    */
+    pub gauges: Vec<Sample<usize>>,
+    /**
+    Histograms of observed values.
+    */
+    pub histograms: Vec<Sample<HistogramSample>>,
+}
+
+impl Snapshot {
+    /**
+    Get the value of a counter.
+    */
+    pub fn counter(&self, module: &str, name: &str) -> Option<usize> {
+        find(&self.counters, module, name).map(|sample| sample.value)
+    }
+
+    /**
+    Get the value of a gauge.
+    */
+    pub fn gauge(&self, module: &str, name: &str) -> Option<usize> {
+        find(&self.gauges, module, name).map(|sample| sample.value)
+    }
+}
+
+fn find<'a, T>(samples: &'a [Sample<T>], module: &str, name: &str) -> Option<&'a Sample<T>> {
+    samples
+        .iter()
+        .find(|sample| sample.module == module && sample.name == name)
+}
+
+/**
+The value of a single metric.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample<T> {
+    /**
+    The module the metric belongs to, like `receive` or `server`.
+    */
+    pub module: &'static str,
+    /**
+    The name of the metric.
+    */
+    pub name: &'static str,
+    /**
+    The value of the metric.
+    */
+    pub value: T,
+}
+
+/**
+The value of a histogram.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramSample {
+    /**
+    The number of observations less than or equal to each bucket's upper bound.
+
+    Buckets are cumulative, so each bucket includes the counts of the ones before it.
+    */
+    pub buckets: Vec<(f64, usize)>,
+    /**
+    The total number of observations.
+    */
+    pub count: usize,
+    /**
+    The sum of all observations.
+    */
+    pub sum: f64,
+}
+
+impl HistogramSample {
+    /**
+    The observations made since a previous sample of the same histogram.
+    */
+    fn since(&self, previous: &HistogramSample) -> HistogramSample {
+        HistogramSample {
+            buckets: self
+                .buckets
+                .iter()
+                .zip(&previous.buckets)
+                .map(|((le, count), (_, previous))| (*le, count.saturating_sub(*previous)))
+                .collect(),
+            count: self.count.saturating_sub(previous.count),
+            sum: (self.sum - previous.sum).max(0.0),
+        }
+    }
+}
+
+/**
+The upper bounds of histogram buckets, in seconds.
+*/
+const HISTOGRAM_BUCKETS: [f64; 10] = [
+    0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+/**
+A histogram of durations with fixed buckets.
+*/
+pub(crate) struct Histogram {
+    buckets: [AtomicUsize; HISTOGRAM_BUCKETS.len()],
+    count: AtomicUsize,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub(crate) const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicUsize::new(0) }; HISTOGRAM_BUCKETS.len()],
+            count: AtomicUsize::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+
+        if let Some(bucket) = HISTOGRAM_BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sample(&self) -> HistogramSample {
+        let mut cumulative = 0;
+        let buckets = HISTOGRAM_BUCKETS
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(le, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+
+                (*le, cumulative)
+            })
+            .collect();
+
+        HistogramSample {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+/**
+Increment a counter.
+
+The counter is incremented by `1`, or by a given value.
+*/
 macro_rules! increment {
+    ($($metric:ident).+ += $value:expr) => {{
+        $crate::diagnostics::METRICS.$($metric).+.fetch_add($value, std::sync::atomic::Ordering::Relaxed);
+    }};
     ($($metric:tt)*) => {{
-        if $crate::diagnostics::MIN_LEVEL.includes($crate::diagnostics::Level::Debug) {
-            $crate::diagnostics::METRICS.$($metric)*.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        }
+        $crate::diagnostics::METRICS.$($metric)*.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }};
+}
+
+/**
+Adjust the value of a gauge.
+*/
+macro_rules! gauge {
+    ($($metric:ident).+ += $value:expr) => {{
+        $crate::diagnostics::METRICS.$($metric).+.fetch_add($value, std::sync::atomic::Ordering::Relaxed);
+    }};
+    ($($metric:ident).+ -= $value:expr) => {{
+        $crate::diagnostics::METRICS.$($metric).+.fetch_sub($value, std::sync::atomic::Ordering::Relaxed);
+    }};
+}
+
+/**
+Record a duration in a histogram.
+*/
+macro_rules! observe {
+    ($($metric:ident).+, $value:expr) => {{
+        $crate::diagnostics::METRICS.$($metric).+.observe($value);
     }};
 }
 
 macro_rules! metrics {
-    ($($metric:ident),*) => {
+    (
+        $($metric:ident),*
+        $(; gauges: $($gauge:ident),*)?
+        $(; histograms: $($histogram:ident),*)?
+    ) => {
         #[allow(dead_code)]
         pub(crate) struct Metrics {
             $(
                 pub(crate) $metric: std::sync::atomic::AtomicUsize,
             )*
+            $($(
+                pub(crate) $gauge: std::sync::atomic::AtomicUsize,
+            )*)?
+            $($(
+                pub(crate) $histogram: $crate::diagnostics::Histogram,
+            )*)?
             _private: (),
         }
 
@@ -328,20 +1044,249 @@ macro_rules! metrics {
                     $(
                         $metric: std::sync::atomic::AtomicUsize::new(0),
                     )*
+                    $($(
+                        $gauge: std::sync::atomic::AtomicUsize::new(0),
+                    )*)?
+                    $($(
+                        $histogram: $crate::diagnostics::Histogram::new(),
+                    )*)?
                     _private: (),
                 }
             }
 
             #[allow(dead_code)]
-            pub(crate) fn take(&self) -> impl AsRef<[(&'static str, usize)]> {
-                let fields = [
-                    $(
-                        (stringify!($metric), self.$metric.swap(0, std::sync::atomic::Ordering::Relaxed)),
-                    )*
-                ];
-
-                fields
+            pub(crate) fn sample(&self, module: &'static str, snapshot: &mut $crate::diagnostics::Snapshot) {
+                $(
+                    snapshot.counters.push($crate::diagnostics::Sample {
+                        module,
+                        name: stringify!($metric),
+                        value: self.$metric.load(std::sync::atomic::Ordering::Relaxed),
+                    });
+                )*
+                $($(
+                    snapshot.gauges.push($crate::diagnostics::Sample {
+                        module,
+                        name: stringify!($gauge),
+                        value: self.$gauge.load(std::sync::atomic::Ordering::Relaxed),
+                    });
+                )*)?
+                $($(
+                    snapshot.histograms.push($crate::diagnostics::Sample {
+                        module,
+                        name: stringify!($histogram),
+                        value: self.$histogram.sample(),
+                    });
+                )*)?
             }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+
+        histogram.observe(Duration::from_micros(50));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(10));
+
+        let sample = histogram.sample();
+
+        assert_eq!(3, sample.count);
+        assert_eq!((0.000_1, 1), sample.buckets[0]);
+        assert_eq!((0.005, 2), sample.buckets[3]);
+        assert_eq!((5.0, 2), sample.buckets[HISTOGRAM_BUCKETS.len() - 1]);
+        assert!((sample.sum - 10.00305).abs() < 0.000_001);
+    }
+
+    #[test]
+    fn emitted_metrics_are_deltas() {
+        let snapshot = |counter, observed: &[u64]| {
+            let histogram = Histogram::new();
+            for micros in observed {
+                histogram.observe(Duration::from_micros(*micros));
+            }
+
+            Snapshot {
+                counters: vec![Sample {
+                    module: "server",
+                    name: "receive_ok",
+                    value: counter,
+                }],
+                gauges: vec![Sample {
+                    module: "server",
+                    name: "tcp_conn_active",
+                    value: 3,
+                }],
+                histograms: vec![Sample {
+                    module: "server",
+                    name: "process_latency_seconds",
+                    value: histogram.sample(),
+                }],
+            }
+        };
+
+        let mut previous = PreviousMetrics::default();
+
+        let first = previous.delta(snapshot(5, &[50]));
+        assert_eq!(5, first.counters[0].value);
+        assert_eq!(1, first.histograms[0].value.count);
+
+        let second = previous.delta(snapshot(8, &[50, 50, 3000]));
+        assert_eq!(3, second.counters[0].value);
+        assert_eq!(3, second.gauges[0].value);
+        assert_eq!(2, second.histograms[0].value.count);
+        assert_eq!((0.000_1, 1), second.histograms[0].value.buckets[0]);
+    }
+
+    #[test]
+    fn snapshot_finds_metrics_by_module_and_name() {
+        let snapshot = metrics();
+
+        assert!(snapshot.counter("server", "receive_ok").is_some());
+        assert!(snapshot.gauge("server", "tcp_conn_active").is_some());
+        assert!(snapshot.gauge("server", "receive_ok").is_none());
+        assert!(snapshot.counter("process", "nope").is_none());
+    }
+
+    #[test]
+    fn properties_are_flattened_into_events() {
+        #[derive(Serialize)]
+        struct Properties {
+            peer: &'static str,
+            payload_size: usize,
+        }
+
+        let additional = to_additional(Properties {
+            peer: "127.0.0.1:12201",
+            payload_size: 42,
+        });
+        let evt = DiagnosticEvent::new(
+            "ERROR",
+            Some("failed"),
+            "GELF processing failed",
+            additional,
+        );
+
+        let json = serde_json::to_value(&evt).expect("infallible JSON");
+
+        assert_eq!("127.0.0.1:12201", json["peer"]);
+        assert_eq!(42, json["payload_size"]);
+        assert_eq!("GELF processing failed", json["@mt"]);
+    }
+
+    #[test]
+    fn non_map_properties_are_ignored() {
+        assert!(to_additional(()).is_none());
+        assert!(to_additional(42).is_none());
+    }
+
+    #[test]
+    fn error_kinds_ignore_numbers() {
+        assert_eq!(
+            error_kind("expected ident at line 1 column 2"),
+            error_kind("expected ident at line 1 column 52")
+        );
+        assert_ne!(
+            error_kind("expected ident at line 1 column 2"),
+            error_kind("EOF while parsing a string at line 1 column 34")
+        );
+    }
+
+    #[test]
+    fn errors_are_rate_limited_by_template_and_kind() {
+        let mut errors = ErrorLimiter::new(2);
+
+        for column in 0..5 {
+            let err = format!("expected ident at line 1 column {}", column);
+
+            assert_eq!(
+                column < 2,
+                errors.try_emit(Level::Error, "GELF processing failed", Some(&err), &None)
+            );
+        }
+
+        // Different kinds of errors are limited independently
+        assert!(errors.try_emit(
+            Level::Error,
+            "GELF processing failed",
+            Some("EOF while parsing"),
+            &None
+        ));
+        assert!(errors.try_emit(
+            Level::Error,
+            "GELF server failed",
+            Some("expected ident at line 1 column 2"),
+            &None
+        ));
+
+        let summaries = errors.take_suppressed();
+
+        assert_eq!(1, summaries.len());
+        assert_eq!("GELF processing failed", summaries[0].message_template);
+        assert_eq!(5, summaries[0].total);
+        assert_eq!(Level::Error, summaries[0].level);
+        assert_eq!(
+            Some("expected ident at line 1 column 0"),
+            summaries[0].sample.err.as_deref()
+        );
+
+        // Taking the summary starts a new interval
+        assert!(errors.try_emit(
+            Level::Error,
+            "GELF processing failed",
+            Some("expected ident"),
+            &None
+        ));
+        assert!(errors.take_suppressed().is_empty());
+    }
+
+    #[test]
+    fn parse_level() {
+        assert_eq!(Level::Verbose, "Verbose".parse::<Level>().unwrap());
+        assert_eq!(Level::Debug, "DEBUG".parse::<Level>().unwrap());
+        assert_eq!(Level::Information, "info".parse::<Level>().unwrap());
+        assert_eq!(Level::Warning, "Warning".parse::<Level>().unwrap());
+        assert_eq!(Level::Error, "error".parse::<Level>().unwrap());
+
+        assert!("Fatal".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn levels_are_ordered_by_verbosity() {
+        for level in [
+            Level::Verbose,
+            Level::Debug,
+            Level::Information,
+            Level::Warning,
+            Level::Error,
+        ] {
+            assert_eq!(level, Level::from_usize(level.to_usize()));
+        }
+
+        assert!(Level::Verbose < Level::Debug);
+        assert!(Level::Warning < Level::Error);
+    }
+
+    #[test]
+    fn counters_are_cumulative() {
+        let before = METRICS.process.msg.load(Ordering::Relaxed);
+
+        increment!(process.msg);
+        increment!(process.msg += 2);
+
+        let _ = metrics();
+        let after = metrics()
+            .counters
+            .into_iter()
+            .find(|sample| sample.module == "process" && sample.name == "msg")
+            .expect("missing counter")
+            .value;
+
+        assert!(after >= before + 3);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs::File,
+    io::{
+        self,
+        BufRead,
+        BufReader,
+    },
+};
+
+use bytes::Bytes;
+use libflate::gzip;
+
+use crate::{
+    diagnostics::*,
+    receive::Message,
+    Error,
+};
+
+/**
+The magic bytes at the start of a gzip file.
+*/
+const MAGIC_GZIP: [u8; 2] = [0x1f, 0x8b];
+
+/**
+The result of importing a file.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /**
+    The number of events that were processed successfully.
+    */
+    pub processed: usize,
+    /**
+    The number of events that failed to be received or processed.
+    */
+    pub failed: usize,
+    /**
+    The number of records that were skipped because they can't be processed on their own.
+    */
+    pub skipped: usize,
+}
+
+/**
+Open a file of GELF messages to import.
+
+A path of `-` reads from stdin. If the file is gzipped then it's decompressed.
+*/
+pub fn open(path: &str) -> Result<Box<dyn BufRead>, Error> {
+    let read: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(path)
+            .map_err(|e| Error::from(e).context(format!("failed to open `{}`", path)))?;
+
+        Box::new(BufReader::new(file))
+    };
+
+    decompress(read)
+}
+
+fn decompress(mut read: Box<dyn BufRead>) -> Result<Box<dyn BufRead>, Error> {
+    if read.fill_buf()?.starts_with(&MAGIC_GZIP) {
+        Ok(Box::new(BufReader::new(gzip::MultiDecoder::new(read)?)))
+    } else {
+        Ok(read)
+    }
+}
+
+/**
+Import GELF messages from a reader and process them.
+
+Messages are separated by newlines or null bytes. Each message is passed through
+the same receive and process functions as messages arriving from the network.
+A message that fails to be received or processed is counted, but doesn't stop the import.
+*/
+pub fn import(
+    mut read: impl BufRead,
+    mut receive: impl FnMut(Bytes) -> Result<Option<Message>, Error>,
+    mut process: impl FnMut(Message) -> Result<(), Error>,
+) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
+    let mut line = 0;
+
+    while let Some(record) = read_record(&mut read)? {
+        line += 1;
+
+        let record = trim(&record);
+
+        // Blank lines between messages are ignored
+        if record.is_empty() {
+            continue;
+        }
+
+        // NOTE: We don't use `?` here because we never want to carry results
+        // We always want to match them and deal with error cases directly
+        let processed = match receive(Bytes::copy_from_slice(record)) {
+            Ok(Some(msg)) => process(msg),
+            // A chunk without the rest of its message
+            Ok(None) => Err(anyhow!("the message is an incomplete chunk")),
+            Err(err) => Err(err),
+        };
+
+        match processed {
+            Ok(()) => {
+                summary.processed += 1;
+            }
+            Err(err) => {
+                summary.failed += 1;
+
+                emit_err_with(
+                    err.as_ref(),
+                    "GELF import failed",
+                    ImportProperties {
+                        line,
+                        payload_size: record.len(),
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[derive(Serialize)]
+struct ImportProperties {
+    line: usize,
+    payload_size: usize,
+}
+
+/**
+Read the next record that's terminated by a newline or null byte.
+
+Returns `None` once the reader is exhausted.
+*/
+fn read_record(read: &mut impl BufRead) -> Result<Option<Vec<u8>>, Error> {
+    let mut record = Vec::new();
+
+    loop {
+        let buf = read.fill_buf()?;
+
+        if buf.is_empty() {
+            return Ok(if record.is_empty() {
+                None
+            } else {
+                Some(record)
+            });
+        }
+
+        match buf.iter().position(|b| *b == b'\n' || *b == b'\0') {
+            Some(end) => {
+                record.extend_from_slice(&buf[..end]);
+                read.consume(end + 1);
+
+                return Ok(Some(record));
+            }
+            None => {
+                let len = buf.len();
+
+                record.extend_from_slice(buf);
+                read.consume(len);
+            }
+        }
+    }
+}
+
+fn trim(mut record: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = record {
+        if first.is_ascii_whitespace() {
+            record = rest;
+        } else {
+            break;
+        }
+    }
+
+    while let [rest @ .., last] = record {
+        if last.is_ascii_whitespace() {
+            record = rest;
+        } else {
+            break;
+        }
+    }
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::{
+        process,
+        receive,
+    };
+
+    fn import_str(read: impl BufRead) -> (Summary, Vec<serde_json::Value>) {
+        let mut receive = receive::build(receive::Config::default());
+        let process = process::build(process::Config::default());
+
+        let mut events = Vec::new();
+
+        let summary = import(
+            read,
+            |src| receive.decode(src),
+            |msg| {
+                process.with_clef(msg, |clef| {
+                    events.push(serde_json::to_value(clef)?);
+
+                    Ok(())
+                })
+            },
+        )
+        .expect("failed to import");
+
+        (summary, events)
+    }
+
+    #[test]
+    fn import_newline_delimited() {
+        let (summary, events) = import_str(
+            &b"{\"host\":\"a\",\"short_message\":\"1\"}\r\n\n{\"host\":\"b\",\"short_message\":\"2\"}"[..],
+        );
+
+        assert_eq!(
+            Summary {
+                processed: 2,
+                failed: 0,
+                skipped: 0,
+            },
+            summary
+        );
+        assert_eq!("1", events[0]["@m"]);
+        assert_eq!("2", events[1]["@m"]);
+    }
+
+    #[test]
+    fn import_null_delimited() {
+        let (summary, events) = import_str(
+            &b"{\"host\":\"a\",\"short_message\":\"1\"}\0{\"host\":\"b\",\"short_message\":\"2\"}\0"[..],
+        );
+
+        assert_eq!(2, summary.processed);
+        assert_eq!("2", events[1]["@m"]);
+    }
+
+    #[test]
+    fn import_counts_failures() {
+        let (summary, events) =
+            import_str(&b"not json\n{\"host\":\"a\",\"short_message\":\"1\"}\n"[..]);
+
+        assert_eq!(
+            Summary {
+                processed: 1,
+                failed: 1,
+                skipped: 0,
+            },
+            summary
+        );
+        assert_eq!(1, events.len());
+    }
+
+    #[test]
+    fn import_gzipped() {
+        let mut encoder = gzip::Encoder::new(Vec::new()).expect("failed to build encoder");
+        encoder
+            .write_all(b"{\"host\":\"a\",\"short_message\":\"1\"}\n")
+            .expect("failed to compress");
+        let compressed = encoder.finish().into_result().expect("failed to compress");
+
+        let read = decompress(Box::new(io::Cursor::new(compressed))).expect("failed to decompress");
+        let (summary, events) = import_str(read);
+
+        assert_eq!(1, summary.processed);
+        assert_eq!("1", events[0]["@m"]);
+    }
+}
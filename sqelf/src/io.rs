@@ -1,4 +1,7 @@
-use std::io;
+use std::{
+    io,
+    net::SocketAddr,
+};
 
 /**
 A reader that may be a contiguous slice of bytes.
@@ -8,13 +11,20 @@ pub trait MemRead {
 
     fn bytes(&self) -> Option<&[u8]>;
     fn into_reader(self) -> io::Result<Self::Reader>;
+
+    /**
+    The address of the client that sent the contents, if it's known.
+    */
+    fn peer(&self) -> Option<SocketAddr> {
+        None
+    }
 }
 
 impl<'a> MemRead for &'a [u8] {
     type Reader = io::Cursor<&'a [u8]>;
 
     fn bytes(&self) -> Option<&[u8]> {
-        Some(&self)
+        Some(self)
     }
 
     fn into_reader(self) -> io::Result<Self::Reader> {
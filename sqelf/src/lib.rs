@@ -5,9 +5,14 @@ The server is split into a few main components, in order of where they appear in
 
 - **Server**: An asynchronous UDP/TCP server built on `tokio` that handles the network.
 - **Receive**: Assembles complete GELF messages from their chunked, compressed, out-of-order
-blocks arriving from the network.
+  blocks arriving from the network.
 - **Process**: Deserializes GELF messages and maps them into CLEF. This is where any transformations
-over properties are made.
+  over properties are made.
+
+The `pipeline` module wires these components together, emitting CLEF to any number of
+`sink`s, like stdout, a file, or an HTTP endpoint.
+
+The `send` module is a client for sending GELF messages to a server, which is useful for testing.
 */
 
 #![recursion_limit = "256"]
@@ -25,11 +30,20 @@ pub mod diagnostics;
 #[macro_use]
 extern crate anyhow;
 
+pub mod capture;
 pub mod config;
+pub mod dead_letter;
+pub mod import;
 pub mod io;
+pub mod pipeline;
 pub mod process;
 pub mod receive;
+pub mod send;
 pub mod server;
+pub mod sink;
+
+#[cfg(test)]
+mod test_util;
 
 pub use self::{
     anyhow::Error,
@@ -3,48 +3,73 @@
 extern crate sqelf;
 
 use sqelf::{
+    capture,
     config::{
         self,
         Config,
     },
+    dead_letter,
     diagnostics::{
         self,
-        emit,
         emit_err,
+        emit_info,
     },
-    process,
-    receive,
+    import,
+    pipeline::Pipeline,
     server,
+    sink,
     Error,
 };
 
+use futures::executor::block_on;
+
 use std::{
     any::Any,
+    env,
     io::Read,
-    panic::catch_unwind,
+    panic::{
+        catch_unwind,
+        UnwindSafe,
+    },
     thread,
 };
 
-fn run() -> Result<(), Error> {
-    let config = Config::from_env()?;
+const USAGE: &str = "\
+Receive GELF messages and write them to stdout as CLEF.
+
+USAGE:
+    sqelf                         Run the server configured by the environment
+    sqelf serve [FLAGS]           Run the server
+    sqelf check-config [FLAGS]    Validate the configuration without running the server
+    sqelf import <FILE> [FLAGS]   Process GELF messages from a file, or `-` for stdin
+    sqelf replay <FILE> [FLAGS]   Process raw traffic from a capture file
+    sqelf resubmit <DIR> [FLAGS]  Process failed messages from a dead-letter directory
+    sqelf --version               Print build information
+    sqelf --help                  Print this message
+
+Flags override any `GELF_*` environment variables, which override any configuration file.
 
+Files to import contain newline or null delimited GELF messages and may be gzipped.
+Capture files are written by the server when `GELF_CAPTURE_PATH` is set.
+Dead-letter directories are written by the server when `GELF_DEAD_LETTER_PATH` is set.
+When importing, replaying, or resubmitting, `--fail-on-error` exits with a non-zero code if any messages fail.
+
+FLAGS:
+";
+
+fn run(config: Config) -> Result<(), Error> {
     // Initialize diagnostics
     diagnostics::init(config.diagnostics);
 
-    // The receiver for GELF messages
-    let receive = {
-        let mut receive = receive::build(config.receive);
-        move |src| receive.decode(src)
-    };
+    // The receiver and processor for converting GELF into CLEF on stdout
+    let pipeline = Pipeline::builder()
+        .receive(config.receive)
+        .process(config.process)
+        .sink(sink::Stdout::new())
+        .build();
 
-    // The processor for converting GELF into CLEF
-    let process = {
-        let process = process::build(config.process);
-        move |msg| process.read_as_clef(msg)
-    };
-
-    // The server that drives the receiver and processor
-    let mut server = server::build(config.server, receive, process)?;
+    // The server that drives the pipeline
+    let mut server = pipeline.serve(config.server)?;
 
     // If we should listen for stdin to terminate
     if config::is_seq_app() {
@@ -84,8 +109,33 @@ fn listen_for_stdin_closed(handle: server::Handle) {
 }
 
 fn main() {
-    let run_server: Result<(), Error> = catch_unwind(|| run())
-        .map_err(|panic| unwrap_panic(panic).into())
+    let mut args = env::args().skip(1);
+
+    match args.next().as_deref() {
+        // With no arguments, the server is configured by the environment
+        // This is how it runs as a Seq App or in a container
+        None => serve(Config::from_env),
+        Some("serve") => {
+            let args: Vec<_> = args.collect();
+            serve(move || Config::from_env_and_args(args))
+        }
+        Some("check-config") => check_config(args),
+        Some("import") => import(Input::Gelf, args),
+        Some("replay") => import(Input::Capture, args),
+        Some("resubmit") => import(Input::DeadLetter, args),
+        Some("--version") | Some("-V") => print_version(),
+        Some("--help") | Some("-h") | Some("help") => print_usage(),
+        Some(command) => {
+            eprintln!("error: unknown command `{}`\n", command);
+            eprint!("{}{}", USAGE, config::flags_usage());
+            std::process::exit(2);
+        }
+    }
+}
+
+fn serve(config: impl FnOnce() -> Result<Config, Error> + UnwindSafe) {
+    let run_server: Result<(), Error> = catch_unwind(|| run(config()?))
+        .map_err(unwrap_panic)
         .and_then(|inner| inner);
 
     if let Err(err) = run_server {
@@ -93,7 +143,174 @@ fn main() {
         std::process::exit(1);
     }
 
-    emit("GELF input stopped");
+    emit_info("GELF input stopped");
+}
+
+fn check_config(args: impl IntoIterator<Item = String>) {
+    let check = || -> Result<(), Error> {
+        let config = Config::from_env_and_args(args)?;
+        server::check(&config.server)?;
+
+        Ok(())
+    };
+
+    match check() {
+        Ok(()) => println!("The configuration is valid"),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/**
+The kind of file to process with `import`.
+*/
+#[derive(Clone, Copy)]
+enum Input {
+    /**
+    Newline or null delimited GELF messages.
+    */
+    Gelf,
+    /**
+    Raw traffic written by the server to a capture file.
+    */
+    Capture,
+    /**
+    Failed messages written by the server to a dead-letter directory.
+    */
+    DeadLetter,
+}
+
+fn import(input: Input, args: impl Iterator<Item = String>) {
+    let import = || -> Result<(), Error> {
+        let mut fail_on_error = false;
+        let mut args: Vec<_> = args
+            .filter(|arg| {
+                if arg == "--fail-on-error" {
+                    fail_on_error = true;
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect();
+
+        // The path is the first argument that isn't a flag or its value
+        // A path of `-` reads from stdin
+        let mut path = None;
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == "-" || !args[i].starts_with("--") {
+                path = Some(args.remove(i));
+                break;
+            }
+
+            i += if config::flag_takes_value(&args[i]) {
+                2
+            } else {
+                1
+            };
+        }
+
+        let path = path.ok_or_else(|| match input {
+            Input::Gelf => Error::msg("missing a file to import"),
+            Input::Capture => Error::msg("missing a capture file to replay"),
+            Input::DeadLetter => Error::msg("missing a dead-letter directory to resubmit"),
+        })?;
+
+        let config = Config::from_env_and_args(args)?;
+
+        diagnostics::init(config.diagnostics);
+
+        let pipeline = Pipeline::builder()
+            .receive(config.receive)
+            .process(config.process)
+            .sink(sink::Stdout::new())
+            .build();
+
+        let mut receive = pipeline.receiver();
+        let mut process = |msg| block_on(pipeline.process(msg));
+
+        let summary = match input {
+            Input::Gelf => {
+                import::import(import::open(&path)?, |src| receive.decode(src), process)?
+            }
+            Input::Capture => {
+                capture::replay(capture::open(&path)?, |src| receive.decode(src), process)?
+            }
+            Input::DeadLetter => {
+                let mut summary = import::Summary::default();
+
+                // Files are resubmitted oldest first
+                for path in dead_letter::files(&path)? {
+                    let replayed = dead_letter::resubmit(
+                        capture::open(&path)?,
+                        |src| receive.decode(src),
+                        &mut process,
+                    )?;
+
+                    summary.processed += replayed.processed;
+                    summary.failed += replayed.failed;
+                    summary.skipped += replayed.skipped;
+                }
+
+                summary
+            }
+        };
+
+        block_on(pipeline.flush())?;
+        diagnostics::stop()?;
+
+        let (done, failed) = match input {
+            Input::Gelf => ("Imported", "import"),
+            Input::Capture => ("Replayed", "replay"),
+            Input::DeadLetter => ("Resubmitted", "resubmit"),
+        };
+
+        if summary.skipped > 0 {
+            eprintln!(
+                "{} {} events; {} failed; {} skipped",
+                done, summary.processed, summary.failed, summary.skipped
+            );
+        } else {
+            eprintln!(
+                "{} {} events; {} failed",
+                done, summary.processed, summary.failed
+            );
+        }
+
+        if fail_on_error && summary.failed > 0 {
+            return Err(Error::msg(format!(
+                "{} events failed to {}",
+                summary.failed, failed
+            )));
+        }
+
+        Ok(())
+    };
+
+    if let Err(err) = import() {
+        eprintln!("error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+fn print_version() {
+    println!(
+        "sqelf {}",
+        option_env!("SQELF_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"))
+    );
+    println!("commit: {}", env!("SQELF_COMMIT"));
+    println!("target: {}-{}", env::consts::ARCH, env::consts::OS);
+    println!(
+        "features: {}",
+        if cfg!(feature = "tls") { "tls" } else { "none" }
+    );
+}
+
+fn print_usage() {
+    print!("{}{}", USAGE, config::flags_usage());
 }
 
 fn unwrap_panic(panic: Box<dyn Any + Send + 'static>) -> Error {
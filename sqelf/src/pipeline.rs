@@ -0,0 +1,268 @@
+/*!
+Wire a receiver, processor, and sinks together.
+
+A pipeline is the same composition the `sqelf` binary uses, with the
+CLEF output sent to any number of sinks instead of just stdout.
+*/
+
+use std::sync::Arc;
+
+use futures::Future;
+
+use crate::{
+    process::{
+        self,
+        Process,
+    },
+    receive::{
+        self,
+        Gelf,
+        Message,
+    },
+    server::{
+        self,
+        Server,
+    },
+    sink::{
+        Sink,
+        Stdout,
+    },
+    Error,
+};
+
+/**
+A receiver and processor that emit CLEF events to a set of sinks.
+*/
+#[derive(Clone)]
+pub struct Pipeline {
+    receive: receive::Config,
+    process: Process,
+    sinks: Arc<[Box<dyn Sink>]>,
+}
+
+/**
+A builder for a `Pipeline`.
+*/
+#[derive(Default)]
+pub struct Builder {
+    receive: receive::Config,
+    process: process::Config,
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Builder {
+    /**
+    Set the configuration for assembling GELF messages.
+    */
+    pub fn receive(mut self, config: receive::Config) -> Self {
+        self.receive = config;
+        self
+    }
+
+    /**
+    Set the configuration for converting GELF into CLEF.
+    */
+    pub fn process(mut self, config: process::Config) -> Self {
+        self.process = config;
+        self
+    }
+
+    /**
+    Add a sink to emit events to.
+
+    Events are emitted to each sink in the order they were added.
+    */
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /**
+    Build the pipeline.
+
+    If no sinks were added then events are written to stdout.
+    */
+    pub fn build(mut self) -> Pipeline {
+        if self.sinks.is_empty() {
+            self.sinks.push(Box::new(Stdout::new()));
+        }
+
+        Pipeline {
+            receive: self.receive,
+            process: process::build(self.process),
+            sinks: self.sinks.into(),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /**
+    Build a receiver for assembling GELF messages from raw payloads.
+
+    Each receiver tracks its own partially received chunked messages.
+    */
+    pub fn receiver(&self) -> Gelf {
+        receive::build(self.receive.clone())
+    }
+
+    /**
+    Convert a GELF message into CLEF and emit it to each sink.
+
+    The returned future fails if the message can't be converted, or if any sink fails.
+    A failing sink stops the event from being emitted to the sinks after it.
+    */
+    pub fn process(
+        &self,
+        msg: Message,
+    ) -> impl Future<Output = Result<(), Error>> + Send + 'static {
+        // NOTE: The event is converted before it's emitted so
+        // the future doesn't borrow the received message
+        let clef = self.process.with_clef(msg, |clef| Ok(clef.into_owned()));
+        let sinks = self.sinks.clone();
+
+        async move {
+            let clef = clef?;
+
+            for sink in sinks.iter() {
+                sink.emit(&clef).await?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /**
+    Flush any events buffered by each sink.
+    */
+    pub fn flush(&self) -> impl Future<Output = Result<(), Error>> + Send + 'static {
+        let sinks = self.sinks.clone();
+
+        async move {
+            for sink in sinks.iter() {
+                sink.flush().await?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /**
+    Build a server that receives GELF messages from the network and emits them to each sink.
+
+    Each sink is flushed once the server stops.
+    */
+    pub fn serve(self, config: server::Config) -> Result<Server, Error> {
+        let receive = {
+            let mut receive = self.receiver();
+            move |src| receive.decode(src)
+        };
+
+        let flush = self.flush();
+        let process = move |msg| self.process(msg);
+
+        Ok(server::build_async(config, receive, process)?.on_stop(move || flush))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use bytes::Bytes;
+    use futures::future::{
+        self,
+        BoxFuture,
+        FutureExt,
+    };
+
+    use crate::{
+        process::clef,
+        sink,
+    };
+
+    #[test]
+    fn emits_to_each_sink() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let collect = |name: &'static str| {
+            let received = received.clone();
+            sink::from_fn(move |msg| {
+                let message = msg.message.as_ref().map(|m| m.as_ref().to_owned());
+                received.lock().unwrap().push((name, message));
+
+                Ok(())
+            })
+        };
+
+        let pipeline = Pipeline::builder()
+            .sink(collect("a"))
+            .sink(collect("b"))
+            .build();
+
+        let msg = pipeline
+            .receiver()
+            .decode(Bytes::from_static(
+                br#"{"host":"foo","short_message":"bar"}"#,
+            ))
+            .expect("failed to decode")
+            .expect("missing message");
+
+        futures::executor::block_on(pipeline.process(msg)).expect("failed to process");
+
+        assert_eq!(
+            vec![("a", Some("bar".to_owned())), ("b", Some("bar".to_owned()))],
+            *received.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn failing_sink_fails_processing() {
+        let pipeline = Pipeline::builder()
+            .sink(sink::from_fn(|_| Err(anyhow!("failed"))))
+            .build();
+
+        let msg = pipeline
+            .receiver()
+            .decode(Bytes::from_static(
+                br#"{"host":"foo","short_message":"bar"}"#,
+            ))
+            .expect("failed to decode")
+            .expect("missing message");
+
+        assert!(futures::executor::block_on(pipeline.process(msg)).is_err());
+    }
+
+    #[test]
+    fn flush_flushes_each_sink() {
+        struct Flushed(Arc<Mutex<usize>>);
+
+        impl Sink for Flushed {
+            fn emit<'a>(&'a self, _: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+                future::ready(Ok(())).boxed()
+            }
+
+            fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+                *self.0.lock().unwrap() += 1;
+
+                future::ready(Ok(())).boxed()
+            }
+        }
+
+        let flushed = Arc::new(Mutex::new(0));
+
+        let pipeline = Pipeline::builder()
+            .sink(Flushed(flushed.clone()))
+            .sink(Flushed(flushed.clone()))
+            .build();
+
+        futures::executor::block_on(pipeline.flush()).expect("failed to flush");
+
+        assert_eq!(2, *flushed.lock().unwrap());
+    }
+}
@@ -76,6 +76,9 @@ impl Timestamp {
             return Some(Timestamp(SystemTime::UNIX_EPOCH));
         }
 
+        // Anything more precise than nanoseconds is rounded
+        let ts = ts.round_dp(9);
+
         let secs = ts.trunc().to_u64()?;
         let mut fract = ts.fract();
         fract.set_scale(0).ok()?;
@@ -139,8 +142,54 @@ impl<'a> Message<'a> {
         }
     }
 
+    /**
+    An error event for a payload that couldn't be parsed.
+    */
+    pub(super) fn invalid(
+        message_template: &'a str,
+        error: String,
+        peer: Option<String>,
+        payload: String,
+    ) -> Self {
+        let mut additional = HashMap::new();
+
+        additional.insert(Str::Borrowed("error"), Value::String(error));
+        additional.insert(Str::Borrowed("payload"), Value::String(payload));
+
+        if let Some(peer) = peer {
+            additional.insert(Str::Borrowed("peer"), Value::String(peer));
+        }
+
+        Message {
+            timestamp: Some(Timestamp::now()),
+            level: Some(Str::Borrowed("Error")),
+            message: None,
+            message_template: Some(Str::Borrowed(message_template)),
+            exception: None,
+            additional,
+        }
+    }
+
+    /**
+    Copy any borrowed data so the message can outlive the payload it was parsed from.
+    */
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            timestamp: self.timestamp,
+            level: self.level.map(Str::into_owned),
+            message: self.message.map(Str::into_owned),
+            message_template: self.message_template.map(Str::into_owned),
+            exception: self.exception.map(Str::into_owned),
+            additional: self
+                .additional
+                .into_iter()
+                .map(|(k, v)| (k.into_owned(), v))
+                .collect(),
+        }
+    }
+
     pub(super) fn maybe_from_json(json: &'a str) -> Option<Self> {
-        if json.chars().next() == Some('{') {
+        if json.starts_with('{') {
             serde_json::from_str(json).ok()
         } else {
             None
@@ -162,6 +211,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn timestamp_from_decimal_rounds_to_nanos() {
+        let ts = Timestamp::from_decimal(Decimal::from_i128_with_scale(16662235671234567891, 10))
+            .unwrap();
+
+        assert_eq!(
+            "\"2022-10-19T23:52:47.123456789Z\"",
+            serde_json::to_string(&ts).unwrap()
+        );
+    }
+
     #[test]
     fn timestamp_from_decimal_overflow() {
         // Ensure we don't panic on timestamps that are out of range
@@ -1,6 +1,23 @@
+use std::{
+    convert::TryFrom,
+    error,
+    fmt,
+    str::FromStr,
+};
+
+use chrono::DateTime;
 use rust_decimal::Decimal;
+use serde::de::{
+    Deserialize,
+    Deserializer,
+};
 use serde_json::Value;
 
+use super::{
+    clef::Timestamp,
+    level::syslog_level,
+};
+
 #[derive(Debug, Deserialize)]
 pub(super) struct Message<TString, TMessage = TString> {
     // GELF built-ins
@@ -8,15 +25,350 @@ pub(super) struct Message<TString, TMessage = TString> {
     pub(super) host: Option<TString>,
     pub(super) short_message: TMessage,
     pub(super) full_message: Option<TMessage>,
-    pub(super) timestamp: Option<Decimal>,
-    pub(super) level: Option<u8>,
+    #[serde(default, deserialize_with = "lenient_timestamp")]
+    pub(super) timestamp: Option<Lenient<Decimal>>,
+    #[serde(default, deserialize_with = "lenient_level")]
+    pub(super) level: Option<Lenient<u8>>,
 
     // Deprecated built-ins, still may be present
     pub(super) facility: Option<TMessage>,
-    pub(super) line: Option<u32>,
+    #[serde(default, deserialize_with = "lenient_line")]
+    pub(super) line: Option<Lenient<u32>>,
     pub(super) file: Option<TMessage>,
 
     // Everything else
     #[serde(flatten)]
     pub(super) additional: Option<Value>,
 }
+
+/**
+A built-in field that may have been sent with an unexpected type.
+
+Some clients send numbers as strings, or levels by name. Values that can
+be coerced into the expected type are, and anything else is kept as-is
+so it isn't lost.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Lenient<T> {
+    Valid(T),
+    Invalid(Value),
+}
+
+impl<T> Lenient<T> {
+    pub(super) fn valid(&self) -> Option<&T> {
+        match self {
+            Lenient::Valid(valid) => Some(valid),
+            Lenient::Invalid(_) => None,
+        }
+    }
+
+    pub(super) fn invalid(&self) -> Option<&Value> {
+        match self {
+            Lenient::Valid(_) => None,
+            Lenient::Invalid(invalid) => Some(invalid),
+        }
+    }
+}
+
+fn lenient<'de, D, T>(
+    deserializer: D,
+    coerce: impl FnOnce(&Value) -> Option<T>,
+) -> Result<Option<Lenient<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        Option::<Value>::deserialize(deserializer)?.map(|value| match coerce(&value) {
+            Some(valid) => Lenient::Valid(valid),
+            None => Lenient::Invalid(value),
+        }),
+    )
+}
+
+/**
+A level is a syslog severity, either as a number or its name.
+*/
+fn lenient_level<'de, D>(deserializer: D) -> Result<Option<Lenient<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    lenient(deserializer, |value| match value {
+        Value::Number(level) => level.as_u64().and_then(|level| u8::try_from(level).ok()),
+        Value::String(level) => {
+            let level = level.trim();
+
+            level.parse().ok().or_else(|| syslog_level(level))
+        }
+        _ => None,
+    })
+}
+
+/**
+A timestamp is seconds since the epoch, either as a number or a string,
+or an RFC 3339 formatted string.
+
+Timestamps before the epoch, or too far after it to represent, are invalid.
+*/
+fn lenient_timestamp<'de, D>(deserializer: D) -> Result<Option<Lenient<Decimal>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    lenient(deserializer, |value| {
+        let ts = match value {
+            Value::Number(_) => <Decimal as Deserialize>::deserialize(value).ok(),
+            Value::String(ts) => {
+                let ts = ts.trim();
+
+                Decimal::from_str(ts).ok().or_else(|| {
+                    let ts = DateTime::parse_from_rfc3339(ts).ok()?;
+
+                    Decimal::try_from_i128_with_scale(
+                        ts.timestamp() as i128 * 1_000_000_000
+                            + ts.timestamp_subsec_nanos() as i128,
+                        9,
+                    )
+                    .ok()
+                })
+            }
+            _ => None,
+        }?;
+
+        // NOTE: The timestamp is checked here so one that can't be
+        // converted is reported as invalid instead of being replaced
+        if ts.is_sign_negative() || Timestamp::from_decimal(ts).is_none() {
+            return None;
+        }
+
+        Some(ts)
+    })
+}
+
+/**
+A line is a number, either as a number or a string.
+*/
+fn lenient_line<'de, D>(deserializer: D) -> Result<Option<Lenient<u32>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    lenient(deserializer, |value| match value {
+        Value::Number(line) => line.as_u64().and_then(|line| u32::try_from(line).ok()),
+        Value::String(line) => line.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/**
+A way a message doesn't conform to the GELF 1.1 specification.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Violation {
+    MissingVersion,
+    MissingHost,
+    UnprefixedField(String),
+    ReservedId,
+    InvalidValue(String),
+    InvalidFieldName(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::MissingVersion => {
+                f.write_str("the GELF message is missing the required `version` field")
+            }
+            Violation::MissingHost => {
+                f.write_str("the GELF message is missing the required `host` field")
+            }
+            Violation::UnprefixedField(name) => write!(
+                f,
+                "the additional field `{}` isn't prefixed with an underscore",
+                name
+            ),
+            Violation::ReservedId => f.write_str("the additional field `_id` is reserved"),
+            Violation::InvalidValue(name) => write!(
+                f,
+                "the additional field `{}` isn't a string or a number",
+                name
+            ),
+            Violation::InvalidFieldName(name) => write!(
+                f,
+                "the field name `{}` may only contain letters, numbers, underscores, dashes, and dots",
+                name
+            ),
+        }
+    }
+}
+
+impl error::Error for Violation {}
+
+impl<TString, TMessage> Message<TString, TMessage> {
+    /**
+    Check that the message conforms to the GELF 1.1 specification.
+
+    Only the first violation is returned.
+    */
+    pub(super) fn check_spec(&self) -> Result<(), Violation> {
+        if self.version.is_none() {
+            return Err(Violation::MissingVersion);
+        }
+
+        if self.host.is_none() {
+            return Err(Violation::MissingHost);
+        }
+
+        if let Some(Value::Object(ref additional)) = self.additional {
+            for (name, value) in additional {
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+                {
+                    return Err(Violation::InvalidFieldName(name.clone()));
+                }
+
+                if !name.starts_with('_') {
+                    return Err(Violation::UnprefixedField(name.clone()));
+                }
+
+                if name == "_id" {
+                    return Err(Violation::ReservedId);
+                }
+
+                if !(value.is_string() || value.is_number()) {
+                    return Err(Violation::InvalidValue(name.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn parse(gelf: Value) -> Message<String> {
+        serde_json::from_value(gelf).expect("failed to parse GELF")
+    }
+
+    #[test]
+    fn lenient_levels() {
+        for (level, expected) in [
+            (json!(3), Lenient::Valid(3)),
+            (json!("3"), Lenient::Valid(3)),
+            (json!("INFO"), Lenient::Valid(6)),
+            (json!("Warning"), Lenient::Valid(4)),
+            (json!("loud"), Lenient::Invalid(json!("loud"))),
+            (json!(-1), Lenient::Invalid(json!(-1))),
+        ] {
+            let gelf = parse(json!({ "short_message": "", "level": level }));
+
+            assert_eq!(Some(expected), gelf.level);
+        }
+    }
+
+    #[test]
+    fn lenient_timestamps() {
+        for (ts, expected) in [
+            (
+                json!(1385053862.3072),
+                Lenient::Valid(Decimal::new(13850538623072, 4)),
+            ),
+            (
+                json!("1385053862.3072"),
+                Lenient::Valid(Decimal::new(13850538623072, 4)),
+            ),
+            (
+                json!("2013-11-21T17:11:02.3072Z"),
+                Lenient::Valid(Decimal::new(1385053862307200000, 9)),
+            ),
+            (
+                json!("2013-11-21T19:11:02.3072+02:00"),
+                Lenient::Valid(Decimal::new(1385053862307200000, 9)),
+            ),
+            (json!("yesterday"), Lenient::Invalid(json!("yesterday"))),
+            (json!(true), Lenient::Invalid(json!(true))),
+            (
+                json!("1969-12-31T23:59:59Z"),
+                Lenient::Invalid(json!("1969-12-31T23:59:59Z")),
+            ),
+            (json!(-1), Lenient::Invalid(json!(-1))),
+            (
+                json!(u64::MAX.to_string()),
+                Lenient::Invalid(json!(u64::MAX.to_string())),
+            ),
+        ] {
+            let gelf = parse(json!({ "short_message": "", "timestamp": ts }));
+
+            assert_eq!(Some(expected), gelf.timestamp);
+        }
+    }
+
+    #[test]
+    fn lenient_lines() {
+        for (line, expected) in [
+            (json!(42), Lenient::Valid(42)),
+            (json!(" 42 "), Lenient::Valid(42)),
+            (json!("forty-two"), Lenient::Invalid(json!("forty-two"))),
+        ] {
+            let gelf = parse(json!({ "short_message": "", "line": line }));
+
+            assert_eq!(Some(expected), gelf.line);
+        }
+    }
+
+    #[test]
+    fn check_spec() {
+        for (gelf, expected) in [
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_a.b-c": 1, "_d": "e" }),
+                Ok(()),
+            ),
+            (
+                json!({ "host": "a", "short_message": "" }),
+                Err(Violation::MissingVersion),
+            ),
+            (
+                json!({ "version": "1.1", "short_message": "" }),
+                Err(Violation::MissingHost),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "a": 1 }),
+                Err(Violation::UnprefixedField("a".to_owned())),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_id": 1 }),
+                Err(Violation::ReservedId),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_a": { "b": 1 } }),
+                Err(Violation::InvalidValue("_a".to_owned())),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_a": null }),
+                Err(Violation::InvalidValue("_a".to_owned())),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_a b": 1 }),
+                Err(Violation::InvalidFieldName("_a b".to_owned())),
+            ),
+            (
+                json!({ "version": "1.1", "host": "a", "short_message": "", "_é": 1 }),
+                Err(Violation::InvalidFieldName("_é".to_owned())),
+            ),
+        ] {
+            assert_eq!(expected, parse(gelf).check_spec());
+        }
+    }
+
+    #[test]
+    fn missing_and_null_fields_are_none() {
+        let gelf = parse(json!({ "short_message": "", "level": null }));
+
+        assert_eq!(None, gelf.level);
+        assert_eq!(None, gelf.timestamp);
+        assert_eq!(None, gelf.line);
+    }
+}
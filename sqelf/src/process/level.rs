@@ -0,0 +1,239 @@
+/*!
+Names for GELF levels.
+
+GELF levels are numeric syslog severities. When they're converted into CLEF
+they're given a name from a table of levels. The table is written as a preset,
+like `seq`, followed by any `level=Name` pairs to override, like `seq,7=Verbose`.
+*/
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    str::FromStr,
+};
+
+use crate::Error;
+
+/**
+The standard syslog severity names.
+*/
+const SYSLOG: &[(u8, &str)] = &[
+    (0, "emerg"),
+    (1, "alert"),
+    (2, "crit"),
+    (3, "err"),
+    (4, "warning"),
+    (5, "notice"),
+    (6, "info"),
+    (7, "debug"),
+];
+
+/**
+Seq's native level names.
+*/
+const SEQ: &[(u8, &str)] = &[
+    (0, "Fatal"),
+    (1, "Fatal"),
+    (2, "Fatal"),
+    (3, "Error"),
+    (4, "Warning"),
+    (5, "Information"),
+    (6, "Information"),
+    (7, "Debug"),
+    (8, "Verbose"),
+];
+
+/**
+A table of names for GELF levels.
+
+A level that isn't in the table is given the name of the
+nearest level below it, so levels above `7` are named like `7`
+unless the table says otherwise.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Levels {
+    names: BTreeMap<u8, String>,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels::preset(SYSLOG)
+    }
+}
+
+impl Levels {
+    /**
+    The standard syslog names, like `emerg`, `err`, and `info`.
+    */
+    pub fn syslog() -> Self {
+        Levels::preset(SYSLOG)
+    }
+
+    /**
+    Seq's native names, like `Fatal`, `Error`, and `Information`.
+
+    Levels above `7` are `Verbose`.
+    */
+    pub fn seq() -> Self {
+        Levels::preset(SEQ)
+    }
+
+    fn preset(names: &[(u8, &str)]) -> Self {
+        Levels {
+            names: names
+                .iter()
+                .map(|(level, name)| (*level, (*name).to_owned()))
+                .collect(),
+        }
+    }
+
+    /**
+    Get the name of a GELF level.
+    */
+    pub fn name(&self, level: u8) -> &str {
+        self.names
+            .range(..=level)
+            .next_back()
+            .map(|(_, name)| &**name)
+            // NOTE: The table always names level `0`
+            .unwrap_or("")
+    }
+
+    /**
+    Get the name of a level given as a string, either its number or a syslog severity name.
+
+    If the level isn't recognized then `None` is returned.
+    */
+    pub fn normalize(&self, level: &str) -> Option<&str> {
+        let level = level.trim();
+
+        level
+            .parse()
+            .ok()
+            .or_else(|| syslog_level(level))
+            .map(|level| self.name(level))
+    }
+}
+
+impl FromStr for Levels {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = Levels::default();
+
+        for (i, part) in s.split(',').map(str::trim).enumerate() {
+            match part.split_once('=') {
+                Some((level, name)) => {
+                    let level = level
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("expected a level between 0 and 255 in `{}`", part))?;
+
+                    let name = name.trim();
+                    if name.is_empty() {
+                        bail!("expected a name for the level in `{}`", part);
+                    }
+
+                    levels.names.insert(level, name.to_owned());
+                }
+                // A preset can only be the first part
+                None if i == 0 => {
+                    levels = match &*part.to_ascii_lowercase() {
+                        "syslog" => Levels::syslog(),
+                        "seq" => Levels::seq(),
+                        _ => bail!("expected a preset of `syslog` or `seq`, or `level=Name` pairs"),
+                    };
+                }
+                None => bail!("expected a `level=Name` pair but got `{}`", part),
+            }
+        }
+
+        Ok(levels)
+    }
+}
+
+impl TryFrom<String> for Levels {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+/**
+Get the numeric value of a syslog severity name.
+*/
+pub(super) fn syslog_level(name: &str) -> Option<u8> {
+    match &*name.to_ascii_lowercase() {
+        "emerg" | "emergency" | "panic" => Some(0),
+        "alert" => Some(1),
+        "crit" | "critical" => Some(2),
+        "err" | "error" => Some(3),
+        "warn" | "warning" => Some(4),
+        "notice" => Some(5),
+        "info" | "informational" | "information" => Some(6),
+        "debug" => Some(7),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syslog_names() {
+        let levels = Levels::default();
+
+        assert_eq!("emerg", levels.name(0));
+        assert_eq!("info", levels.name(6));
+        assert_eq!("debug", levels.name(7));
+        assert_eq!("debug", levels.name(100));
+    }
+
+    #[test]
+    fn seq_names() {
+        let levels = Levels::seq();
+
+        assert_eq!("Fatal", levels.name(2));
+        assert_eq!("Error", levels.name(3));
+        assert_eq!("Information", levels.name(5));
+        assert_eq!("Debug", levels.name(7));
+        assert_eq!("Verbose", levels.name(8));
+        assert_eq!("Verbose", levels.name(100));
+    }
+
+    #[test]
+    fn parse_levels() {
+        assert_eq!(Levels::seq(), "seq".parse::<Levels>().unwrap());
+        assert_eq!(Levels::syslog(), "SYSLOG".parse::<Levels>().unwrap());
+
+        let levels: Levels = "seq, 7=Verbose, 10=Trace".parse().unwrap();
+
+        assert_eq!("Verbose", levels.name(7));
+        assert_eq!("Verbose", levels.name(9));
+        assert_eq!("Trace", levels.name(10));
+
+        // Pairs without a preset override the syslog names
+        let levels: Levels = "3=Error".parse().unwrap();
+
+        assert_eq!("Error", levels.name(3));
+        assert_eq!("warning", levels.name(4));
+
+        assert!("loud".parse::<Levels>().is_err());
+        assert!("seq,syslog".parse::<Levels>().is_err());
+        assert!("256=Trace".parse::<Levels>().is_err());
+        assert!("7=".parse::<Levels>().is_err());
+    }
+
+    #[test]
+    fn normalize_levels() {
+        let levels = Levels::seq();
+
+        assert_eq!(Some("Warning"), levels.normalize("warn"));
+        assert_eq!(Some("Error"), levels.normalize("3"));
+        assert_eq!(Some("Information"), levels.normalize("INFORMATION"));
+        assert_eq!(None, levels.normalize("Fatal"));
+    }
+}
@@ -1,13 +1,21 @@
 pub mod clef;
 mod gelf;
+pub mod level;
 pub mod str;
 
 use serde_json::Value;
 
-use self::str::{
-    CachedString,
-    Inlinable,
-    Str,
+use self::{
+    gelf::{
+        Lenient,
+        Violation,
+    },
+    level::Levels,
+    str::{
+        CachedString,
+        Inlinable,
+        Str,
+    },
 };
 
 use crate::{
@@ -21,27 +29,65 @@ use std::{
 };
 
 metrics! {
-    msg
+    msg,
+    msg_invalid,
+    strict_missing_version,
+    strict_missing_host,
+    strict_unprefixed_field,
+    strict_reserved_id,
+    strict_invalid_value,
+    strict_invalid_field_name
 }
 
+/**
+The maximum number of bytes of an invalid payload to include in an error event.
+*/
+const MAX_INVALID_PAYLOAD_BYTES: usize = 1024;
+
+/**
+The template of events for payloads that couldn't be parsed.
+*/
+const INVALID_MESSAGE_TEMPLATE: &str = "Failed to parse a GELF payload: {error}";
+
 /**
 Configuration for CELF formatting.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /**
     Whether or not to buffer and include the raw GELF payload
     in the event message.
     */
     pub include_raw_payload: bool,
-}
+    /**
+    Whether to emit payloads that can't be parsed as CLEF events with the `Error` level.
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            include_raw_payload: false,
-        }
-    }
+    If this value is `false` then payloads that can't be parsed are dropped.
+    */
+    pub emit_invalid_as_errors: bool,
+    /**
+    Whether to reject messages that don't conform to the GELF 1.1 specification.
+
+    A message is rejected if it's missing its `version` or `host`, or if it has
+    additional fields that aren't prefixed with `_`, use the reserved `_id` name,
+    have invalid names, or have values that aren't strings or numbers.
+    */
+    pub strict: bool,
+    /**
+    The names to give GELF levels in CLEF.
+
+    This is written as a preset, either `syslog` or `seq`, followed by any
+    `level=Name` pairs to override, like `seq,7=Verbose`.
+    */
+    pub levels: Levels,
+    /**
+    Whether to map string levels in embedded JSON through the same names as GELF levels.
+
+    Levels are recognized if they're a number or a syslog severity name, like `warn`.
+    If this value is `false`, or the level isn't recognized, then it's used as-is.
+    */
+    pub normalize_embedded_levels: bool,
 }
 
 /**
@@ -57,44 +103,39 @@ Process a raw message
 #[derive(Debug, Clone)]
 pub struct Process {
     include_raw_payload: bool,
+    emit_invalid_as_errors: bool,
+    strict: bool,
+    levels: Levels,
+    normalize_embedded_levels: bool,
 }
 
 impl Process {
     pub fn new(config: Config) -> Self {
         Process {
             include_raw_payload: config.include_raw_payload,
+            emit_invalid_as_errors: config.emit_invalid_as_errors,
+            strict: config.strict,
+            levels: config.levels,
+            normalize_embedded_levels: config.normalize_embedded_levels,
         }
     }
 
-    pub fn with_clef(
+    pub fn with_clef<T>(
         &self,
         msg: impl MemRead,
-        with: impl FnOnce(clef::Message) -> Result<(), Error>,
-    ) -> Result<(), Error> {
+        with: impl FnOnce(clef::Message) -> Result<T, Error>,
+    ) -> Result<T, Error> {
         increment!(process.msg);
 
-        if let Some(bytes) = msg.bytes() {
-            let value = if self.include_raw_payload {
-                let mut value: gelf::Message<Str> = serde_json::from_slice(bytes)
-                    .map_err(Error::from)
-                    .map_err(|e| {
-                        e.context(format!(
-                            "could not parse GELF from: {:?}",
-                            String::from_utf8_lossy(bytes)
-                        ))
-                    })?;
-
-                value.add(
-                    "raw_payload",
-                    Value::String(String::from_utf8_lossy(bytes).into_owned()),
-                );
-
-                value
-            } else {
-                serde_json::from_slice(bytes)?
-            };
+        if self.emit_invalid_as_errors {
+            return self.with_clef_or_error(msg, with);
+        }
 
-            with(value.to_clef())
+        if let Some(bytes) = msg.bytes() {
+            with(
+                self.parse_bytes(bytes)?
+                    .to_clef(&self.levels, self.normalize_embedded_levels),
+            )
         } else {
             let value = if self.include_raw_payload {
                 let mut payload = String::new();
@@ -107,14 +148,126 @@ impl Process {
                             e.context(format!("could not parse GELF from: {:?}", payload))
                         })?;
 
+                self.check_strict(&value)?;
                 value.add("raw_payload", Value::String(payload));
 
                 value
             } else {
-                serde_json::from_reader(msg.into_reader()?)?
+                let value: gelf::Message<Inlinable<CachedString>, String> =
+                    serde_json::from_reader(msg.into_reader()?)?;
+
+                self.check_strict(&value)?;
+
+                value
             };
 
-            with(value.to_clef())
+            with(value.to_clef(&self.levels, self.normalize_embedded_levels))
+        }
+    }
+
+    /**
+    Parse a message, converting it into an error event if it's invalid.
+
+    The payload is buffered so it can be included in the error event.
+    */
+    fn with_clef_or_error<T>(
+        &self,
+        msg: impl MemRead,
+        with: impl FnOnce(clef::Message) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let peer = msg.peer().map(|peer| peer.to_string());
+
+        let mut buffered = Vec::new();
+        let payload = if let Some(bytes) = msg.bytes() {
+            Ok(bytes)
+        } else {
+            msg.into_reader()
+                .and_then(|mut reader| reader.read_to_end(&mut buffered))
+                .map(|_| &*buffered)
+        };
+
+        let (parsed, payload) = match payload {
+            Ok(payload) => (self.parse_bytes(payload), payload),
+            // NOTE: If the payload couldn't be read then include what was
+            Err(err) => (Err(Error::from(err)), &*buffered),
+        };
+
+        match parsed {
+            Ok(value) => with(value.to_clef(&self.levels, self.normalize_embedded_levels)),
+            Err(err) => {
+                increment!(process.msg_invalid);
+
+                let truncated = &payload[..payload.len().min(MAX_INVALID_PAYLOAD_BYTES)];
+                let mut preview = String::from_utf8_lossy(truncated).into_owned();
+
+                if truncated.len() < payload.len() {
+                    preview.push('…');
+                }
+
+                with(clef::Message::invalid(
+                    INVALID_MESSAGE_TEMPLATE,
+                    err.root_cause().to_string(),
+                    peer,
+                    preview,
+                ))
+            }
+        }
+    }
+
+    fn parse_bytes<'a>(&self, bytes: &'a [u8]) -> Result<gelf::Message<Str<'a>>, Error> {
+        if self.include_raw_payload {
+            let mut value: gelf::Message<Str> = serde_json::from_slice(bytes)
+                .map_err(Error::from)
+                .map_err(|e| {
+                    e.context(format!(
+                        "could not parse GELF from: {:?}",
+                        String::from_utf8_lossy(bytes)
+                    ))
+                })?;
+
+            self.check_strict(&value)?;
+            value.add(
+                "raw_payload",
+                Value::String(String::from_utf8_lossy(bytes).into_owned()),
+            );
+
+            Ok(value)
+        } else {
+            let value = serde_json::from_slice(bytes)?;
+
+            self.check_strict(&value)?;
+
+            Ok(value)
+        }
+    }
+
+    /**
+    Check that a message conforms to the GELF 1.1 specification if strict validation is enabled.
+    */
+    fn check_strict<TString, TMessage>(
+        &self,
+        value: &gelf::Message<TString, TMessage>,
+    ) -> Result<(), Error> {
+        if !self.strict {
+            return Ok(());
+        }
+
+        match value.check_spec() {
+            Ok(()) => Ok(()),
+            Err(violation) => {
+                match violation {
+                    Violation::MissingVersion => increment!(process.strict_missing_version),
+                    Violation::MissingHost => increment!(process.strict_missing_host),
+                    Violation::UnprefixedField(_) => increment!(process.strict_unprefixed_field),
+                    Violation::ReservedId => increment!(process.strict_reserved_id),
+                    Violation::InvalidValue(_) => increment!(process.strict_invalid_value),
+                    Violation::InvalidFieldName(_) => {
+                        increment!(process.strict_invalid_field_name)
+                    }
+                }
+
+                Err(violation.into())
+            }
         }
     }
 
@@ -150,8 +303,15 @@ where
 
     If fields conflict, then the lower-priority field is included with a
     double-underscore-prefixed name, e.g.: "__host".
+
+    Levels are named using the given table of levels. Embedded levels are
+    only renamed with it if `normalize_embedded_levels` is set.
     */
-    fn to_clef(&self) -> clef::Message {
+    fn to_clef<'a>(
+        &'a self,
+        levels: &'a Levels,
+        normalize_embedded_levels: bool,
+    ) -> clef::Message<'a> {
         #![deny(unused_variables)]
 
         let gelf::Message {
@@ -170,23 +330,29 @@ where
         let mut clef = clef::Message::maybe_from_json(short_message.as_ref())
             .unwrap_or_else(|| clef::Message::from_message(short_message.as_ref()));
 
-        // Set the log level; these are the standard Syslog levels, giving priority to the embedded CLEF level
+        // Set the log level, giving priority to the embedded CLEF level
         // Before using the level on the GELF payload, we'll try find a suitable property in the JSON itself
         if clef.level.is_none() {
             clef.level = Some(
                 Self::find_first(&clef.additional, &["level", "lvl", "LVL"])
                     .and_then(Str::try_from_value)
-                    .map(|s| s.into_owned())
-                    .unwrap_or_else(|| match level.unwrap_or(6) {
-                        0 => Str::Borrowed("emerg"),
-                        1 => Str::Borrowed("alert"),
-                        2 => Str::Borrowed("crit"),
-                        3 => Str::Borrowed("err"),
-                        4 => Str::Borrowed("warning"),
-                        5 => Str::Borrowed("notice"),
-                        6 => Str::Borrowed("info"),
-                        7 => Str::Borrowed("debug"),
-                        _ => Str::Borrowed("debug"),
+                    .map(|embedded| {
+                        let normalized = if normalize_embedded_levels {
+                            levels.normalize(embedded.as_ref())
+                        } else {
+                            None
+                        };
+
+                        match normalized {
+                            Some(normalized) => Str::Borrowed(normalized),
+                            None => embedded.into_owned(),
+                        }
+                    })
+                    .unwrap_or_else(|| {
+                        // GELF levels are syslog severities; `info` is the default
+                        let level = level.as_ref().and_then(Lenient::valid).copied();
+
+                        Str::Borrowed(levels.name(level.unwrap_or(6)))
                     }),
             );
         }
@@ -197,10 +363,12 @@ where
             clef.timestamp =
                 Self::find_first(&clef.additional, &["timestamp", "ts", "TIMESTAMP", "TS"])
                     .and_then(|v| v.as_str())
-                    .and_then(|s| clef::Timestamp::try_parse_rfc3339(s))
+                    .and_then(clef::Timestamp::try_parse_rfc3339)
                     .or_else(|| {
                         timestamp
-                            .and_then(clef::Timestamp::from_decimal)
+                            .as_ref()
+                            .and_then(Lenient::valid)
+                            .and_then(|ts| clef::Timestamp::from_decimal(*ts))
                             .or_else(|| Some(clef::Timestamp::now()))
                     });
         }
@@ -257,17 +425,29 @@ where
             );
         }
 
-        if let Some(line) = line {
-            Self::override_value(&mut clef.additional, "line", (*line).into());
+        match line {
+            Some(Lenient::Valid(line)) => {
+                Self::override_value(&mut clef.additional, "line", (*line).into())
+            }
+            Some(Lenient::Invalid(line)) => {
+                Self::override_value(&mut clef.additional, "line", line.clone())
+            }
+            None => (),
+        }
+
+        // Keep any built-ins that couldn't be coerced so they aren't lost
+        if let Some(level) = level.as_ref().and_then(Lenient::invalid) {
+            Self::override_value(&mut clef.additional, "level", level.clone());
+        }
+
+        if let Some(timestamp) = timestamp.as_ref().and_then(Lenient::invalid) {
+            Self::override_value(&mut clef.additional, "timestamp", timestamp.clone());
         }
 
         clef
     }
 
-    fn find_first<'a, 'b>(
-        fields: &'b HashMap<Str<'a>, Value>,
-        names: &'b [&str],
-    ) -> Option<&'b Value> {
+    fn find_first<'b>(fields: &'b HashMap<Str<'_>, Value>, names: &'b [&str]) -> Option<&'b Value> {
         for name in names {
             if let Some(value) = fields.get(&Str::Borrowed(name)) {
                 return Some(value);
@@ -306,7 +486,7 @@ where
     fn additional(&self) -> Option<impl IntoIterator<Item = (&str, &Value)>> {
         match self.additional {
             Some(Value::Object(ref additional)) => Some(additional.iter().map(|(k, v)| {
-                let k = if k.starts_with('_') { &k[1..] } else { &k };
+                let k = k.strip_prefix('_').unwrap_or(k);
 
                 (k, v)
             })),
@@ -319,8 +499,11 @@ where
 mod tests {
     use super::*;
 
+    use bytes::Bytes;
     use serde_json::json;
 
+    use crate::receive;
+
     #[test]
     fn from_gelf_msg() {
         let gelf = json!({
@@ -462,6 +645,180 @@ mod tests {
             .expect("failed to read gelf event");
     }
 
+    #[test]
+    fn from_gelf_lenient_built_ins() {
+        let gelf = json!({
+            "version": "1.1",
+            "host": "example.org",
+            "short_message": "A short message",
+            "timestamp": "2013-11-21T17:11:02.3072Z",
+            "level": "WARN",
+            "line": "42"
+        });
+
+        let process = Process::new(Default::default());
+
+        process
+            .with_clef(gelf.to_string().as_bytes(), |clef| {
+                let expected = json!({
+                    "@t": "2013-11-21T17:11:02.307200000Z",
+                    "@l": "warning",
+                    "@m": "A short message",
+                    "host": "example.org",
+                    "line": 42
+                });
+
+                let clef = serde_json::to_value(&clef).expect("failed to read clef");
+
+                assert_eq!(expected, clef);
+
+                Ok(())
+            })
+            .expect("failed to read gelf event");
+    }
+
+    #[test]
+    fn from_gelf_invalid_built_ins_are_kept() {
+        let gelf = json!({
+            "version": "1.1",
+            "host": "example.org",
+            "short_message": "A short message",
+            "timestamp": "yesterday",
+            "level": "loud",
+            "line": "forty-two"
+        });
+
+        let process = Process::new(Default::default());
+
+        process
+            .with_clef(gelf.to_string().as_bytes(), |clef| {
+                let clef = serde_json::to_value(&clef).expect("failed to read clef");
+
+                // The defaults are used for the event itself
+                assert_eq!("info", clef["@l"]);
+                assert!(clef["@t"].is_string());
+
+                assert_eq!("yesterday", clef["timestamp"]);
+                assert_eq!("loud", clef["level"]);
+                assert_eq!("forty-two", clef["line"]);
+
+                Ok(())
+            })
+            .expect("failed to read gelf event");
+    }
+
+    #[test]
+    fn strict_rejects_messages_that_break_the_spec() {
+        let process = Process::new(Config {
+            strict: true,
+            ..Default::default()
+        });
+
+        let gelf = json!({
+            "version": "1.1",
+            "host": "example.org",
+            "short_message": "A short message",
+            "_user_id": 9001,
+        });
+
+        process
+            .with_clef(gelf.to_string().as_bytes(), |_| Ok(()))
+            .expect("failed to read gelf event");
+
+        let gelf = json!({
+            "version": "1.1",
+            "host": "example.org",
+            "short_message": "A short message",
+            "user_id": 9001,
+        });
+
+        let err = process
+            .with_clef::<()>(gelf.to_string().as_bytes(), |_| unreachable!())
+            .expect_err("expected validation to fail");
+
+        assert_eq!(
+            Some(&Violation::UnprefixedField("user_id".to_owned())),
+            err.downcast_ref::<Violation>()
+        );
+    }
+
+    #[test]
+    fn strict_violations_are_emitted_as_errors() {
+        let process = Process::new(Config {
+            strict: true,
+            emit_invalid_as_errors: true,
+            include_raw_payload: true,
+            ..Default::default()
+        });
+
+        let gelf = json!({
+            "version": "1.1",
+            "short_message": "A short message",
+        });
+
+        process
+            .with_clef(gelf.to_string().as_bytes(), |clef| {
+                let clef = serde_json::to_value(&clef).expect("failed to read clef");
+
+                assert_eq!("Error", clef["@l"]);
+                assert_eq!(Violation::MissingHost.to_string(), clef["error"]);
+
+                Ok(())
+            })
+            .expect("failed to emit error event");
+    }
+
+    #[test]
+    fn from_gelf_seq_levels() {
+        let process = Process::new(Config {
+            levels: Levels::seq(),
+            ..Default::default()
+        });
+
+        for (level, expected) in [(0, "Fatal"), (3, "Error"), (5, "Information"), (7, "Debug")] {
+            let gelf = json!({
+                "version": "1.1",
+                "host": "example.org",
+                "short_message": "A short message",
+                "level": level,
+            });
+
+            process
+                .with_clef(gelf.to_string().as_bytes(), |clef| {
+                    assert_eq!(Some(expected), clef.level.as_ref().map(|l| l.as_ref()));
+
+                    Ok(())
+                })
+                .expect("failed to read gelf event");
+        }
+    }
+
+    #[test]
+    fn from_gelf_inner_json_levels_are_normalized() {
+        for (normalize_embedded_levels, expected) in [(true, "Warning"), (false, "WARN")] {
+            let process = Process::new(Config {
+                levels: Levels::seq(),
+                normalize_embedded_levels,
+                ..Default::default()
+            });
+
+            let gelf = json!({
+                "version": "1.1",
+                "host": "example.org",
+                "short_message": json!({ "lvl": "WARN", "msg": "A short message" }).to_string(),
+                "level": 6,
+            });
+
+            process
+                .with_clef(gelf.to_string().as_bytes(), |clef| {
+                    assert_eq!(Some(expected), clef.level.as_ref().map(|l| l.as_ref()));
+
+                    Ok(())
+                })
+                .expect("failed to read gelf event");
+        }
+    }
+
     #[test]
     fn include_raw_payload() {
         let gelf = json!({
@@ -518,7 +875,7 @@ mod tests {
         });
 
         let err = process
-            .with_clef(gelf.as_bytes(), |_| unreachable!())
+            .with_clef::<()>(gelf.as_bytes(), |_| unreachable!())
             .expect_err("expected parsing to fail");
 
         assert!(err.to_string().contains(gelf));
@@ -534,9 +891,89 @@ mod tests {
         });
 
         let err = process
-            .with_clef(gelf, |_| unreachable!())
+            .with_clef::<()>(gelf, |_| unreachable!())
             .expect_err("expected parsing to fail");
 
         assert!(err.to_string().contains(&*String::from_utf8_lossy(gelf)));
     }
+
+    #[test]
+    fn invalid_payloads_are_emitted_as_errors() {
+        let process = Process::new(Config {
+            emit_invalid_as_errors: true,
+            ..Default::default()
+        });
+
+        let msg = receive::build(receive::Config::default())
+            .decode(Bytes::from_static(b"this is definitely not json"))
+            .expect("failed to decode")
+            .expect("missing message")
+            .with_peer(([127, 0, 0, 1], 12201).into());
+
+        process
+            .with_clef(msg, |clef| {
+                let clef = serde_json::to_value(&clef).expect("failed to read clef");
+
+                assert_eq!("Error", clef["@l"]);
+                assert_eq!(INVALID_MESSAGE_TEMPLATE, clef["@mt"]);
+                assert_eq!("127.0.0.1:12201", clef["peer"]);
+                assert_eq!("this is definitely not json", clef["payload"]);
+                assert!(clef["error"]
+                    .as_str()
+                    .expect("missing error")
+                    .contains("expected"));
+                assert!(clef["@t"].is_string());
+
+                Ok(())
+            })
+            .expect("failed to emit error event");
+    }
+
+    #[test]
+    fn invalid_payloads_in_error_events_are_truncated() {
+        let process = Process::new(Config {
+            emit_invalid_as_errors: true,
+            ..Default::default()
+        });
+
+        let gelf = "a".repeat(MAX_INVALID_PAYLOAD_BYTES + 10);
+
+        process
+            .with_clef(gelf.as_bytes(), |clef| {
+                let payload = clef.additional[&Str::Borrowed("payload")]
+                    .as_str()
+                    .expect("missing payload");
+
+                assert!(!clef.additional.contains_key(&Str::Borrowed("peer")));
+                assert_eq!(format!("{}…", &gelf[..MAX_INVALID_PAYLOAD_BYTES]), payload);
+
+                Ok(())
+            })
+            .expect("failed to emit error event");
+    }
+
+    #[test]
+    fn valid_payloads_are_unaffected_by_error_events() {
+        let process = Process::new(Config {
+            emit_invalid_as_errors: true,
+            ..Default::default()
+        });
+
+        let gelf = json!({
+            "host": "example.org",
+            "short_message": "A short message",
+        });
+
+        process
+            .with_clef(gelf.to_string().as_bytes(), |clef| {
+                assert_eq!(
+                    Some("A short message"),
+                    clef.message.as_ref().map(|m| m.as_ref())
+                );
+                assert!(!clef.additional.contains_key(&Str::Borrowed("payload")));
+
+                Ok(())
+            })
+            .expect("failed to read gelf event");
+    }
 }
@@ -50,7 +50,7 @@ impl<'a, S> Str<'a, S> {
 }
 
 impl<'a> Str<'a> {
-    pub(crate) fn into_owned(self) -> Str<'static> {
+    pub fn into_owned(self) -> Str<'static> {
         match self {
             Str::Borrowed(s) => Str::Owned(s.to_owned()),
             Str::Owned(s) => Str::Owned(s),
@@ -189,7 +189,7 @@ where
 {
     fn as_ref(&self) -> &str {
         match self {
-            Inlinable::Inline(s) => &*s,
+            Inlinable::Inline(s) => s,
             Inlinable::Spilled(s) => s.as_ref(),
         }
     }
@@ -203,8 +203,8 @@ where
 
     fn deref(&self) -> &str {
         match self {
-            Inlinable::Inline(s) => &*s,
-            Inlinable::Spilled(s) => &*s,
+            Inlinable::Inline(s) => s,
+            Inlinable::Spilled(s) => s,
         }
     }
 }
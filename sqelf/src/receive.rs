@@ -9,6 +9,16 @@ use std::{
         self,
         Read,
     },
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        Weak,
+    },
     time::{
         self,
         Duration,
@@ -37,10 +47,35 @@ metrics! {
     msg_incomplete_chunk_overflow
 }
 
+/**
+The number of incomplete messages held by each live receiver.
+
+The `msg_incomplete` gauge is summed from these when it's sampled,
+so it always matches the receivers that are actually holding messages.
+*/
+static INCOMPLETE: Mutex<Vec<Weak<AtomicUsize>>> = Mutex::new(Vec::new());
+
+/**
+The number of incomplete messages held across all live receivers.
+*/
+pub(crate) fn incomplete_messages() -> usize {
+    let mut incomplete = INCOMPLETE.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Receivers that have been dropped no longer hold any messages
+    incomplete.retain(|count| count.strong_count() > 0);
+
+    incomplete
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|count| count.load(Ordering::Relaxed))
+        .sum()
+}
+
 /**
 GELF receiver configuration.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /**
     The maximum number of incomplete chunked messages.
@@ -89,14 +124,27 @@ A message may be chunked and compressed.
 This decoder won't attempt to validate that the contents
 of the message itself conforms to the GELF specification.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Gelf {
     config: Config,
     by_id: ById,
     by_arrival: ByArrival,
+    incomplete: Arc<AtomicUsize>,
 }
 
-#[derive(Debug, Clone)]
+impl Clone for Gelf {
+    /**
+    Create a decoder with the same configuration.
+
+    The clone starts without any incomplete messages, so chunks
+    received by one decoder are never completed by the other.
+    */
+    fn clone(&self) -> Self {
+        Gelf::new(self.config.clone())
+    }
+}
+
+#[derive(Debug)]
 struct ById {
     chunks: HashMap<u64, (Chunks, UniqueTimestamp)>,
 }
@@ -109,7 +157,7 @@ impl ById {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct ByArrival {
     counter: u64,
     chunks: BTreeMap<UniqueTimestamp, u64>,
@@ -145,10 +193,18 @@ impl UniqueTimestamp {
 
 impl Gelf {
     pub fn new(config: Config) -> Self {
+        let incomplete = Arc::new(AtomicUsize::new(0));
+
+        INCOMPLETE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::downgrade(&incomplete));
+
         Gelf {
             config,
             by_id: ById::new(),
             by_arrival: ByArrival::new(),
+            incomplete,
         }
     }
 
@@ -161,7 +217,12 @@ impl Gelf {
             // Push a chunk onto a message
             // If the chunk completes the message then it
             // will be returned
-            self.chunked(src)
+            let msg = self.chunked(src);
+
+            self.incomplete
+                .store(self.by_id.chunks.len(), Ordering::Relaxed);
+
+            msg
         } else {
             increment!(receive.msg_unchunked);
 
@@ -184,7 +245,7 @@ impl Gelf {
             } => {
                 let magic = Message::peek_magic_bytes(&src);
 
-                return Ok(Message::single(magic.and_then(Compression::detect), src));
+                Ok(Message::single(magic.and_then(Compression::detect), src))
             }
             // If the message has too many chunks then discard it
             ChunkHeader { seq_count, .. } if seq_count > self.config.max_chunks_per_message => {
@@ -268,9 +329,7 @@ impl Gelf {
 
                     increment!(receive.msg_chunked);
 
-                    Ok(Message::chunked(
-                        chunks.inner.into_iter().map(|(_, chunk)| chunk),
-                    ))
+                    Ok(Message::chunked(chunks.inner.into_values()))
                 } else {
                     Ok(None)
                 }
@@ -313,11 +372,16 @@ impl Chunks {
 
 /**
 A raw GELF message.
+
+Cloning a message is cheap because its chunks are reference counted.
 */
-#[derive(Debug, PartialEq, Eq)]
-pub struct Message(MessageInner);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    inner: MessageInner,
+    peer: Option<SocketAddr>,
+}
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum MessageInner {
     /**
     A message consisting of a single chunk.
@@ -379,11 +443,48 @@ enum Compression {
     Zlib,
 }
 
+/**
+Whether a payload is one chunk of a message that was split into several.
+
+A chunk like this can't be decoded into a message without the others.
+*/
+pub fn is_partial_chunk(src: &[u8]) -> bool {
+    Message::peek_magic_bytes(src) == Some(Message::MAGIC_CHUNKED)
+        && src.get(ChunkHeader::SIZE - 1) != Some(&1)
+}
+
 impl Message {
     const MAGIC_CHUNKED: [u8; 2] = [0x1e, 0x0f];
 
+    fn from_inner(inner: MessageInner) -> Self {
+        Message { inner, peer: None }
+    }
+
+    /**
+    Set the address of the client that sent the message.
+    */
+    pub fn with_peer(self, peer: SocketAddr) -> Self {
+        Message {
+            peer: Some(peer),
+            ..self
+        }
+    }
+
+    /**
+    The raw bytes of the message.
+
+    The chunks of a chunked message are joined together. Any compression is kept,
+    so the bytes can be decoded again as an unchunked message.
+    */
+    pub fn to_bytes(&self) -> Bytes {
+        match &self.inner {
+            MessageInner::Single { bytes, .. } => bytes.clone(),
+            MessageInner::Chunked { chunks } => Bytes::from(chunks.concat()),
+        }
+    }
+
     fn single(compression: Option<Compression>, src: Bytes) -> Option<Self> {
-        if src.len() == 0 {
+        if src.is_empty() {
             return None;
         }
 
@@ -392,7 +493,7 @@ impl Message {
             compression
         );
 
-        Some(Message(MessageInner::Single {
+        Some(Message::from_inner(MessageInner::Single {
             compression,
             bytes: src,
         }))
@@ -401,11 +502,11 @@ impl Message {
     fn chunked(chunks: impl IntoIterator<Item = Bytes>) -> Option<Self> {
         let chunks: Vec<_> = chunks.into_iter().collect();
 
-        if chunks.len() == 0 {
+        if chunks.is_empty() {
             return None;
         }
 
-        Some(Message(MessageInner::Chunked { chunks }))
+        Some(Message::from_inner(MessageInner::Chunked { chunks }))
     }
 
     fn peek_magic_bytes(src: &[u8]) -> Option<[u8; 2]> {
@@ -420,11 +521,11 @@ impl Message {
     }
 
     fn compression(&self) -> Option<Compression> {
-        match &self.0 {
+        match &self.inner {
             MessageInner::Single { compression, .. } => *compression,
             MessageInner::Chunked { chunks } => chunks
                 .first()
-                .and_then(|chunk| Self::peek_magic_bytes(&chunk))
+                .and_then(|chunk| Self::peek_magic_bytes(chunk))
                 .and_then(Compression::detect),
         }
     }
@@ -434,11 +535,11 @@ impl MemRead for Message {
     type Reader = Reader;
 
     fn bytes(&self) -> Option<&[u8]> {
-        match &self.0 {
+        match &self.inner {
             MessageInner::Single {
                 bytes,
                 compression: None,
-            } => Some(&*bytes),
+            } => Some(bytes),
             _ => None,
         }
     }
@@ -449,7 +550,7 @@ impl MemRead for Message {
         let body = ChunkRead {
             chunk: 0,
             cursor: 0,
-            msg: self.0,
+            msg: self.inner,
         };
 
         let reader = match compression {
@@ -460,6 +561,10 @@ impl MemRead for Message {
 
         Ok(reader)
     }
+
+    fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
 }
 
 /**
@@ -493,7 +598,7 @@ impl Read for ChunkRead {
     fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
         match &mut self.msg {
             MessageInner::Single { bytes, .. } => {
-                if b.len() == 0 {
+                if b.is_empty() {
                     return Ok(0);
                 }
 
@@ -509,7 +614,7 @@ impl Read for ChunkRead {
                 let mut b = b;
                 let mut total = 0;
 
-                while b.len() > 0 {
+                while !b.is_empty() {
                     if let Some(bytes) = chunks.get(self.chunk) {
                         let readable = &bytes[self.cursor..];
 
@@ -632,7 +737,7 @@ mod tests {
             .expect("failed to decode message")
             .expect("missing message value");
 
-        let expected = Message(MessageInner::Single {
+        let expected = Message::from_inner(MessageInner::Single {
             compression: None,
             bytes: Bytes::from(b"Hello!" as &[u8]),
         });
@@ -703,7 +808,7 @@ mod tests {
             .expect("failed to decode message")
             .expect("missing message value");
 
-        let expected = Message(MessageInner::Single {
+        let expected = Message::from_inner(MessageInner::Single {
             compression: None,
             bytes: Bytes::from(b"Hello!" as &[u8]),
         });
@@ -743,7 +848,7 @@ mod tests {
             .expect("failed to decode message")
             .expect("missing message value");
 
-        let expected = Message(MessageInner::Chunked {
+        let expected = Message::from_inner(MessageInner::Chunked {
             chunks: vec![
                 Bytes::from(b"Hello" as &[u8]),
                 Bytes::from(b" World" as &[u8]),
@@ -834,6 +939,39 @@ mod tests {
         assert_eq!("Hello World!", read);
     }
 
+    #[test]
+    fn chunked_message_bytes_decode_as_unchunked() {
+        let buf = gzip(b"Hello World!");
+
+        let (chunk_1, chunk_2) = (&buf[0..2], &buf[2..]);
+
+        let mut gelf = Gelf::new(Default::default());
+
+        gelf.decode(chunk(0, 0, 2, chunk_1))
+            .expect("failed to decode message");
+
+        let bytes = gelf
+            .decode(chunk(0, 1, 2, chunk_2))
+            .expect("failed to decode message")
+            .expect("missing message value")
+            .to_bytes();
+
+        assert_eq!(buf, bytes);
+
+        let mut msg = gelf
+            .decode(bytes)
+            .expect("failed to decode message")
+            .expect("missing message value")
+            .into_reader()
+            .expect("failed to build reader");
+
+        let mut read = String::new();
+        msg.read_to_string(&mut read)
+            .expect("failed to read message");
+
+        assert_eq!("Hello World!", read);
+    }
+
     #[test]
     fn when_capacity_is_reached_all_incomplete_messages_are_dropped() {
         let mut gelf = Gelf::new(Config {
@@ -860,6 +998,33 @@ mod tests {
         assert_eq!(2, *gelf.by_id.chunks.keys().next().unwrap());
     }
 
+    #[test]
+    fn incomplete_messages_are_counted_by_each_receiver() {
+        let mut gelf = Gelf::new(Default::default());
+
+        gelf.decode(chunk(0, 0, 2, b"1"))
+            .expect("failed to decode message");
+        gelf.decode(chunk(1, 0, 2, b"2"))
+            .expect("failed to decode message");
+
+        assert_eq!(2, gelf.incomplete.load(Ordering::Relaxed));
+
+        // A clone doesn't copy the incomplete messages
+        let clone = gelf.clone();
+
+        assert_eq!(0, clone.by_id.chunks.len());
+        assert_eq!(0, clone.incomplete.load(Ordering::Relaxed));
+
+        gelf.decode(chunk(0, 1, 2, b"1"))
+            .expect("failed to decode message")
+            .expect("missing message");
+
+        assert_eq!(1, gelf.incomplete.load(Ordering::Relaxed));
+
+        // NOTE: Other tests may be holding incomplete messages too
+        assert!(incomplete_messages() >= 1);
+    }
+
     #[test]
     fn when_timeout_expires_incomplete_messages_are_dropped() {
         let mut gelf = Gelf::new(Config {
@@ -0,0 +1,261 @@
+/*!
+A client for sending GELF messages.
+
+Messages are GELF JSON documents. Over UDP they may be compressed and are split
+into chunks when they're too large for a single datagram. Over TCP they're written
+uncompressed as null-delimited frames, optionally over TLS.
+*/
+
+use std::{
+    io::Write,
+    str::FromStr,
+};
+
+use libflate::{
+    gzip,
+    zlib,
+};
+
+use crate::Error;
+
+pub mod tcp;
+pub mod udp;
+
+/**
+The magic bytes at the start of a chunked datagram.
+*/
+pub const MAGIC_CHUNKED: [u8; 2] = [0x1e, 0x0f];
+
+/**
+The size of the header at the start of each chunk.
+*/
+pub const CHUNK_HEADER_SIZE: usize = 12;
+
+/**
+The maximum number of chunks a single message can be split into.
+*/
+pub const MAX_CHUNKS: usize = 128;
+
+/**
+The compression to apply to a message before sending it.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zlib,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zlib" => Ok(Compression::Zlib),
+            _ => bail!("expected `none`, `gzip`, or `zlib`"),
+        }
+    }
+}
+
+/**
+Compress a message.
+*/
+pub fn compress(msg: &[u8], compression: Compression) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::None => Ok(msg.to_vec()),
+        Compression::Gzip => {
+            let mut encoder = gzip::Encoder::new(Vec::new())?;
+            encoder.write_all(msg)?;
+
+            Ok(encoder.finish().into_result()?)
+        }
+        Compression::Zlib => {
+            let mut encoder = zlib::Encoder::new(Vec::new())?;
+            encoder.write_all(msg)?;
+
+            Ok(encoder.finish().into_result()?)
+        }
+    }
+}
+
+/**
+Build the header for a single chunk of a message.
+*/
+pub fn chunk_header(id: u64, seq_num: u8, seq_count: u8) -> [u8; CHUNK_HEADER_SIZE] {
+    let mut header = [0; CHUNK_HEADER_SIZE];
+
+    header[0..2].copy_from_slice(&MAGIC_CHUNKED);
+    header[2..10].copy_from_slice(&id.to_be_bytes());
+    header[10] = seq_num;
+    header[11] = seq_count;
+
+    header
+}
+
+/**
+Split a message into datagrams that are no larger than `max_datagram_size`.
+
+A message that fits in a single datagram isn't chunked. Otherwise each chunk
+shares a random message id.
+*/
+pub fn chunks(msg: &[u8], max_datagram_size: usize) -> Result<Vec<Vec<u8>>, Error> {
+    chunks_with_id(rand::random(), msg, max_datagram_size)
+}
+
+/**
+Split a message into datagrams that are no larger than `max_datagram_size`,
+using the given message id for any chunks.
+*/
+pub fn chunks_with_id(
+    id: u64,
+    msg: &[u8],
+    max_datagram_size: usize,
+) -> Result<Vec<Vec<u8>>, Error> {
+    if msg.len() <= max_datagram_size {
+        return Ok(vec![msg.to_vec()]);
+    }
+
+    if max_datagram_size <= CHUNK_HEADER_SIZE {
+        bail!(
+            "the maximum datagram size of {} bytes is too small to fit a chunk",
+            max_datagram_size
+        );
+    }
+
+    let chunk_size = max_datagram_size - CHUNK_HEADER_SIZE;
+    let seq_count = (msg.len() + chunk_size - 1) / chunk_size;
+
+    if seq_count > MAX_CHUNKS {
+        bail!(
+            "the message of {} bytes needs {} chunks but at most {} are allowed",
+            msg.len(),
+            seq_count,
+            MAX_CHUNKS
+        );
+    }
+
+    let chunks = msg
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(seq_num, chunk)| {
+            let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+
+            datagram.extend_from_slice(&chunk_header(id, seq_num as u8, seq_count as u8));
+            datagram.extend_from_slice(chunk);
+
+            datagram
+        })
+        .collect();
+
+    Ok(chunks)
+}
+
+/**
+Build a null-delimited TCP frame for a message.
+*/
+pub fn frame(msg: &[u8]) -> Result<Vec<u8>, Error> {
+    if msg.contains(&b'\0') {
+        bail!("the message contains a null byte, which would terminate the frame early");
+    }
+
+    let mut frame = Vec::with_capacity(msg.len() + 1);
+
+    frame.extend_from_slice(msg);
+    frame.push(b'\0');
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use crate::{
+        process,
+        receive,
+    };
+
+    fn receive_all(datagrams: Vec<Vec<u8>>) -> Option<serde_json::Value> {
+        let mut receive = receive::build(receive::Config::default());
+        let process = process::build(process::Config::default());
+
+        let mut received = None;
+
+        for datagram in datagrams {
+            if let Some(msg) = receive
+                .decode(Bytes::from(datagram))
+                .expect("failed to decode datagram")
+            {
+                process
+                    .with_clef(msg, |clef| {
+                        received = Some(serde_json::to_value(clef)?);
+
+                        Ok(())
+                    })
+                    .expect("failed to process message");
+            }
+        }
+
+        received
+    }
+
+    fn msg(short_message: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "host": "foo",
+            "short_message": short_message,
+        }))
+        .expect("infallible JSON")
+    }
+
+    #[test]
+    fn chunk_header_layout() {
+        assert_eq!(
+            [0x1e, 0x0f, 0, 0, 0, 0, 0, 0, 0x01, 0x02, 3, 4],
+            chunk_header(0x0102, 3, 4)
+        );
+    }
+
+    #[test]
+    fn small_messages_are_not_chunked() {
+        let msg = msg("bar");
+        let chunks = chunks(&msg, 8192).expect("failed to chunk");
+
+        assert_eq!(vec![msg], chunks);
+    }
+
+    #[test]
+    fn chunked_compressed_messages_are_received() {
+        for compression in &[Compression::None, Compression::Gzip, Compression::Zlib] {
+            let short_message = "a".repeat(1024);
+            let compressed =
+                compress(&msg(&short_message), *compression).expect("failed to compress");
+
+            let chunks = chunks_with_id(42, &compressed, 24).expect("failed to chunk");
+
+            assert!(chunks.len() > 1);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= 24));
+
+            let received = receive_all(chunks).expect("missing message");
+            assert_eq!(short_message, received["@m"]);
+        }
+    }
+
+    #[test]
+    fn too_many_chunks_is_an_error() {
+        let msg = vec![b'a'; (MAX_CHUNKS + 1) * 4];
+
+        assert!(chunks(&msg, CHUNK_HEADER_SIZE + 4).is_err());
+        assert!(chunks(&msg[..MAX_CHUNKS * 4], CHUNK_HEADER_SIZE + 4).is_ok());
+    }
+
+    #[test]
+    fn frames_are_null_delimited() {
+        assert_eq!(b"{}\0".to_vec(), frame(b"{}").expect("failed to frame"));
+        assert!(frame(b"{\0}").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    convert::TryFrom,
+    io::{
+        self,
+        Write,
+    },
+    net::{
+        Shutdown,
+        TcpStream,
+        ToSocketAddrs,
+    },
+    sync::Arc,
+};
+
+use rustls::{
+    pki_types::ServerName,
+    ClientConfig,
+    ClientConnection,
+    StreamOwned,
+};
+
+use crate::{
+    send::frame,
+    Error,
+};
+
+/**
+A client that sends GELF messages as null-delimited frames over TCP.
+*/
+pub struct Client {
+    stream: Stream,
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Client {
+    /**
+    Connect to a server over plain TCP.
+    */
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Client {
+            stream: Stream::Tcp(stream),
+        })
+    }
+
+    /**
+    Connect to a server over TLS.
+
+    The `server_name` is used to verify the certificate presented by the server.
+    */
+    pub fn connect_tls(
+        addr: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self, Error> {
+        let server_name = ServerName::try_from(server_name.to_owned()).map_err(|e| {
+            Error::from(e).context(format!("invalid server name `{}`", server_name))
+        })?;
+
+        let conn = ClientConnection::new(config, server_name)?;
+
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Client {
+            stream: Stream::Tls(Box::new(StreamOwned::new(conn, stream))),
+        })
+    }
+
+    /**
+    Send a GELF message.
+
+    Messages are written uncompressed because a compressed message
+    could contain the null byte that delimits frames.
+    */
+    pub fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(&frame(msg)?)?;
+        self.stream.flush()?;
+
+        Ok(())
+    }
+
+    /**
+    Get the underlying stream to write raw bytes to.
+
+    Bytes written to the stream aren't framed.
+    */
+    pub fn get_mut(&mut self) -> &mut (dyn Write + Send) {
+        &mut self.stream
+    }
+
+    /**
+    Close the connection.
+    */
+    pub fn close(self) -> Result<(), Error> {
+        let sock = match self.stream {
+            Stream::Tcp(sock) => sock,
+            Stream::Tls(mut stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+
+                stream.sock
+            }
+        };
+
+        sock.shutdown(Shutdown::Both)?;
+
+        Ok(())
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
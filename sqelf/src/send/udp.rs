@@ -0,0 +1,96 @@
+use std::net::{
+    Ipv4Addr,
+    Ipv6Addr,
+    SocketAddr,
+    ToSocketAddrs,
+    UdpSocket,
+};
+
+use crate::{
+    send::{
+        chunks,
+        compress,
+        Compression,
+    },
+    Error,
+};
+
+/**
+UDP client configuration.
+*/
+#[derive(Debug, Clone)]
+pub struct Config {
+    /**
+    The compression to apply to messages before chunking them.
+    */
+    pub compression: Compression,
+    /**
+    The maximum size of a single datagram, including any chunk header.
+
+    Messages larger than this are split into chunks.
+    */
+    pub max_datagram_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            compression: Compression::None,
+            max_datagram_size: 8192, // 8kiB
+        }
+    }
+}
+
+/**
+A client that sends GELF messages as UDP datagrams.
+*/
+pub struct Client {
+    sock: UdpSocket,
+    config: Config,
+}
+
+impl Client {
+    /**
+    Bind a local socket for sending messages to the given address.
+    */
+    pub fn connect(addr: impl ToSocketAddrs, config: Config) -> Result<Self, Error> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("the address didn't resolve to any socket addresses"))?;
+
+        // Bind to any local port on the same address family as the server
+        let local = if addr.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+
+        let sock = UdpSocket::bind(local)?;
+        sock.connect(addr)?;
+
+        Ok(Client { sock, config })
+    }
+
+    /**
+    Send a GELF message.
+    */
+    pub fn send(&self, msg: &[u8]) -> Result<(), Error> {
+        let msg = compress(msg, self.config.compression)?;
+
+        for datagram in chunks(&msg, self.config.max_datagram_size)? {
+            self.sock.send(&datagram)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+    Send a raw datagram without compressing or chunking it.
+    */
+    pub fn send_raw(&self, datagram: &[u8]) -> Result<(), Error> {
+        self.sock.send(datagram)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    diagnostics::*,
+    server::State,
+};
+
+use anyhow::Error;
+
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    time::timeout,
+};
+
+/**
+The maximum size of a request head before the request is rejected.
+*/
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/**
+The maximum duration to spend reading a request.
+*/
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+A minimal HTTP server for health checks and metrics.
+
+This isn't a general purpose HTTP implementation. It reads just enough
+of a request to route it and always closes the connection after responding.
+*/
+pub(super) struct Server(TcpListener);
+
+impl Server {
+    pub(super) async fn bind(addr: &SocketAddr) -> Result<Self, Error> {
+        let listener = TcpListener::bind(&addr).await?;
+
+        Ok(Server(listener))
+    }
+
+    pub(super) fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.0.local_addr()?)
+    }
+
+    pub(super) async fn serve(self, state: Arc<State>) {
+        emit("Setting up for HTTP");
+
+        loop {
+            match self.0.accept().await {
+                Ok((conn, _)) => {
+                    let state = state.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(err) = respond(conn, &state).await {
+                            emit_debug_err(err.as_ref(), "HTTP request failed");
+                        }
+                    });
+                }
+                Err(err) => emit_debug_err(&err, "HTTP connection failed"),
+            }
+        }
+    }
+}
+
+async fn respond(mut conn: TcpStream, state: &State) -> Result<(), Error> {
+    let head = timeout(REQUEST_TIMEOUT, read_head(&mut conn)).await??;
+
+    let response = match Request::parse(&head) {
+        Some(request) => route(&request, state),
+        None => Response::new(400, "bad request"),
+    };
+
+    conn.write_all(&response.to_bytes()).await?;
+    conn.shutdown().await?;
+
+    Ok(())
+}
+
+async fn read_head(conn: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        let read = conn.read(&mut buf).await?;
+
+        if read == 0 {
+            return Ok(head);
+        }
+
+        head.extend_from_slice(&buf[..read]);
+
+        if head.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(head);
+        }
+
+        if head.len() > MAX_REQUEST_BYTES {
+            bail!("the HTTP request is too large");
+        }
+    }
+}
+
+fn route(request: &Request, state: &State) -> Response {
+    if request.method != "GET" && request.method != "HEAD" {
+        return Response::new(405, "method not allowed");
+    }
+
+    let response = match request.path {
+        // The process is alive
+        "/healthz" => Response::new(200, "ok"),
+        // The process is ready to receive events
+        "/readyz" => match state.readiness() {
+            Ok(()) => Response::new(200, "ok"),
+            Err(reason) => Response::new(503, reason),
+        },
+        // The current value of all metrics
+        "/metrics" => Response::new(200, render_prometheus())
+            .with_content_type("text/plain; version=0.0.4; charset=utf-8"),
+        _ => Response::new(404, "not found"),
+    };
+
+    if request.method == "HEAD" {
+        response.without_body()
+    } else {
+        response
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Request<'a> {
+    method: &'a str,
+    path: &'a str,
+}
+
+impl<'a> Request<'a> {
+    fn parse(head: &'a [u8]) -> Option<Self> {
+        let head = std::str::from_utf8(head).ok()?;
+        let mut parts = head.lines().next()?.split(' ');
+
+        let method = parts.next()?;
+        let target = parts.next()?;
+
+        if !parts.next()?.starts_with("HTTP/1.") {
+            return None;
+        }
+
+        // Ignore any query string
+        let path = target.split('?').next()?;
+
+        Some(Request { method, path })
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    include_body: bool,
+}
+
+impl Response {
+    fn new(status: u16, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+            include_body: true,
+        }
+    }
+
+    fn with_content_type(self, content_type: &'static str) -> Self {
+        Response {
+            content_type,
+            ..self
+        }
+    }
+
+    fn without_body(self) -> Self {
+        Response {
+            include_body: false,
+            ..self
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len() + 1,
+        )
+        .into_bytes();
+
+        if self.include_body {
+            response.extend_from_slice(self.body.as_bytes());
+            response.push(b'\n');
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request() {
+        let request = Request::parse(b"GET /readyz?verbose HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .expect("failed to parse request");
+
+        assert_eq!(
+            Request {
+                method: "GET",
+                path: "/readyz",
+            },
+            request
+        );
+    }
+
+    #[test]
+    fn parse_invalid_request() {
+        assert!(Request::parse(b"not http").is_none());
+        assert!(Request::parse(b"GET / SMTP\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn readyz_requires_listeners_to_be_bound() {
+        let state = State::new(1);
+
+        let request = Request {
+            method: "GET",
+            path: "/readyz",
+        };
+
+        assert_eq!(503, route(&request, &state).status);
+
+        state.set_bound(true);
+
+        assert_eq!(200, route(&request, &state).status);
+    }
+
+    #[test]
+    fn readyz_requires_output_to_keep_up() {
+        let state = State::new(1);
+        state.set_bound(true);
+
+        state.set_saturated(true);
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(state.readiness_with_lag(Duration::from_millis(10)).is_err());
+
+        state.set_saturated(false);
+
+        assert!(state.readiness_with_lag(Duration::from_millis(10)).is_ok());
+    }
+
+    #[test]
+    fn readyz_fails_while_paused() {
+        let state = State::new(1);
+        state.set_bound(true);
+
+        state.set_paused(true);
+        assert_eq!(Err("the server is paused"), state.readiness());
+
+        state.set_paused(false);
+        assert!(state.readiness().is_ok());
+    }
+
+    #[test]
+    fn healthz_is_always_ok() {
+        let state = State::new(1);
+
+        let request = Request {
+            method: "GET",
+            path: "/healthz",
+        };
+
+        assert_eq!(200, route(&request, &state).status);
+    }
+}
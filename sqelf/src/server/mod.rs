@@ -1,37 +1,91 @@
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{
+    BufReader,
+    Read,
+};
 use std::{
+    cmp,
+    convert::TryFrom,
+    future::IntoFuture,
     marker::Unpin,
+    net::SocketAddr,
+    ops::ControlFlow,
+    pin::Pin,
     str::FromStr,
-    time::Duration,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use futures::{
     future::{
+        self,
         BoxFuture,
         Either,
+        FusedFuture,
     },
     select,
+    stream::{
+        FusedStream,
+        FuturesUnordered,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+    Future,
     FutureExt,
+    Stream,
     StreamExt,
 };
 
 use tokio::{
-    runtime::Runtime,
+    runtime,
     signal::ctrl_c,
-    sync::oneshot,
+    sync::{
+        oneshot,
+        watch,
+        Notify,
+    },
+    task::spawn_blocking,
+    time::sleep,
 };
 
+use tokio_util::sync::CancellationToken;
+
 use anyhow::Error;
 
 use bytes::Bytes;
+use chrono::{
+    DateTime,
+    Utc,
+};
 use tokio_rustls::rustls;
 
 use crate::{
-    diagnostics::*,
+    capture,
+    dead_letter,
+    diagnostics::{
+        self,
+        *,
+    },
+    io::MemRead,
     receive::Message,
 };
 
+mod http;
 mod tcp;
 mod udp;
 
@@ -43,42 +97,153 @@ metrics! {
     tcp_conn_accept,
     tcp_conn_close,
     tcp_conn_timeout,
-    tcp_msg_overflow
+    tcp_conn_frame_timeout,
+    tcp_conn_too_slow,
+    tcp_msg_overflow,
+    udp_bytes_received,
+    tcp_bytes_received,
+    dead_letter_msg,
+    dead_letter_err;
+    gauges:
+    tcp_conn_active;
+    histograms:
+    process_latency_seconds
 }
 
 /**
 Server configuration.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /**
     The address to bind the server to.
     */
     pub bind: Bind,
     /**
-    The duration to keep client TCP connections alive for.
+    The duration a client TCP connection can be idle for.
 
-    If the client doesn't complete a message within the period
+    If the client doesn't send any data within the period
     then the connection will be closed.
     */
+    pub tcp_idle_timeout_secs: u64,
+    /**
+    The maximum duration to receive a single message over TCP.
+
+    The period starts when the first bytes of a message arrive.
+    If the client doesn't complete the message within the period
+    then the connection will be closed.
+    */
+    pub tcp_frame_timeout_secs: u64,
+    /**
+    The minimum rate a client must send data at while a message is
+    in progress.
+
+    Clients that trickle data slower than this rate will have their
+    connection closed. A value of `0` disables the check.
+    */
+    pub tcp_min_bytes_per_sec: u64,
+    /**
+    The duration a client TCP connection can be idle for.
+
+    This is an alias for `tcp_idle_timeout_secs` that's kept for compatibility.
+    If this value is greater than `0` then it's used as the idle timeout instead.
+    */
+    #[deprecated(note = "use `tcp_idle_timeout_secs` instead")]
     pub tcp_keep_alive_secs: u64,
     /**
+    The idle period before the OS starts sending TCP keepalive probes
+    on client connections.
+
+    If this value is `None` then OS-level keepalive isn't enabled.
+    */
+    pub tcp_os_keep_alive_secs: Option<u64>,
+    /**
     The maximum size of a single event before it'll be discarded.
     */
     pub tcp_max_size_bytes: u64,
     /**
+    The maximum duration to spend draining in-flight messages on shutdown.
+
+    When the server is asked to stop it stops accepting new connections
+    and waits for messages that have already started arriving to complete.
+    Anything still in-flight once this period elapses is dropped.
+    */
+    pub shutdown_timeout_secs: u64,
+    /**
     The path to a PEM certificate file.
     */
     pub certificate: Option<Certificate>,
+    /**
+    The address to bind an HTTP server for health checks to.
+
+    If this value is `None` then no HTTP server is started.
+    */
+    pub http_bind: Option<String>,
+    /**
+    The path to a file to capture raw traffic to.
+
+    Each datagram or TCP frame is appended to the file along with
+    when it was received, the peer that sent it, and the protocol.
+    The capture can be replayed later with `sqelf replay`.
+    If this value is `None` then traffic isn't captured.
+    */
+    pub capture_path: Option<String>,
+    /**
+    The path to a directory to write messages that fail to be received or processed to.
+
+    Each failed message is written along with the error it failed with,
+    when it was received, the peer that sent it, and the protocol.
+    Dead-letter files can be resubmitted later with `sqelf resubmit`.
+    Payloads that are emitted as errors by processing succeed, so they
+    aren't written here.
+    If this value is `None` then failed messages are dropped.
+    */
+    pub dead_letter_path: Option<String>,
+    /**
+    The size a dead-letter file can grow to before a new one is started.
+    */
+    pub dead_letter_max_file_bytes: u64,
+    /**
+    The maximum number of dead-letter files to keep.
+
+    Once there are more files than this the oldest are removed.
+    A value of `0` keeps all files.
+    */
+    pub dead_letter_max_files: usize,
+    /**
+    The number of worker threads to use when the server creates its own runtime.
+
+    A value of `0` runs the server on a single-threaded runtime.
+    If this value is `None` then a thread is used per CPU core.
+    This setting doesn't apply when the server runs on an existing runtime.
+    */
+    pub worker_threads: Option<usize>,
+    /**
+    The maximum number of messages to process concurrently.
+
+    Once this many messages are being processed the server stops
+    receiving until one of them completes. With a value greater than `1`
+    messages may finish processing in a different order than they arrived.
+    */
+    pub max_in_flight: usize,
 }
 
-#[derive(Debug, Clone)]
+/**
+The address and protocol to bind the server to.
+
+A bind is written as a URL like `tcp://0.0.0.0:12201`.
+If the protocol is omitted then it defaults to UDP.
+*/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Bind {
     pub addr: String,
     pub protocol: Protocol,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Certificate {
     /**
     Path to the `.pem` file containing the certificate.
@@ -86,7 +251,10 @@ pub struct Certificate {
     pub path: String,
     /**
     Path to the `.pem` file containing the certificate private key.
+
+    If this value is empty then the certificate path is used.
     */
+    #[serde(default)]
     pub private_key_path: String,
 }
 
@@ -96,6 +264,15 @@ pub enum Protocol {
     Tcp,
 }
 
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Udp => "udp",
+            Protocol::Tcp => "tcp",
+        }
+    }
+}
+
 impl FromStr for Bind {
     type Err = Error;
 
@@ -117,26 +294,67 @@ impl FromStr for Bind {
     }
 }
 
+impl TryFrom<String> for Bind {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Config {
+    /**
+    The idle timeout for TCP connections.
+
+    The deprecated `tcp_keep_alive_secs` takes priority if it's set.
+    */
+    #[allow(deprecated)]
+    fn tcp_idle_timeout(&self) -> Duration {
+        if self.tcp_keep_alive_secs > 0 {
+            Duration::from_secs(self.tcp_keep_alive_secs)
+        } else {
+            Duration::from_secs(self.tcp_idle_timeout_secs)
+        }
+    }
+}
+
 impl Default for Config {
+    #[allow(deprecated)]
     fn default() -> Self {
         Config {
             bind: Bind {
                 addr: "0.0.0.0:12201".to_owned(),
                 protocol: Protocol::Udp,
             },
-            tcp_keep_alive_secs: 2 * 60,    // 2 minutes
+            tcp_idle_timeout_secs: 5 * 60, // 5 minutes
+            tcp_frame_timeout_secs: 30,    // 30 seconds
+            tcp_min_bytes_per_sec: 0,
+            tcp_keep_alive_secs: 0,
+            tcp_os_keep_alive_secs: None,
             tcp_max_size_bytes: 1024 * 256, // 256kiB
+            shutdown_timeout_secs: 5,       // 5 seconds
             certificate: None,
+            http_bind: None,
+            capture_path: None,
+            dead_letter_path: None,
+            dead_letter_max_file_bytes: 1024 * 1024 * 10, // 10MiB
+            dead_letter_max_files: 10,
+            worker_threads: None,
+            max_in_flight: 1,
         }
     }
 }
 
 /**
 A GELF server.
+
+The server can either be run on a runtime of its own with `run`,
+or awaited on an existing `tokio` runtime.
 */
 pub struct Server {
-    fut: BoxFuture<'static, ()>,
+    fut: BoxFuture<'static, Result<(), Error>>,
     handle: Option<Handle>,
+    worker_threads: Option<usize>,
 }
 
 impl Server {
@@ -144,15 +362,64 @@ impl Server {
         self.handle.take()
     }
 
+    /**
+    Run a future once the server has stopped, like flushing any buffered output.
+
+    The future is run even if the server stopped with an error.
+    */
+    pub(crate) fn on_stop<F>(mut self, stop: impl FnOnce() -> F + Send + 'static) -> Self
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let fut = self.fut;
+
+        self.fut = async move {
+            let run = fut.await;
+            stop().await?;
+
+            run
+        }
+        .boxed();
+
+        self
+    }
+
+    /**
+    Run the server on a new runtime, blocking until it stops.
+    */
     pub fn run(self) -> Result<(), Error> {
         // Run the server on a fresh runtime
         // We attempt to shut this runtime down cleanly to release
         // any used resources
-        let runtime = Runtime::new().expect("failed to start new Runtime");
+        let mut builder = match self.worker_threads {
+            Some(0) => runtime::Builder::new_current_thread(),
+            Some(worker_threads) => {
+                let mut builder = runtime::Builder::new_multi_thread();
+                builder.worker_threads(worker_threads);
 
-        runtime.block_on(self.fut);
+                builder
+            }
+            None => runtime::Builder::new_multi_thread(),
+        };
 
-        Ok(())
+        let runtime = builder.enable_all().build()?;
+
+        runtime.block_on(self.fut)
+    }
+}
+
+impl IntoFuture for Server {
+    type Output = Result<(), Error>;
+    type IntoFuture = BoxFuture<'static, Result<(), Error>>;
+
+    /**
+    Run the server on the current runtime.
+
+    The returned future must be polled on a `tokio` runtime with IO and time enabled.
+    It completes once the server stops.
+    */
+    fn into_future(self) -> Self::IntoFuture {
+        self.fut
     }
 }
 
@@ -162,9 +429,122 @@ programmatically.
 */
 pub struct Handle {
     close: oneshot::Sender<()>,
+    local_addrs: watch::Receiver<Option<LocalAddrs>>,
+    state: Arc<State>,
+}
+
+/**
+The local addresses a server's listeners are bound to.
+
+If the server was configured to bind to port `0` then these
+addresses contain the port assigned by the OS.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalAddrs {
+    /**
+    The address of the GELF listener.
+    */
+    pub gelf: SocketAddr,
+    /**
+    The address of the HTTP server, if one was configured.
+    */
+    pub http: Option<SocketAddr>,
 }
 
 impl Handle {
+    /**
+    The addresses the server is bound to.
+
+    This returns `None` until the server's listeners are bound.
+    */
+    pub fn local_addrs(&self) -> Option<LocalAddrs> {
+        *self.local_addrs.borrow()
+    }
+
+    /**
+    Wait for the server's listeners to be bound.
+
+    The returned future resolves with the bound addresses, or fails
+    if the server stops before it's ready.
+    */
+    pub fn ready(&self) -> impl Future<Output = Result<LocalAddrs, Error>> + Send + 'static {
+        let mut local_addrs = self.local_addrs.clone();
+
+        async move {
+            let bound = local_addrs
+                .wait_for(Option::is_some)
+                .await
+                .map_err(|_| anyhow!("the server stopped before it was ready"))?;
+
+            Ok(bound.expect("missing local addresses"))
+        }
+    }
+
+    /**
+    Stop receiving messages until the server is resumed.
+
+    While paused, TCP connections aren't accepted or read from, and the
+    OS may drop UDP datagrams once its receive buffer fills. Messages that
+    are already in-flight continue to be processed. The server reports
+    that it isn't ready through its HTTP health checks.
+    */
+    pub fn pause(&self) {
+        if !self.state.set_paused(true) {
+            emit_info("Pausing GELF server");
+        }
+    }
+
+    /**
+    Start receiving messages again after the server was paused.
+    */
+    pub fn resume(&self) {
+        if self.state.set_paused(false) {
+            emit_info("Resuming GELF server");
+        }
+    }
+
+    /**
+    Whether the server is paused.
+    */
+    pub fn is_paused(&self) -> bool {
+        self.state.is_paused()
+    }
+
+    /**
+    Change the maximum number of messages to process concurrently.
+
+    Messages already in-flight aren't affected if the limit is lowered.
+    */
+    pub fn set_max_in_flight(&self, max_in_flight: usize) -> Result<(), Error> {
+        if max_in_flight == 0 {
+            bail!("the maximum number of in-flight messages must be greater than 0");
+        }
+
+        self.state.set_max_in_flight(max_in_flight);
+
+        Ok(())
+    }
+
+    /**
+    Get a snapshot of the server's current state.
+    */
+    pub fn stats(&self) -> Stats {
+        let metrics = diagnostics::metrics();
+
+        Stats {
+            paused: self.state.is_paused(),
+            in_flight: self.state.in_flight(),
+            max_in_flight: self.state.max_in_flight(),
+            active_connections: metrics
+                .gauge("server", "tcp_conn_active")
+                .unwrap_or_default(),
+            incomplete_messages: metrics
+                .gauge("receive", "msg_incomplete")
+                .unwrap_or_default(),
+            metrics,
+        }
+    }
+
     /**
     Close the server.
     */
@@ -173,6 +553,169 @@ impl Handle {
     }
 }
 
+/**
+A point-in-time snapshot of a running server.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    /**
+    Whether the server is paused.
+    */
+    pub paused: bool,
+    /**
+    The number of messages currently being processed.
+    */
+    pub in_flight: usize,
+    /**
+    The maximum number of messages that can be processed concurrently.
+    */
+    pub max_in_flight: usize,
+    /**
+    The number of open TCP connections.
+
+    This is process-wide, so it includes the connections of any other servers.
+    */
+    pub active_connections: usize,
+    /**
+    The number of chunked messages that are waiting for more chunks to arrive.
+
+    This is process-wide, so it includes the messages of any other servers.
+    */
+    pub incomplete_messages: usize,
+    /**
+    The current value of all metrics.
+
+    Metrics are process-wide and cumulative from when the process started.
+    */
+    pub metrics: Snapshot,
+}
+
+/**
+The maximum duration the server can spend unable to receive because
+the maximum number of messages are in-flight before it's considered
+to not be keeping up.
+*/
+const MAX_PROCESS_LAG: Duration = Duration::from_secs(5);
+
+/**
+The live state of a running server.
+*/
+pub(crate) struct State {
+    bound: AtomicBool,
+    start: Instant,
+    /**
+    The number of milliseconds since `start` that the maximum number of
+    in-flight messages was reached, plus one. A value of `0` means the
+    server can receive more messages.
+    */
+    saturated_since_ms: AtomicU64,
+    paused: AtomicBool,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    /**
+    Wakes the server when its settings change while it's waiting.
+    */
+    changed: Notify,
+}
+
+impl State {
+    pub(crate) fn new(max_in_flight: usize) -> Self {
+        State {
+            bound: AtomicBool::new(false),
+            start: Instant::now(),
+            saturated_since_ms: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(max_in_flight),
+            changed: Notify::new(),
+        }
+    }
+
+    /**
+    Pause or resume the server, returning whether it was previously paused.
+    */
+    fn set_paused(&self, paused: bool) -> bool {
+        let was_paused = self.paused.swap(paused, Ordering::Relaxed);
+        self.changed.notify_one();
+
+        was_paused
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn set_in_flight(&self, in_flight: usize) {
+        self.in_flight.store(in_flight, Ordering::Relaxed);
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn set_max_in_flight(&self, max_in_flight: usize) {
+        self.max_in_flight.store(max_in_flight, Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_bound(&self, bound: bool) {
+        self.bound.store(bound, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_saturated(&self, saturated: bool) {
+        if saturated {
+            let since = self.start.elapsed().as_millis() as u64 + 1;
+
+            // NOTE: Only the start of a saturated period is recorded
+            let _ = self.saturated_since_ms.compare_exchange(
+                0,
+                since,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        } else {
+            self.saturated_since_ms.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /**
+    Whether the server is ready to receive messages.
+
+    The server is ready if its listeners are bound and the output
+    is keeping up with the messages being received.
+    */
+    pub(crate) fn readiness(&self) -> Result<(), &'static str> {
+        self.readiness_with_lag(MAX_PROCESS_LAG)
+    }
+
+    fn readiness_with_lag(&self, max_lag: Duration) -> Result<(), &'static str> {
+        if !self.bound.load(Ordering::Relaxed) {
+            return Err("listeners are not bound");
+        }
+
+        if self.is_paused() {
+            return Err("the server is paused");
+        }
+
+        match self.saturated_since_ms.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            since => {
+                let now = self.start.elapsed().as_millis() as u64 + 1;
+
+                if now.saturating_sub(since) > max_lag.as_millis() as u64 {
+                    Err("output is not keeping up")
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
 /**
 Build a server to receive GELF messages and process them.
 */
@@ -181,154 +724,848 @@ pub fn build(
     receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Send + Sync + Unpin + Clone + 'static,
     mut process: impl FnMut(Message) -> Result<(), Error> + Send + Sync + Unpin + Clone + 'static,
 ) -> Result<Server, Error> {
-    emit("Starting GELF server");
+    build_async(config, receive, move |msg| future::ready(process(msg)))
+}
+
+/**
+Build a server to receive GELF messages and process them asynchronously.
+
+Up to `max_in_flight` messages are processed concurrently.
+*/
+pub fn build_async<F>(
+    config: Config,
+    receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Send + Sync + Unpin + Clone + 'static,
+    mut process: impl FnMut(Message) -> F + Send + Sync + Unpin + Clone + 'static,
+) -> Result<Server, Error>
+where
+    F: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    emit_info("Starting GELF server");
+
+    if config.max_in_flight == 0 {
+        bail!("the maximum number of in-flight messages must be greater than 0");
+    }
 
     let addr = config.bind.addr.parse()?;
     let (handle_tx, handle_rx) = oneshot::channel();
+    let shutdown = CancellationToken::new();
+    let state = Arc::new(State::new(config.max_in_flight));
+
+    let http_addr = match config.http_bind {
+        Some(ref http_bind) => Some(http_bind.parse()?),
+        None => None,
+    };
+
+    let mut capture = match config.capture_path {
+        Some(ref capture_path) => {
+            emit_warn_with(
+                "Capturing raw traffic to {capture_path}",
+                CaptureProperties { capture_path },
+            );
+
+            Some(capture::Writer::open(capture_path)?)
+        }
+        None => None,
+    };
+
+    let (dead_letter, dead_letter_worker) = match config.dead_letter_path {
+        Some(ref dead_letter_path) => {
+            emit_with(
+                "Writing failed messages to {dead_letter_path}",
+                DeadLetterProperties { dead_letter_path },
+            );
+
+            let writer = dead_letter::Writer::open(
+                dead_letter_path,
+                config.dead_letter_max_file_bytes,
+                config.dead_letter_max_files,
+            )?;
+
+            let (dead_letter, worker) = DeadLetter::spawn(writer);
+
+            (Some(dead_letter), Some(worker))
+        }
+        None => (None, None),
+    };
+
+    let (local_addrs_tx, local_addrs_rx) = watch::channel(None);
 
     // Build a handle
-    let handle = Some(Handle { close: handle_tx });
+    let handle = Some(Handle {
+        close: handle_tx,
+        local_addrs: local_addrs_rx,
+        state: state.clone(),
+    });
+
+    let worker_threads = config.worker_threads;
 
     let server = async move {
-        let incoming = match config.bind.protocol {
+        // The HTTP server runs independently of the GELF server
+        // so it can report on its health while it's busy
+        let (http, http_addr) = match http_addr {
+            Some(http_addr) => {
+                let http = http::Server::bind(&http_addr).await?;
+                let http_addr = http.local_addr()?;
+
+                (
+                    Some(tokio::spawn(http.serve(state.clone()))),
+                    Some(http_addr),
+                )
+            }
+            None => (None, None),
+        };
+
+        let (incoming, addr) = match config.bind.protocol {
             Protocol::Udp => {
                 if config.certificate.is_some() {
                     bail!("TLS is not supported when the protocol is UDP")
                 }
 
-                let server = udp::Server::bind(&addr).await?.build(receive);
+                let server = udp::Server::bind(&addr).await?;
+                let addr = server.local_addr()?;
 
-                Either::Left(server)
+                (Either::Left(server.build(receive, shutdown.clone())), addr)
             }
             Protocol::Tcp => {
-                let tls_config = if let Some(Certificate {
-                    path,
-                    private_key_path,
-                }) = config.certificate
-                {
+                let tls_config = if let Some(ref certificate) = config.certificate {
                     emit("Using TLS");
 
-                    let mut reader = BufReader::new(File::open(&path)?);
-                    let cert = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
-
-                    let mut reader = BufReader::new(File::open(&private_key_path)?);
-                    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
-                        .collect::<Result<Vec<_>, _>>()?;
-
-                    if keys.len() == 0 {
-                        bail!(format!("The file `{}` used for the certificate private key doesn't contain any PKCS8 keys", private_key_path));
-                    }
-
-                    let config = rustls::ServerConfig::builder()
-                        .with_no_client_auth()
-                        .with_single_cert(cert, keys.remove(0).into())?;
-
-                    Some(config)
+                    Some(load_tls(certificate)?)
                 } else {
                     None
                 };
 
-                let server = tcp::Server::bind(&addr).await?.build(
-                    Duration::from_secs(config.tcp_keep_alive_secs),
-                    config.tcp_max_size_bytes as usize,
-                    tls_config,
-                    receive,
-                );
+                let conn = tcp::Conn {
+                    idle_timeout: config.tcp_idle_timeout(),
+                    frame_timeout: Duration::from_secs(config.tcp_frame_timeout_secs),
+                    min_bytes_per_sec: config.tcp_min_bytes_per_sec,
+                    keep_alive: config.tcp_os_keep_alive_secs.map(Duration::from_secs),
+                    max_size_bytes: config.tcp_max_size_bytes as usize,
+                };
+
+                let server = tcp::Server::bind(&addr).await?;
+                let addr = server.local_addr()?;
 
-                Either::Right(server)
+                (
+                    Either::Right(server.build(conn, tls_config, receive, shutdown.clone())),
+                    addr,
+                )
             }
         };
 
-        let mut close = handle_rx.fuse();
-        let mut ctrl_c = ctrl_c().boxed().fuse();
+        state.set_bound(true);
+        emit_with("Listening on {addr}", ListenProperties { addr, http_addr });
+
+        // NOTE: There may not be any receivers for the bound addresses
+        let _ = local_addrs_tx.send(Some(LocalAddrs {
+            gelf: addr,
+            http: http_addr,
+        }));
+
+        // NOTE: If the handle is dropped without being closed
+        // then the server keeps running
+        let mut close = async move {
+            if handle_rx.await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+        .boxed()
+        .fuse();
+        let mut terminate = terminate().boxed().fuse();
         let mut incoming = incoming.fuse();
+        let mut in_flight = FuturesUnordered::new();
+        let mut changed = state.changed.notified().boxed().fuse();
 
         // NOTE: We don't use `?` here because we never want to carry results
         // We always want to match them and deal with error cases directly
-        loop {
+        let drain = loop {
+            // NOTE: While the maximum number of messages are in-flight we stop
+            // receiving, so backpressure is applied to clients
+            let saturated = in_flight.len() >= state.max_in_flight();
+            state.set_saturated(saturated);
+            state.set_in_flight(in_flight.len());
+
             select! {
                 // A message that's ready to process
-                msg = incoming.next() => match msg {
-                    // A complete message has been received
-                    Some(Ok(Received::Complete(msg))) => {
-                        increment!(server.receive_ok);
-
-                        // Process the received message
-                        match process(msg) {
-                            Ok(()) => {
-                                increment!(server.process_ok);
-                            }
-                            Err(err) => {
-                                increment!(server.process_err);
-                                emit_err(err.as_ref(), "GELF processing failed");
-                            }
-                        }
-                    },
-                    // A chunk of a message has been received
-                    Some(Ok(Received::Incomplete)) => {
-                        continue;
-                    },
-                    // An error occurred receiving a chunk
-                    Some(Ok(Received::Error(err))) => {
-                        increment!(server.receive_err);
-                        emit_err(err.as_ref(), "GELF processing failed");
-                        continue;
-                    }
-                    // An unrecoverable error occurred receiving a chunk
-                    Some(Err(err)) => {
-                        increment!(server.receive_err);
-                        emit_err(err.as_ref(), "GELF processing failed irrecoverably");
-                        break;
+                msg = next_or_pending(&mut incoming, saturated || state.is_paused()) => match msg {
+                    Some(msg) => if receive_msg(msg, &mut capture, &dead_letter, &mut process, &mut in_flight).is_break() {
+                        break false;
                     },
                     None => {
                         unreachable!("receiver stream should never terminate")
                     },
                 },
+                // A message has finished processing
+                _ = in_flight.select_next_some() => (),
+                // The server was paused, resumed, or its limits changed
+                _ = changed => {
+                    changed = state.changed.notified().boxed().fuse();
+                },
                 // A termination signal from the programmatic handle
                 _ = close => {
                     emit("Handle closed; shutting down");
-                    break;
+                    break true;
                 },
                 // A termination signal from the environment
-                _ = ctrl_c => {
-                    emit("Termination signal received; shutting down");
-                    break;
+                _ = terminate => {
+                    emit_info("Termination signal received; shutting down");
+                    break true;
                 },
             };
+        };
+
+        // Stop accepting new work and wait for any messages
+        // that are already in-flight to be processed
+        if drain {
+            emit("Draining in-flight messages");
+            state.set_bound(false);
+            shutdown.cancel();
+
+            let mut timeout = sleep(Duration::from_secs(config.shutdown_timeout_secs))
+                .boxed()
+                .fuse();
+
+            let mut received_all = false;
+
+            loop {
+                // All in-flight messages have been drained
+                if received_all && in_flight.is_empty() {
+                    emit("Drained in-flight messages");
+                    break;
+                }
+
+                // NOTE: A paused server still drains on shutdown
+                let saturated = received_all || in_flight.len() >= state.max_in_flight();
+                state.set_in_flight(in_flight.len());
+
+                select! {
+                    // A message that was in-flight
+                    msg = next_or_pending(&mut incoming, saturated) => match msg {
+                        Some(msg) => if receive_msg(msg, &mut capture, &dead_letter, &mut process, &mut in_flight).is_break() {
+                            break;
+                        },
+                        // All in-flight messages have been received
+                        None => received_all = true,
+                    },
+                    // A message has finished processing
+                    _ = in_flight.select_next_some() => (),
+                    // The shutdown timeout has elapsed
+                    _ = timeout => {
+                        emit_warn("Shutdown timeout elapsed; dropping in-flight messages");
+                        break;
+                    },
+                };
+            }
+        }
+
+        state.set_in_flight(0);
+
+        // Wait for any dead letters that are still queued to be written
+        // NOTE: Any messages left in-flight hold on to the dead-letter
+        // writer, so they need to be dropped before it will finish
+        drop(in_flight);
+        drop(dead_letter);
+        if let Some(worker) = dead_letter_worker {
+            if spawn_blocking(move || worker.join()).await.is_err() {
+                emit_warn("Failed to finish writing GELF dead letters");
+            }
+        }
+
+        if let Some(http) = http {
+            http.abort();
         }
 
-        emit("Stopping GELF server");
+        emit_info("Stopping GELF server");
 
         Result::Ok::<(), Error>(())
     };
 
     Ok(Server {
-        fut: Box::pin(async move {
-            if let Err(err) = server.await {
-                emit_err(err.as_ref(), "GELF server failed");
-            }
-        }),
+        fut: Box::pin(server),
         handle,
+        worker_threads,
     })
 }
 
+/**
+Load a TLS configuration from a certificate.
+*/
+fn load_tls(certificate: &Certificate) -> Result<rustls::ServerConfig, Error> {
+    let mut reader = BufReader::new(File::open(&certificate.path)?);
+    let cert = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    let mut reader = BufReader::new(File::open(&certificate.private_key_path)?);
+    let mut keys =
+        rustls_pemfile::pkcs8_private_keys(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        bail!(format!(
+            "The file `{}` used for the certificate private key doesn't contain any PKCS8 keys",
+            certificate.private_key_path
+        ));
+    }
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert, keys.remove(0).into())?;
+
+    Ok(config)
+}
+
+/**
+Check that a server could be built from the given configuration.
+
+This validates addresses and loads any TLS certificate without binding any sockets.
+*/
+pub fn check(config: &Config) -> Result<(), Error> {
+    config.bind.addr.parse::<SocketAddr>().map_err(|e| {
+        Error::from(e).context(format!("invalid bind address `{}`", config.bind.addr))
+    })?;
+
+    if let Some(ref http_bind) = config.http_bind {
+        http_bind
+            .parse::<SocketAddr>()
+            .map_err(|e| Error::from(e).context(format!("invalid HTTP address `{}`", http_bind)))?;
+    }
+
+    if let Some(ref certificate) = config.certificate {
+        if let Protocol::Udp = config.bind.protocol {
+            bail!("TLS is not supported when the protocol is UDP")
+        }
+
+        load_tls(certificate).map_err(|e| {
+            e.context(format!(
+                "invalid certificate `{}` or private key `{}`",
+                certificate.path, certificate.private_key_path
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+/**
+Get the next item from a stream, unless the server is saturated.
+
+A saturated server doesn't poll the stream, so it won't receive any
+more messages until one that's in-flight completes.
+*/
+fn next_or_pending<S>(
+    stream: &mut S,
+    saturated: bool,
+) -> impl FusedFuture<Output = Option<S::Item>> + '_
+where
+    S: FusedStream + Unpin,
+{
+    if saturated {
+        Either::Left(future::pending())
+    } else {
+        Either::Right(stream.next())
+    }
+}
+
+/**
+Handle a message received from the network.
+
+A complete message is processed concurrently with any others in-flight.
+If an unrecoverable error is encountered then the server should stop.
+*/
+fn receive_msg<F>(
+    msg: Result<Received, Error>,
+    capture: &mut Option<capture::Writer>,
+    dead_letter: &Option<DeadLetter>,
+    process: &mut impl FnMut(Message) -> F,
+    in_flight: &mut FuturesUnordered<BoxFuture<'static, ()>>,
+) -> ControlFlow<()>
+where
+    F: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    if let (Some(capture), Ok(received)) = (capture, &msg) {
+        capture_msg(capture, received.source());
+    }
+
+    match msg {
+        // A complete message has been received
+        Ok(Received::Complete(msg, source)) => {
+            increment!(server.receive_ok);
+
+            // NOTE: The message is kept before it's processed so it can be
+            // previewed or written as a dead letter if processing fails.
+            // Its chunks are only joined back together if it does fail
+            let dead_letter = dead_letter.clone();
+            let kept = (dead_letter.is_some() || diagnostics::includes_payload_preview())
+                .then(|| msg.clone());
+
+            // Process the received message
+            let processed = process(msg);
+
+            in_flight.push(
+                async move {
+                    let processed = processed.await;
+
+                    observe!(server.process_latency_seconds, source.received_at.elapsed());
+
+                    match processed {
+                        Ok(()) => {
+                            increment!(server.process_ok);
+                        }
+                        Err(err) => {
+                            increment!(server.process_err);
+                            emit_err_with(
+                                err.as_ref(),
+                                "GELF processing failed",
+                                source.properties(kept.as_ref()),
+                            );
+
+                            if let (Some(dead_letter), Some(msg)) = (dead_letter, kept) {
+                                dead_letter_msg(
+                                    &dead_letter,
+                                    &source,
+                                    dead_letter::Stage::Process,
+                                    &err,
+                                    &msg.to_bytes(),
+                                );
+                            }
+                        }
+                    }
+                }
+                .boxed(),
+            );
+
+            ControlFlow::Continue(())
+        }
+        // A chunk of a message has been received
+        Ok(Received::Incomplete(_)) => ControlFlow::Continue(()),
+        // An error occurred receiving a chunk
+        Ok(Received::Error(err, source)) => {
+            increment!(server.receive_err);
+            emit_err_with(
+                err.as_ref(),
+                "GELF processing failed",
+                source.properties(None),
+            );
+
+            if let Some(dead_letter) = dead_letter {
+                dead_letter_msg(
+                    dead_letter,
+                    &source,
+                    dead_letter::Stage::Receive,
+                    &err,
+                    &source.payload,
+                );
+            }
+
+            ControlFlow::Continue(())
+        }
+        // An unrecoverable error occurred receiving a chunk
+        Err(err) => {
+            increment!(server.receive_err);
+            emit_err(err.as_ref(), "GELF processing failed irrecoverably");
+
+            ControlFlow::Break(())
+        }
+    }
+}
+
+/**
+Append the raw payload of a received message to a capture file.
+
+A failure to capture doesn't stop the message from being processed.
+*/
+fn capture_msg(capture: &mut capture::Writer, source: &Source) {
+    let record = capture::Record::new(
+        source.timestamp,
+        source.peer,
+        source.protocol.as_str(),
+        &source.payload,
+    );
+
+    if let Err(err) = capture.write(&record) {
+        emit_warn_err(err.as_ref(), "Failed to capture GELF payload");
+    }
+}
+
+/**
+A writer for messages that fail to be received or processed.
+
+Records are written on a dedicated thread instead of `tokio`
+so that file I/O doesn't block the runtime.
+*/
+#[derive(Clone)]
+struct DeadLetter {
+    tx: mpsc::Sender<dead_letter::Record>,
+}
+
+impl DeadLetter {
+    /**
+    Start writing dead letters on a new thread.
+
+    The thread finishes once every clone of the returned writer has been
+    dropped and any records still queued have been written.
+    */
+    fn spawn(mut writer: dead_letter::Writer) -> (Self, thread::JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel::<dead_letter::Record>();
+
+        let worker = thread::spawn(move || {
+            for record in rx {
+                match writer.write(&record) {
+                    Ok(()) => {
+                        increment!(server.dead_letter_msg);
+                    }
+                    Err(err) => {
+                        increment!(server.dead_letter_err);
+                        emit_warn_err(err.as_ref(), "Failed to write GELF dead letter");
+                    }
+                }
+            }
+        });
+
+        (DeadLetter { tx }, worker)
+    }
+}
+
+/**
+Queue the raw payload of a failed message to be written to the dead-letter directory.
+
+A failure to write the message is reported but otherwise ignored.
+*/
+fn dead_letter_msg(
+    dead_letter: &DeadLetter,
+    source: &Source,
+    stage: dead_letter::Stage,
+    err: &Error,
+    payload: &[u8],
+) {
+    let record = dead_letter::Record::new(
+        source.timestamp,
+        source.peer,
+        source.protocol.as_str(),
+        stage,
+        err,
+        payload,
+    );
+
+    // NOTE: The writer thread only stops early if it panicked
+    if dead_letter.tx.send(record).is_err() {
+        increment!(server.dead_letter_err);
+        emit_warn("Failed to write GELF dead letter; the dead-letter writer has stopped");
+    }
+}
+
+#[derive(Serialize)]
+struct ListenProperties {
+    addr: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_addr: Option<SocketAddr>,
+}
+
+#[derive(Serialize)]
+struct CaptureProperties<'a> {
+    capture_path: &'a str,
+}
+
+#[derive(Serialize)]
+struct DeadLetterProperties<'a> {
+    dead_letter_path: &'a str,
+}
+
+/**
+Wait for a termination signal from the environment.
+
+This is either a ctrl-c, or a `SIGTERM` on platforms that support it.
+*/
+async fn terminate() {
+    #[cfg(unix)]
+    let sigterm = async {
+        use tokio::signal::unix::{
+            signal,
+            SignalKind,
+        };
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                emit_warn_err(&err, "Failed to listen for SIGTERM");
+                future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let sigterm = future::pending::<()>();
+
+    select! {
+        _ = ctrl_c().fuse() => (),
+        _ = sigterm.boxed().fuse() => (),
+    }
+}
+
+/**
+A stream that completes once shutdown has started and it has no
+more items that are immediately ready.
+*/
+struct Drain<S> {
+    stream: S,
+    shutdown: CancellationToken,
+}
+
+impl<S> Stream for Drain<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let unpinned = Pin::into_inner(self);
+
+        match Pin::new(&mut unpinned.stream).poll_next(cx) {
+            Poll::Ready(item) => Poll::Ready(item),
+            // If we're shutting down then there's nothing left to drain
+            Poll::Pending if unpinned.shutdown.is_cancelled() => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+trait StreamDrainExt: Stream {
+    fn drain(self, shutdown: CancellationToken) -> Drain<Self>
+    where
+        Self: Sized + Unpin,
+    {
+        Drain {
+            stream: self,
+            shutdown,
+        }
+    }
+}
+
+impl<S> StreamDrainExt for S where S: Stream {}
+
 #[derive(Debug)]
 enum Received {
-    Incomplete,
-    Complete(Message),
-    Error(Error),
+    Incomplete(Source),
+    Complete(Message, Source),
+    Error(Error, Source),
+}
+
+impl Received {
+    fn with_peer(self, peer: SocketAddr) -> Self {
+        match self {
+            Received::Incomplete(source) => Received::Incomplete(source.with_peer(peer)),
+            Received::Complete(msg, source) => {
+                Received::Complete(msg.with_peer(peer), source.with_peer(peer))
+            }
+            Received::Error(err, source) => Received::Error(err, source.with_peer(peer)),
+        }
+    }
+
+    fn source(&self) -> &Source {
+        match self {
+            Received::Incomplete(source) => source,
+            Received::Complete(_, source) => source,
+            Received::Error(_, source) => source,
+        }
+    }
+}
+
+/**
+The maximum number of bytes of a payload to include in diagnostics.
+*/
+const MAX_PREVIEW_BYTES: usize = 256;
+
+/**
+Where a received message came from.
+
+This is carried alongside messages so diagnostics can describe them.
+*/
+#[derive(Debug, Clone)]
+struct Source {
+    received_at: Instant,
+    timestamp: DateTime<Utc>,
+    peer: Option<SocketAddr>,
+    protocol: Protocol,
+    payload: Bytes,
+}
+
+impl Source {
+    fn new(protocol: Protocol, peer: Option<SocketAddr>, payload: Bytes) -> Self {
+        Source {
+            received_at: Instant::now(),
+            timestamp: Utc::now(),
+            peer,
+            protocol,
+            payload,
+        }
+    }
+
+    fn with_peer(self, peer: SocketAddr) -> Self {
+        Source {
+            peer: Some(peer),
+            ..self
+        }
+    }
+
+    /**
+    Properties describing the source to attach to diagnostic events.
+
+    A preview of the payload is only included if it's enabled in diagnostics.
+    */
+    fn properties(&self, msg: Option<&Message>) -> SourceProperties {
+        SourceProperties {
+            peer: self.peer.map(|peer| peer.to_string()),
+            protocol: self.protocol.as_str(),
+            payload_size: self.payload.len(),
+            payload_preview: if diagnostics::includes_payload_preview() {
+                Some(self.preview(msg))
+            } else {
+                None
+            },
+        }
+    }
+
+    /**
+    A preview of the payload.
+
+    If the complete message is available then its decoded contents are previewed,
+    otherwise the raw datagram or frame is.
+    */
+    fn preview(&self, msg: Option<&Message>) -> String {
+        msg.and_then(|msg| {
+            let mut decoded = Vec::new();
+
+            msg.clone()
+                .into_reader()
+                .ok()?
+                .take(MAX_PREVIEW_BYTES as u64 + 1)
+                .read_to_end(&mut decoded)
+                .ok()?;
+
+            Some(preview(&decoded))
+        })
+        .unwrap_or_else(|| preview(&self.payload))
+    }
+}
+
+#[derive(Serialize)]
+struct SourceProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    protocol: &'static str,
+    payload_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_preview: Option<String>,
+}
+
+/**
+A lossy, truncated, textual representation of a payload.
+*/
+fn preview(payload: &[u8]) -> String {
+    let truncated = &payload[..cmp::min(payload.len(), MAX_PREVIEW_BYTES)];
+    let mut preview = String::from_utf8_lossy(truncated).into_owned();
+
+    if truncated.len() < payload.len() {
+        preview.push('…');
+    }
+
+    preview
 }
 
 trait OptionMessageExt {
-    fn into_received(self) -> Option<Received>;
+    fn into_received(self, source: Source) -> Option<Received>;
 }
 
 impl OptionMessageExt for Result<Option<Message>, Error> {
-    fn into_received(self) -> Option<Received> {
+    fn into_received(self, source: Source) -> Option<Received> {
         match self {
-            Ok(Some(msg)) => Some(Received::Complete(msg)),
-            Ok(None) => Some(Received::Incomplete),
-            Err(err) => Some(Received::Error(err)),
+            Ok(Some(msg)) => {
+                let msg = match source.peer {
+                    Some(peer) => msg.with_peer(peer),
+                    None => msg,
+                };
+
+                Some(Received::Complete(msg, source))
+            }
+            Ok(None) => Some(Received::Incomplete(source)),
+            Err(err) => Some(Received::Error(err, source)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_properties_exclude_the_preview_by_default() {
+        let payload = Bytes::from(vec![b'a'; MAX_PREVIEW_BYTES + 10]);
+        let source = Source::new(Protocol::Tcp, None, payload).with_peer(
+            "127.0.0.1:12201"
+                .parse()
+                .expect("failed to parse socket address"),
+        );
+
+        let properties = serde_json::to_value(source.properties(None)).expect("infallible JSON");
+
+        assert_eq!("127.0.0.1:12201", properties["peer"]);
+        assert_eq!("tcp", properties["protocol"]);
+        assert_eq!(MAX_PREVIEW_BYTES + 10, properties["payload_size"]);
+        assert!(properties.get("payload_preview").is_none());
+    }
+
+    #[test]
+    fn preview_is_truncated() {
+        let payload = Bytes::from(vec![b'a'; MAX_PREVIEW_BYTES + 10]);
+        let source = Source::new(Protocol::Tcp, None, payload);
+
+        assert_eq!(
+            format!("{}…", "a".repeat(MAX_PREVIEW_BYTES)),
+            source.preview(None)
+        );
+    }
+
+    #[test]
+    fn preview_decodes_complete_messages() {
+        let chunks: [&[u8]; 2] = [
+            b"\x1e\x0f\x00\x00\x00\x00\x00\x00\x00\x01\x00\x02{\"host\":\"a\",",
+            b"\x1e\x0f\x00\x00\x00\x00\x00\x00\x00\x01\x01\x02\"short_message\":\"1\"}",
+        ];
+
+        let mut receive = crate::receive::build(Default::default());
+
+        assert!(receive
+            .decode(Bytes::from_static(chunks[0]))
+            .expect("failed to decode")
+            .is_none());
+        let msg = receive
+            .decode(Bytes::from_static(chunks[1]))
+            .expect("failed to decode")
+            .expect("missing message");
+
+        let source = Source::new(Protocol::Udp, None, Bytes::from_static(chunks[1]));
+
+        assert_eq!(
+            r#"{"host":"a","short_message":"1"}"#,
+            source.preview(Some(&msg))
+        );
+    }
+
+    #[test]
+    fn preview_is_lossy() {
+        assert_eq!("a\u{fffd}b", preview(b"a\xffb"));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn tcp_keep_alive_secs_overrides_the_idle_timeout() {
+        let mut config = Config::default();
+        assert_eq!(
+            Duration::from_secs(config.tcp_idle_timeout_secs),
+            config.tcp_idle_timeout()
+        );
+
+        config.tcp_keep_alive_secs = 120;
+        assert_eq!(Duration::from_secs(120), config.tcp_idle_timeout());
+    }
+}
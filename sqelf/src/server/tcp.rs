@@ -12,7 +12,9 @@ use crate::{
     receive::Message,
     server::{
         OptionMessageExt,
+        Protocol,
         Received,
+        Source,
     },
 };
 
@@ -51,21 +53,46 @@ use tokio::{
         TcpStream,
     },
     time::{
+        sleep_until,
         timeout,
-        Timeout,
+        Instant,
+        Sleep,
     },
 };
 use tokio_rustls::rustls::ServerConfig;
 
-use tokio_util::codec::{
-    Decoder,
-    FramedRead,
+use socket2::{
+    SockRef,
+    TcpKeepalive,
+};
+
+use tokio_util::{
+    codec::{
+        Decoder,
+        FramedRead,
+    },
+    sync::{
+        CancellationToken,
+        WaitForCancellationFutureOwned,
+    },
 };
 
 use tokio_rustls::TlsAcceptor;
 
 pub(super) struct Server(TcpIncoming);
 
+/**
+Settings for individual client connections.
+*/
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Conn {
+    pub(super) idle_timeout: Duration,
+    pub(super) frame_timeout: Duration,
+    pub(super) min_bytes_per_sec: u64,
+    pub(super) keep_alive: Option<Duration>,
+    pub(super) max_size_bytes: usize,
+}
+
 impl Server {
     pub(super) async fn bind(addr: &SocketAddr) -> Result<Self, Error> {
         let listener = TcpListener::bind(&addr).await?;
@@ -73,10 +100,13 @@ impl Server {
         Ok(Server(TcpIncoming(listener)))
     }
 
+    pub(super) fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok((self.0).0.local_addr()?)
+    }
+
     pub(super) fn build(
         self,
-        keep_alive: Duration,
-        max_size_bytes: usize,
+        conn_config: Conn,
         tls: Option<ServerConfig>,
         receive: impl FnMut(Bytes) -> Result<Option<Message>, Error>
             + Send
@@ -84,35 +114,39 @@ impl Server {
             + Unpin
             + Clone
             + 'static,
+        shutdown: CancellationToken,
     ) -> impl Stream<Item = Result<Received, Error>> {
         emit("Setting up for TCP");
 
         if let Some(tls) = tls {
             let tls = TlsAcceptor::from(Arc::new(tls));
+            let conn_shutdown = shutdown.clone();
 
             self.0
                 .filter_map(move |conn| {
                     Box::pin(accept_tls(
                         conn,
                         tls.clone(),
-                        keep_alive.clone(),
-                        max_size_bytes,
+                        conn_config,
                         receive.clone(),
+                        conn_shutdown.clone(),
                     ))
                 })
-                .listen(1024)
+                .listen(1024, shutdown)
                 .boxed()
         } else {
+            let conn_shutdown = shutdown.clone();
+
             self.0
                 .filter_map(move |conn| {
                     Box::pin(accept(
                         conn,
-                        keep_alive.clone(),
-                        max_size_bytes,
+                        conn_config,
                         receive.clone(),
+                        conn_shutdown.clone(),
                     ))
                 })
-                .listen(1024)
+                .listen(1024, shutdown)
                 .boxed()
         }
     }
@@ -121,23 +155,34 @@ impl Server {
 async fn accept_tls(
     conn: Result<TcpStream, io::Error>,
     tls: TlsAcceptor,
-    keep_alive: Duration,
-    max_size_bytes: usize,
+    conn_config: Conn,
     receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Send + Sync + Unpin + Clone + 'static,
+    shutdown: CancellationToken,
 ) -> Option<impl Stream<Item = Result<Received, Error>>> {
     match conn {
         // The connection was successfully established
         // Create a new protocol reader over it
         // It'll get added to the connection pool
         Ok(conn) => {
-            let conn = tls.accept(conn).await;
+            set_keep_alive(&conn, conn_config);
+            let peer = conn.peer_addr().ok();
+
+            // NOTE: The handshake is bounded by the frame timeout
+            // so a client that stalls partway through it can't
+            // hold up accepting other connections
+            let conn = timeout(conn_config.frame_timeout, tls.accept(conn)).await;
 
             match conn {
-                Ok(conn) => {
-                    accept_protocol(QuietClose::new(conn), keep_alive, max_size_bytes, receive)
+                Ok(Ok(conn)) => {
+                    accept_protocol(QuietClose::new(conn), peer, conn_config, receive, shutdown)
                         .await
                 }
-                Err(_) => None,
+                Ok(Err(_)) => None,
+                Err(_) => {
+                    increment!(server.tcp_conn_frame_timeout);
+
+                    None
+                }
             }
         }
         // The connection could not be established
@@ -148,15 +193,20 @@ async fn accept_tls(
 
 async fn accept(
     conn: Result<TcpStream, io::Error>,
-    keep_alive: Duration,
-    max_size_bytes: usize,
+    conn_config: Conn,
     receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Send + Sync + Unpin + Clone + 'static,
+    shutdown: CancellationToken,
 ) -> Option<impl Stream<Item = Result<Received, Error>>> {
     match conn {
         // The connection was successfully established
         // Create a new protocol reader over it
         // It'll get added to the connection pool
-        Ok(conn) => accept_protocol(conn, keep_alive, max_size_bytes, receive).await,
+        Ok(conn) => {
+            set_keep_alive(&conn, conn_config);
+            let peer = conn.peer_addr().ok();
+
+            accept_protocol(conn, peer, conn_config, receive, shutdown).await
+        }
         // The connection could not be established
         // Just ignore it
         Err(_) => None,
@@ -165,18 +215,29 @@ async fn accept(
 
 async fn accept_protocol(
     conn: impl AsyncRead + Unpin,
-    keep_alive: Duration,
-    max_size_bytes: usize,
+    peer: Option<SocketAddr>,
+    conn_config: Conn,
     receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Send + Sync + Unpin + Clone + 'static,
+    shutdown: CancellationToken,
 ) -> Option<impl Stream<Item = Result<Received, Error>>> {
-    let decode = Decode::new(max_size_bytes, receive.clone());
-    let protocol = FramedRead::new(conn, decode);
+    let decode = Decode::new(conn_config.max_size_bytes, peer, receive.clone());
+    let protocol = FramedRead::new(CountRead::new(conn), decode);
 
     // NOTE: The timeout stream wraps _the protocol_
-    // That means it'll close the connection if it doesn't
-    // produce a valid message within the timeframe, not just
-    // whether or not it writes to the stream
-    Some(TimeoutStream::new(protocol, keep_alive))
+    // That means it can tell the difference between a connection
+    // that's idle between messages and one that's stalled partway
+    // through a message
+    Some(TimeoutStream::new(protocol, conn_config, shutdown))
+}
+
+fn set_keep_alive(conn: &TcpStream, conn_config: Conn) {
+    if let Some(keep_alive) = conn_config.keep_alive {
+        let keep_alive = TcpKeepalive::new().with_time(keep_alive);
+
+        if let Err(err) = SockRef::from(conn).set_tcp_keepalive(&keep_alive) {
+            emit_warn_err(&err, "Failed to set TCP keepalive");
+        }
+    }
 }
 
 /**
@@ -233,6 +294,52 @@ impl<R: AsyncRead> AsyncRead for QuietClose<R> {
     }
 }
 
+/**
+A wrapper around a reader that tracks how much data has been read from it.
+*/
+struct CountRead<R> {
+    inner: R,
+    read_bytes: u64,
+    last_read: Instant,
+}
+
+impl<R> CountRead<R> {
+    fn new(read: R) -> Self {
+        CountRead {
+            inner: read,
+            read_bytes: 0,
+            last_read: Instant::now(),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountRead<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let unpinned = Pin::into_inner(self);
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut unpinned.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let read = buf.filled().len() - filled;
+
+                if read > 0 {
+                    increment!(server.tcp_bytes_received += read);
+
+                    unpinned.read_bytes += read as u64;
+                    unpinned.last_read = Instant::now();
+                }
+
+                Poll::Ready(Ok(()))
+            }
+            r => r,
+        }
+    }
+}
+
 /**
 An active set of connections that are processed fairly.
 */
@@ -241,9 +348,10 @@ where
     S: Stream,
     S::Item: Stream,
 {
-    accept: Fuse<S>,
+    accept: Option<Fuse<S>>,
     connections: FuturesUnordered<StreamFuture<S::Item>>,
     max: usize,
+    shutdown: CancellationToken,
 }
 
 impl<S> Listen<S>
@@ -251,7 +359,6 @@ where
     S: Stream,
     S::Item: Stream,
 {
-    unsafe_pinned!(accept: Fuse<S>);
     unsafe_pinned!(connections: FuturesUnordered<StreamFuture<S::Item>>);
 }
 
@@ -263,10 +370,21 @@ where
     type Item = Result<T, Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        // If we're shutting down then stop accepting new connections
+        // Dropping the listener means new clients will be refused
+        if self.shutdown.is_cancelled() {
+            self.accept = None;
+        }
+
         'poll_conns: loop {
             // Fill up our accepted connections
             'fill_conns: while self.connections.len() < self.max {
-                let conn = match self.as_mut().accept().poll_next(cx) {
+                let accept = match self.accept.as_mut() {
+                    Some(accept) => accept,
+                    None => break 'fill_conns,
+                };
+
+                let conn = match Pin::new(accept).poll_next(cx) {
                     Poll::Ready(Some(s)) => s.into_future(),
                     Poll::Ready(None) | Poll::Pending => break 'fill_conns,
                 };
@@ -292,9 +410,9 @@ where
                         // An error occurred, probably IO-related
                         // In this case the connection isn't returned to the pool.
                         // It's closed on drop and the next connection will be polled.
-                        Err(err) => {
+                        // The connection has already emitted a diagnostic for the error.
+                        Err(_) => {
                             increment!(server.receive_err);
-                            emit_debug_err(err.as_ref(), "GELF TCP client failed");
 
                             continue 'poll_conns;
                         }
@@ -312,24 +430,28 @@ where
         // If we've gotten this far, then there are no events for us to process
         // and nothing was ready, so figure out if we're not done yet or if
         // we've reached the end.
-        if self.accept.is_done() {
-            Poll::Ready(None)
-        } else {
-            Poll::Pending
+        match self.accept {
+            Some(ref accept) if accept.is_done() => Poll::Ready(None),
+            Some(_) => Poll::Pending,
+            // If we've stopped accepting then we're done once
+            // all the connections have closed
+            None if self.connections.is_empty() => Poll::Ready(None),
+            None => Poll::Pending,
         }
     }
 }
 
 trait StreamListenExt: Stream {
-    fn listen(self, max_connections: usize) -> Listen<Self>
+    fn listen(self, max_connections: usize, shutdown: CancellationToken) -> Listen<Self>
     where
         Self: Sized + Unpin,
         Self::Item: Stream + Unpin,
     {
         Listen {
-            accept: self.fuse(),
+            accept: Some(self.fuse()),
             connections: FuturesUnordered::new(),
             max: max_connections,
+            shutdown,
         }
     }
 }
@@ -340,18 +462,51 @@ struct Decode<F> {
     max_size_bytes: usize,
     read_head: usize,
     discarding: bool,
+    peer: Option<SocketAddr>,
     receive: F,
 }
 
 impl<F> Decode<F> {
-    pub fn new(max_size_bytes: usize, receive: F) -> Self {
+    pub fn new(max_size_bytes: usize, peer: Option<SocketAddr>, receive: F) -> Self {
         Decode {
             read_head: 0,
             discarding: false,
             max_size_bytes,
+            peer,
             receive,
         }
     }
+
+    fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    fn overflow(&mut self, size: usize) {
+        increment!(server.tcp_msg_overflow);
+        emit_warn_with(
+            "GELF TCP message exceeded the maximum size and will be discarded",
+            ConnProperties {
+                peer: self.peer,
+                payload_size: Some(size),
+                ..ConnProperties::new()
+            },
+        );
+
+        self.discarding = true;
+    }
+
+    fn receive(&mut self, src: Bytes) -> Option<Received>
+    where
+        F: FnMut(Bytes) -> Result<Option<Message>, Error>,
+    {
+        let source = Source::new(Protocol::Tcp, self.peer, src.clone());
+
+        (self.receive)(src).into_received(source)
+    }
+
+    fn is_discarding(&self) -> bool {
+        self.discarding
+    }
 }
 
 impl<F> Decoder for Decode<F>
@@ -380,9 +535,7 @@ where
                     // for us, but since it's bigger than our max capacity
                     // we still discard it
                     if frame_end > self.max_size_bytes {
-                        increment!(server.tcp_msg_overflow);
-
-                        self.discarding = true;
+                        self.overflow(frame_end);
 
                         continue 'read_frame;
                     }
@@ -390,14 +543,12 @@ where
                     self.read_head = 0;
                     let src = src.split_to(frame_end + 1).freeze();
 
-                    return Ok((self.receive)(src.slice(..src.len() - 1)).into_received());
+                    return Ok(self.receive(src.slice(..src.len() - 1)));
                 }
                 // A delimiter wasn't found, but the incomplete
                 // message is too big. Start discarding the input
                 (false, None) if src.len() > self.max_size_bytes => {
-                    increment!(server.tcp_msg_overflow);
-
-                    self.discarding = true;
+                    self.overflow(src.len());
 
                     continue 'read_frame;
                 }
@@ -450,60 +601,224 @@ where
                     let src = src.split_to(src.len()).freeze();
                     self.read_head = 0;
 
-                    (self.receive)(src).into_received()
+                    self.receive(src)
                 }
             }
         })
     }
 }
 
-struct TimeoutStream<S> {
-    keep_alive: Duration,
-    stream: Pin<Box<Timeout<StreamFuture<S>>>>,
+/**
+Properties describing a connection to attach to diagnostic events.
+*/
+#[derive(Serialize)]
+struct ConnProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<SocketAddr>,
+    protocol: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_size: Option<usize>,
 }
 
-impl<S> TimeoutStream<S>
-where
-    S: Stream + Unpin,
-{
-    fn new(stream: S, keep_alive: Duration) -> Self {
+impl ConnProperties {
+    fn new() -> Self {
+        ConnProperties {
+            peer: None,
+            protocol: Protocol::Tcp.as_str(),
+            payload_size: None,
+        }
+    }
+}
+
+/**
+The grace period given to a message before checking its transfer rate.
+*/
+const MIN_RATE_GRACE: Duration = Duration::from_secs(1);
+
+/**
+A wrapper around a connection's protocol that closes it when a timeout elapses.
+
+A connection is idle when there's no message in progress. It's closed if
+no data arrives within the idle timeout. Once the first bytes of a message
+arrive it needs to be completed within the frame timeout, and data needs to
+keep arriving at the minimum rate.
+
+When the server is shutting down the connection is closed as soon as it's idle.
+*/
+struct TimeoutStream<R, F> {
+    conn: Conn,
+    stream: FramedRead<CountRead<R>, Decode<F>>,
+    frame: Option<Frame>,
+    expiry: Expiry,
+    sleep: Pin<Box<Sleep>>,
+    shutdown: CancellationToken,
+    shutdown_wait: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+/**
+A message that's in progress on a connection.
+*/
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    start: Instant,
+    start_read_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expiry {
+    Idle,
+    Frame,
+    TooSlow,
+}
+
+impl<R, F> TimeoutStream<R, F> {
+    fn new(
+        stream: FramedRead<CountRead<R>, Decode<F>>,
+        conn: Conn,
+        shutdown: CancellationToken,
+    ) -> Self {
         increment!(server.tcp_conn_accept);
+        gauge!(server.tcp_conn_active += 1);
+
+        emit_verbose_with(
+            "Accepted GELF TCP connection",
+            ConnProperties {
+                peer: stream.decoder().peer(),
+                ..ConnProperties::new()
+            },
+        );
+
+        let deadline = stream.get_ref().last_read + conn.idle_timeout;
 
         TimeoutStream {
-            keep_alive,
-            stream: Box::pin(timeout(keep_alive, stream.into_future())),
+            conn,
+            stream,
+            frame: None,
+            expiry: Expiry::Idle,
+            sleep: Box::pin(sleep_until(deadline)),
+            shutdown_wait: Box::pin(shutdown.clone().cancelled_owned()),
+            shutdown,
+        }
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context) -> bool {
+        self.shutdown.is_cancelled() || self.shutdown_wait.as_mut().poll(cx).is_ready()
+    }
+
+    fn reset_deadline(&mut self) {
+        let read = self.stream.get_ref();
+        let buffered = self.stream.read_buffer().len();
+
+        // A message is in progress if there's unprocessed data in the buffer
+        // or if the decoder is partway through discarding an oversized message
+        let in_progress = buffered > 0 || self.stream.decoder().is_discarding();
+
+        self.frame = match (in_progress, self.frame) {
+            (true, Some(frame)) => Some(frame),
+            (true, None) => Some(Frame {
+                start: Instant::now(),
+                start_read_bytes: read.read_bytes - buffered as u64,
+            }),
+            (false, _) => None,
+        };
+
+        let (deadline, expiry) = match self.frame {
+            Some(frame) => {
+                let frame_deadline = frame.start + self.conn.frame_timeout;
+
+                if self.conn.min_bytes_per_sec > 0 {
+                    let frame_read_bytes = read.read_bytes - frame.start_read_bytes;
+                    let rate_deadline = frame.start
+                        + MIN_RATE_GRACE
+                        + Duration::from_secs_f64(
+                            frame_read_bytes as f64 / self.conn.min_bytes_per_sec as f64,
+                        );
+
+                    if rate_deadline < frame_deadline {
+                        (rate_deadline, Expiry::TooSlow)
+                    } else {
+                        (frame_deadline, Expiry::Frame)
+                    }
+                } else {
+                    (frame_deadline, Expiry::Frame)
+                }
+            }
+            None => (read.last_read + self.conn.idle_timeout, Expiry::Idle),
+        };
+
+        self.expiry = expiry;
+        if self.sleep.deadline() != deadline {
+            self.sleep.as_mut().reset(deadline);
         }
     }
 }
 
-impl<S> Drop for TimeoutStream<S> {
+impl<R, F> Drop for TimeoutStream<R, F> {
     fn drop(&mut self) {
         increment!(server.tcp_conn_close);
+        gauge!(server.tcp_conn_active -= 1);
+
+        emit_verbose_with(
+            "Closed GELF TCP connection",
+            ConnProperties {
+                peer: self.stream.decoder().peer(),
+                ..ConnProperties::new()
+            },
+        );
     }
 }
 
-impl<S> Stream for TimeoutStream<S>
+impl<R, F> Stream for TimeoutStream<R, F>
 where
-    S: Stream + Unpin,
+    R: AsyncRead + Unpin,
+    F: FnMut(Bytes) -> Result<Option<Message>, Error> + Unpin,
 {
-    type Item = S::Item;
+    type Item = Result<Received, Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let unpinned = Pin::into_inner(self);
 
-        match Pin::new(&mut unpinned.stream).poll(cx) {
-            // The timeout has elapsed
-            Poll::Ready(Err(_)) => {
-                increment!(server.tcp_conn_timeout);
+        match Pin::new(&mut unpinned.stream).poll_next(cx) {
+            // The stream has produced an item
+            // Any message in progress has been completed
+            Poll::Ready(item) => {
+                unpinned.frame = None;
+                unpinned.reset_deadline();
+
+                if let Some(Err(ref err)) = item {
+                    emit_debug_err_with(
+                        err.as_ref(),
+                        "GELF TCP client failed",
+                        ConnProperties {
+                            peer: unpinned.stream.decoder().peer(),
+                            ..ConnProperties::new()
+                        },
+                    );
+                }
 
-                Poll::Ready(None)
+                return Poll::Ready(item);
             }
-            // The stream has produced an item
-            // The timeout is reset
-            Poll::Ready(Ok((item, stream))) => {
-                unpinned.stream = Box::pin(timeout(unpinned.keep_alive, stream.into_future()));
+            // The stream hasn't produced an item
+            // It may have read some more data though
+            Poll::Pending => unpinned.reset_deadline(),
+        }
+
+        // If we're shutting down and there's no message in progress
+        // then there's nothing left to drain from the connection
+        if unpinned.frame.is_none() && unpinned.poll_shutdown(cx) {
+            return Poll::Ready(None);
+        }
 
-                Poll::Ready(item)
+        match unpinned.sleep.as_mut().poll(cx) {
+            // The timeout has elapsed
+            Poll::Ready(()) => {
+                match unpinned.expiry {
+                    Expiry::Idle => increment!(server.tcp_conn_timeout),
+                    Expiry::Frame => increment!(server.tcp_conn_frame_timeout),
+                    Expiry::TooSlow => increment!(server.tcp_conn_too_slow),
+                }
+
+                Poll::Ready(None)
             }
             // The timeout hasn't elapsed and the stream hasn't produced an item
             Poll::Pending => Poll::Pending,
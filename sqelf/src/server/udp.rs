@@ -5,7 +5,10 @@ use crate::{
     receive::Message,
     server::{
         OptionMessageExt,
+        Protocol,
         Received,
+        Source,
+        StreamDrainExt,
     },
 };
 
@@ -25,6 +28,7 @@ use tokio::net::UdpSocket;
 
 use tokio_util::{
     codec::Decoder,
+    sync::CancellationToken,
     udp::UdpFramed,
 };
 
@@ -37,13 +41,22 @@ impl Server {
         Ok(Server(sock))
     }
 
+    pub(super) fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.0.local_addr()?)
+    }
+
     pub(super) fn build(
         self,
         receive: impl FnMut(Bytes) -> Result<Option<Message>, Error> + Unpin,
+        shutdown: CancellationToken,
     ) -> impl Stream<Item = Result<Received, Error>> {
         emit("Setting up for UDP");
 
-        UdpFramed::new(self.0, Decode(receive)).map(|r| r.map(|(msg, _)| msg))
+        // NOTE: On shutdown, we stop receiving once there are no more
+        // datagrams already buffered on the socket
+        UdpFramed::new(self.0, Decode(receive))
+            .map(|r| r.map(|(msg, peer)| msg.with_peer(peer)))
+            .drain(shutdown)
     }
 }
 
@@ -69,6 +82,10 @@ where
             return Ok(None);
         }
 
-        Ok((self.0)(src).into_received())
+        increment!(server.udp_bytes_received += src.len());
+
+        let source = Source::new(Protocol::Udp, None, src.clone());
+
+        Ok((self.0)(src).into_received(source))
     }
 }
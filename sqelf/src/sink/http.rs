@@ -0,0 +1,882 @@
+use std::{
+    mem,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[cfg(feature = "tls")]
+use std::convert::TryFrom;
+
+use futures::future::{
+    BoxFuture,
+    FutureExt,
+};
+
+use tokio::{
+    io::{
+        self,
+        AsyncBufRead,
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpStream,
+    runtime,
+    sync::Mutex as AsyncMutex,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+
+#[cfg(feature = "tls")]
+use tokio_rustls::{
+    rustls::{
+        pki_types::ServerName,
+        ClientConfig,
+    },
+    TlsConnector,
+};
+
+use crate::{
+    diagnostics::*,
+    process::clef,
+    sink::Sink,
+    Error,
+};
+
+/**
+The maximum size of a response head before the response is rejected.
+*/
+const MAX_RESPONSE_BYTES: usize = 8 * 1024;
+
+/**
+A sink that posts events to an HTTP endpoint as CLEF.
+
+Events are batched into newline-delimited CLEF bodies, like Seq's
+`/api/events/raw?clef` endpoint accepts. A batch is sent once it reaches
+`max_batch_bytes`, or once `max_batch_interval` has passed since its first
+event, whichever comes first. Connections are kept alive between requests.
+
+Since events are sent in batches, a failure to send is reported by the
+`emit` or `flush` that sent the batch, rather than for each event in it.
+Batches sent after an interval report failures as diagnostics instead.
+
+This isn't a general purpose HTTP client. It only inspects the status of
+responses, and reads just enough of them to reuse the connection.
+*/
+pub struct Http {
+    client: Client,
+    max_batch_bytes: usize,
+    max_batch_interval: Duration,
+}
+
+/**
+The parts of the sink that are shared with batches sent after an interval.
+*/
+#[derive(Clone)]
+struct Client {
+    endpoint: Arc<Endpoint>,
+    conn: Arc<AsyncMutex<Option<Conn>>>,
+    batch: Arc<Mutex<Batch>>,
+}
+
+#[derive(Clone)]
+struct Endpoint {
+    url: Url,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<ClientConfig>>,
+}
+
+#[derive(Default)]
+struct Batch {
+    body: Vec<u8>,
+    /**
+    Incremented each time a batch is taken, so a batch
+    is only sent once after its interval.
+    */
+    generation: u64,
+}
+
+impl Batch {
+    fn take(&mut self) -> Vec<u8> {
+        self.generation = self.generation.wrapping_add(1);
+        mem::take(&mut self.body)
+    }
+}
+
+/**
+A connection that can be kept alive between requests.
+*/
+type Conn = BufReader<Box<dyn Stream>>;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Url {
+    https: bool,
+    authority: String,
+    path: String,
+}
+
+/**
+The parts of a response needed to decide whether it succeeded.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Response {
+    status: u16,
+    keep_alive: bool,
+}
+
+impl Http {
+    /**
+    Create a sink that posts events to the given `http://` or `https://` URL.
+
+    An `https://` URL needs a TLS configuration set with `tls`.
+    */
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(Http {
+            client: Client {
+                endpoint: Arc::new(Endpoint {
+                    url: Url::parse(url)?,
+                    headers: Vec::new(),
+                    timeout: Duration::from_secs(30), // 30 seconds
+                    #[cfg(feature = "tls")]
+                    tls: None,
+                }),
+                conn: Arc::new(AsyncMutex::new(None)),
+                batch: Arc::new(Mutex::new(Batch::default())),
+            },
+            max_batch_bytes: 1024 * 1024,                   // 1MiB
+            max_batch_interval: Duration::from_millis(500), // 500 milliseconds
+        })
+    }
+
+    /**
+    Add a header to each request.
+
+    The name must be a valid HTTP header name, and neither the name nor the value
+    may contain line breaks, so they can't add other headers to the request.
+    */
+    pub fn header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let (name, value) = (name.into(), value.into());
+
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        {
+            bail!("the HTTP header name `{}` is invalid", name.escape_debug());
+        }
+
+        if value.contains(|c| c == '\r' || c == '\n' || c == '\0') {
+            bail!(
+                "the value of the HTTP header `{}` can't contain line breaks",
+                name
+            );
+        }
+
+        self.endpoint_mut().headers.push((name, value));
+        Ok(self)
+    }
+
+    /**
+    Add a Seq API key to each request.
+    */
+    pub fn api_key(self, api_key: impl Into<String>) -> Result<Self, Error> {
+        self.header("X-Seq-ApiKey", api_key)
+    }
+
+    /**
+    Set the maximum duration to spend sending a single request.
+    */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint_mut().timeout = timeout;
+        self
+    }
+
+    /**
+    Set the size a batch of events can grow to before it's sent.
+    */
+    pub fn max_batch_bytes(mut self, max_batch_bytes: usize) -> Self {
+        self.max_batch_bytes = max_batch_bytes;
+        self
+    }
+
+    /**
+    Set the maximum duration to wait after an event before sending its batch.
+    */
+    pub fn max_batch_interval(mut self, max_batch_interval: Duration) -> Self {
+        self.max_batch_interval = max_batch_interval;
+        self
+    }
+
+    /**
+    Set the TLS configuration to use for `https://` URLs.
+    */
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: Arc<ClientConfig>) -> Self {
+        self.endpoint_mut().tls = Some(config);
+        self
+    }
+
+    fn endpoint_mut(&mut self) -> &mut Endpoint {
+        Arc::make_mut(&mut self.client.endpoint)
+    }
+
+    /**
+    Send the batch after the interval, unless it's already been sent.
+    */
+    fn send_after_interval(&self, generation: u64) {
+        // NOTE: Without a runtime the batch is sent once it's full or flushed
+        if let Ok(runtime) = runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let interval = self.max_batch_interval;
+
+            runtime.spawn(async move {
+                sleep(interval).await;
+
+                if let Err(err) = client.send_batch(Some(generation)).await {
+                    emit_err(err.as_ref(), "Failed to send a batch of events over HTTP");
+                }
+            });
+        }
+    }
+}
+
+impl Client {
+    /**
+    Send the current batch, if it has any events in it.
+
+    If a generation is given then the batch is only sent if it hasn't been taken since.
+    */
+    async fn send_batch(&self, generation: Option<u64>) -> Result<(), Error> {
+        // NOTE: The batch is taken while the connection is held
+        // so batches are always sent in the order they were filled
+        let mut conn = self.conn.lock().await;
+
+        let body = {
+            let mut batch = self.batch.lock().unwrap_or_else(|e| e.into_inner());
+
+            match generation {
+                Some(generation) if generation != batch.generation => return Ok(()),
+                _ => batch.take(),
+            }
+        };
+
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        timeout(self.endpoint.timeout, self.endpoint.post(&mut conn, &body))
+            .await
+            .map_err(|_| anyhow!("the HTTP request timed out"))?
+    }
+}
+
+impl Endpoint {
+    async fn post(&self, conn: &mut Option<Conn>, body: &[u8]) -> Result<(), Error> {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/vnd.serilog.clef\r\nContent-Length: {}\r\n",
+            self.url.path,
+            self.url.authority,
+            body.len(),
+        );
+
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        request.push_str("\r\n");
+
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+
+        // NOTE: A connection that was kept alive may have since been
+        // closed by the endpoint, so failures on it are retried once
+        let (stream, response) = match conn.take() {
+            Some(mut stream) => match send(&mut stream, &request).await {
+                Ok(response) => (stream, response),
+                Err(_) => {
+                    let mut stream = self.connect().await?;
+                    let response = send(&mut stream, &request).await?;
+
+                    (stream, response)
+                }
+            },
+            None => {
+                let mut stream = self.connect().await?;
+                let response = send(&mut stream, &request).await?;
+
+                (stream, response)
+            }
+        };
+
+        if response.keep_alive {
+            *conn = Some(stream);
+        }
+
+        if !(200..300).contains(&response.status) {
+            bail!(
+                "the HTTP endpoint responded with status {}",
+                response.status
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn connect(&self) -> Result<Conn, Error> {
+        let stream = TcpStream::connect(self.url.addr()).await?;
+        stream.set_nodelay(true)?;
+
+        let stream: Box<dyn Stream> = if self.url.https {
+            self.connect_tls(stream).await?
+        } else {
+            Box::new(stream)
+        };
+
+        Ok(BufReader::new(stream))
+    }
+
+    #[cfg(feature = "tls")]
+    async fn connect_tls(&self, stream: TcpStream) -> Result<Box<dyn Stream>, Error> {
+        let config = self
+            .tls
+            .clone()
+            .ok_or_else(|| anyhow!("a TLS configuration is needed for `https://` URLs"))?;
+
+        let server_name = ServerName::try_from(self.url.host().to_owned())?;
+        let stream = TlsConnector::from(config)
+            .connect(server_name, stream)
+            .await?;
+
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    async fn connect_tls(&self, _: TcpStream) -> Result<Box<dyn Stream>, Error> {
+        bail!("`https://` URLs need the `tls` feature")
+    }
+}
+
+impl Sink for Http {
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut line = serde_json::to_vec(msg)?;
+            line.push(b'\n');
+
+            let is_full = {
+                let mut batch = self.client.batch.lock().unwrap_or_else(|e| e.into_inner());
+
+                // The first event in a batch starts its interval
+                if batch.body.is_empty() {
+                    self.send_after_interval(batch.generation);
+                }
+
+                batch.body.extend_from_slice(&line);
+                batch.body.len() >= self.max_batch_bytes
+            };
+
+            if is_full {
+                self.client.send_batch(None).await?;
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        self.client.send_batch(None).boxed()
+    }
+}
+
+/**
+Write a request and read its response.
+
+The body of the response is read and discarded so the connection can be reused.
+*/
+async fn send(stream: &mut Conn, request: &[u8]) -> Result<Response, Error> {
+    stream.get_mut().write_all(request).await?;
+    stream.get_mut().flush().await?;
+
+    let mut remaining = MAX_RESPONSE_BYTES;
+    let mut line = Vec::new();
+
+    read_line(stream, &mut line, &mut remaining).await?;
+    let status = parse_status(&line).ok_or_else(|| anyhow!("the HTTP response is invalid"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut close = false;
+
+    loop {
+        read_line(stream, &mut line, &mut remaining).await?;
+
+        let header = String::from_utf8_lossy(&line);
+        let header = header.trim_end();
+
+        // The head ends with an empty line
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("the HTTP response is invalid"))?,
+                );
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.to_ascii_lowercase().contains("chunked");
+            } else if name.eq_ignore_ascii_case("connection") {
+                close = value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    // NOTE: Some responses never have a body, whatever their headers say
+    let content_length = match status {
+        100..=199 | 204 | 304 => Some(0),
+        _ if chunked => None,
+        _ => content_length,
+    };
+
+    let keep_alive = match (content_length, chunked) {
+        (Some(content_length), _) => {
+            discard(stream, content_length).await?;
+            !close
+        }
+        (None, true) => {
+            discard_chunked(stream).await?;
+            !close
+        }
+        // The body ends when the connection is closed
+        (None, false) => {
+            io::copy(stream, &mut io::sink()).await?;
+            false
+        }
+    };
+
+    Ok(Response { status, keep_alive })
+}
+
+/**
+Read a single line of a response head, including its line ending.
+*/
+async fn read_line(
+    stream: &mut (impl AsyncBufRead + Unpin),
+    line: &mut Vec<u8>,
+    remaining: &mut usize,
+) -> Result<(), Error> {
+    line.clear();
+
+    let read = (&mut *stream)
+        .take(*remaining as u64)
+        .read_until(b'\n', line)
+        .await?;
+
+    if read == 0 {
+        bail!("the HTTP endpoint closed the connection without responding");
+    }
+
+    if !line.ends_with(b"\n") {
+        bail!("the HTTP response is too large");
+    }
+
+    *remaining -= read;
+
+    Ok(())
+}
+
+async fn discard(stream: &mut (impl AsyncBufRead + Unpin), len: u64) -> Result<(), Error> {
+    let discarded = io::copy(&mut (&mut *stream).take(len), &mut io::sink()).await?;
+
+    if discarded < len {
+        bail!("the HTTP endpoint closed the connection before the response was complete");
+    }
+
+    Ok(())
+}
+
+async fn discard_chunked(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<(), Error> {
+    let mut remaining = MAX_RESPONSE_BYTES;
+    let mut line = Vec::new();
+
+    loop {
+        read_line(stream, &mut line, &mut remaining).await?;
+
+        let size = String::from_utf8_lossy(&line);
+        let size = size.trim_end().split(';').next().unwrap_or_default();
+        let size = u64::from_str_radix(size.trim(), 16)
+            .map_err(|_| anyhow!("the HTTP response is invalid"))?;
+
+        // The last chunk is followed by any trailers and an empty line
+        if size == 0 {
+            loop {
+                read_line(stream, &mut line, &mut remaining).await?;
+
+                if line.trim_ascii().is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+
+        discard(stream, size).await?;
+        read_line(stream, &mut line, &mut remaining).await?;
+    }
+}
+
+fn parse_status(head: &[u8]) -> Option<u16> {
+    // NOTE: Only the status line is decoded, because the bytes
+    // after it may be part of a body that isn't UTF-8
+    let end = head
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .unwrap_or(head.len());
+    let status_line = std::str::from_utf8(&head[..end]).ok()?;
+
+    let mut parts = status_line.split(' ');
+
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+
+    parts.next()?.parse().ok()
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, Error> {
+        // NOTE: The URL is written into the request line as-is
+        if url.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            bail!(
+                "the URL `{}` can't contain whitespace or control characters",
+                url.escape_debug()
+            );
+        }
+
+        let (https, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            bail!("the URL `{}` must start with `http://` or `https://`", url);
+        };
+
+        let (authority, path) = match rest.find(|c| c == '/' || c == '?') {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_owned()),
+            None => (rest, "/".to_owned()),
+        };
+
+        if authority.is_empty() {
+            bail!("the URL `{}` is missing a host", url);
+        }
+
+        Ok(Url {
+            https,
+            authority: authority.to_owned(),
+            path,
+        })
+    }
+
+    fn host(&self) -> &str {
+        // An IPv6 host is bracketed, like `[::1]:5341`
+        if let Some(rest) = self.authority.strip_prefix('[') {
+            return rest.split(']').next().unwrap_or(rest);
+        }
+
+        self.authority.split(':').next().unwrap_or(&self.authority)
+    }
+
+    fn addr(&self) -> String {
+        let has_port = match self.authority.rfind(':') {
+            Some(i) => !self.authority[i..].contains(']'),
+            None => false,
+        };
+
+        if has_port {
+            self.authority.clone()
+        } else if self.https {
+            format!("{}:443", self.authority)
+        } else {
+            format!("{}:80", self.authority)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{
+            BufRead,
+            BufReader as StdBufReader,
+            Read,
+            Write,
+        },
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+    };
+
+    /**
+    Start an endpoint that responds to each request on a connection until it's closed.
+
+    The index of the connection each request was received on is sent along with its body.
+    */
+    fn endpoint() -> (String, mpsc::Receiver<(usize, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind endpoint");
+        let url = format!(
+            "http://{}/api/events/raw?clef",
+            listener.local_addr().expect("missing endpoint address")
+        );
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (index, conn) in listener.incoming().enumerate() {
+                let conn = conn.expect("failed to accept");
+                let tx = tx.clone();
+
+                thread::spawn(move || {
+                    let mut reader = StdBufReader::new(conn.try_clone().expect("failed to clone"));
+                    let mut writer = conn;
+
+                    loop {
+                        let mut content_length = None;
+                        let mut line = String::new();
+
+                        loop {
+                            line.clear();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+
+                            if line.trim_end().is_empty() {
+                                break;
+                            }
+
+                            if let Some(len) = line.strip_prefix("Content-Length: ") {
+                                content_length = len.trim().parse().ok();
+                            }
+                        }
+
+                        let mut body = vec![0; content_length.expect("missing content length")];
+                        reader.read_exact(&mut body).expect("failed to read body");
+
+                        writer
+                            .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\n{}")
+                            .expect("failed to write response");
+
+                        let _ = tx.send((index, String::from_utf8(body).expect("invalid body")));
+                    }
+                });
+            }
+        });
+
+        (url, rx)
+    }
+
+    fn message(json: &str) -> clef::Message {
+        serde_json::from_str(json).expect("invalid CLEF")
+    }
+
+    fn runtime() -> runtime::Runtime {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime")
+    }
+
+    fn recv(requests: &mpsc::Receiver<(usize, String)>) -> (usize, String) {
+        requests
+            .recv_timeout(Duration::from_secs(3))
+            .expect("failed to receive a request")
+    }
+
+    #[test]
+    fn events_are_batched_on_a_kept_alive_connection() {
+        let (url, requests) = endpoint();
+        let http = Http::new(&url)
+            .expect("invalid URL")
+            .max_batch_interval(Duration::from_secs(60));
+
+        runtime().block_on(async {
+            for msg in [r#"{"@m":"a"}"#, r#"{"@m":"b"}"#, r#"{"@m":"c"}"#] {
+                http.emit(&message(msg)).await.expect("failed to emit");
+            }
+
+            http.flush().await.expect("failed to flush");
+
+            http.emit(&message(r#"{"@m":"d"}"#))
+                .await
+                .expect("failed to emit");
+            http.flush().await.expect("failed to flush");
+        });
+
+        // Several events are sent in one request
+        let (first, body) = recv(&requests);
+        let messages: Vec<_> = body
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).expect("invalid JSON")["@m"].clone()
+            })
+            .collect();
+
+        assert_eq!(vec!["a", "b", "c"], messages);
+
+        // The connection is reused for the next request
+        let (second, body) = recv(&requests);
+
+        assert_eq!(first, second);
+        assert!(body.contains(r#""@m":"d""#));
+    }
+
+    #[test]
+    fn full_batches_are_sent_without_flushing() {
+        let (url, requests) = endpoint();
+        let http = Http::new(&url)
+            .expect("invalid URL")
+            .max_batch_bytes(1)
+            .max_batch_interval(Duration::from_secs(60));
+
+        runtime().block_on(async {
+            http.emit(&message(r#"{"@m":"a"}"#))
+                .await
+                .expect("failed to emit");
+        });
+
+        let (_, body) = recv(&requests);
+        assert!(body.contains(r#""@m":"a""#));
+    }
+
+    #[test]
+    fn batches_are_sent_after_the_interval() {
+        let (url, requests) = endpoint();
+        let http = Http::new(&url)
+            .expect("invalid URL")
+            .max_batch_interval(Duration::from_millis(10));
+
+        runtime().block_on(async {
+            http.emit(&message(r#"{"@m":"a"}"#))
+                .await
+                .expect("failed to emit");
+
+            sleep(Duration::from_millis(500)).await;
+        });
+
+        let (_, body) = recv(&requests);
+        assert!(body.contains(r#""@m":"a""#));
+    }
+
+    #[test]
+    fn responses_are_read_to_the_end() {
+        runtime().block_on(async {
+            let (client, mut server) = io::duplex(1024);
+
+            server
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n\
+                    HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\n\xff\xfe\
+                    HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .expect("failed to write responses");
+
+            let mut conn: Conn = BufReader::new(Box::new(client));
+
+            for expected in [
+                Response {
+                    status: 200,
+                    keep_alive: true,
+                },
+                Response {
+                    status: 201,
+                    keep_alive: true,
+                },
+                Response {
+                    status: 503,
+                    keep_alive: false,
+                },
+            ] {
+                assert_eq!(
+                    expected,
+                    send(&mut conn, b"").await.expect("failed to read response")
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn parse_url() {
+        let url = Url::parse("http://localhost:5341/api/events/raw?clef").expect("invalid URL");
+
+        assert!(!url.https);
+        assert_eq!("localhost", url.host());
+        assert_eq!("localhost:5341", url.addr());
+        assert_eq!("/api/events/raw?clef", url.path);
+
+        let url = Url::parse("https://seq.example.com").expect("invalid URL");
+
+        assert!(url.https);
+        assert_eq!("seq.example.com:443", url.addr());
+        assert_eq!("/", url.path);
+
+        let url = Url::parse("http://[::1]?clef").expect("invalid URL");
+
+        assert_eq!("::1", url.host());
+        assert_eq!("[::1]:80", url.addr());
+        assert_eq!("/?clef", url.path);
+
+        assert!(Url::parse("tcp://localhost").is_err());
+        assert!(Url::parse("http://").is_err());
+        assert!(Url::parse("http://localhost/ HTTP/1.1\r\nX-Injected: 1").is_err());
+    }
+
+    #[test]
+    fn headers_are_validated() {
+        let http = || Http::new("http://localhost:5341").expect("invalid URL");
+
+        assert!(http().header("X-Custom", "value").is_ok());
+        assert!(http().api_key("abc").is_ok());
+
+        assert!(http().api_key("abc\r\nX-Injected: 1").is_err());
+        assert!(http().header("X-Custom", "a\nb").is_err());
+        assert!(http().header("X-Custom: 1\r\nX-Injected", "value").is_err());
+        assert!(http().header("", "value").is_err());
+    }
+
+    #[test]
+    fn parse_response_status() {
+        assert_eq!(Some(201), parse_status(b"HTTP/1.1 201 Created\r\n"));
+        assert_eq!(None, parse_status(b"SSH-2.0\r\n"));
+    }
+
+    #[test]
+    fn parse_response_status_before_a_binary_body() {
+        assert_eq!(
+            Some(200),
+            parse_status(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n\xff\xfe")
+        );
+        assert_eq!(None, parse_status(b"HTTP/1.1 \xff\r\n"));
+    }
+}
@@ -0,0 +1,248 @@
+/*!
+Destinations for processed CLEF events.
+
+A sink receives each event after it's been converted from GELF into CLEF.
+Sinks can be synchronous, like writing to stdout, or asynchronous, like
+posting events to an HTTP endpoint.
+*/
+
+use std::{
+    fs::{
+        File as StdFile,
+        OpenOptions,
+    },
+    io::{
+        self,
+        LineWriter,
+        Write,
+    },
+    path::Path,
+    sync::Mutex,
+};
+
+use futures::future::{
+    self,
+    BoxFuture,
+    FutureExt,
+};
+
+use crate::{
+    process::clef,
+    Error,
+};
+
+mod http;
+
+pub use self::http::Http;
+
+/**
+A destination for CLEF events.
+*/
+pub trait Sink: Send + Sync {
+    /**
+    Emit a single event.
+
+    The returned future completes once the sink has accepted the event.
+    */
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>>;
+
+    /**
+    Flush any events the sink has buffered.
+
+    This is called when the pipeline is stopped. Sinks that don't buffer
+    events don't need to implement it.
+    */
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        future::ready(Ok(())).boxed()
+    }
+}
+
+impl<S> Sink for Box<S>
+where
+    S: Sink + ?Sized,
+{
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        (**self).emit(msg)
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        (**self).flush()
+    }
+}
+
+/**
+A sink that calls a synchronous function for each event.
+*/
+pub struct FromFn<F>(F);
+
+/**
+Create a sink from a synchronous function.
+*/
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: Fn(&clef::Message) -> Result<(), Error> + Send + Sync,
+{
+    FromFn(f)
+}
+
+impl<F> Sink for FromFn<F>
+where
+    F: Fn(&clef::Message) -> Result<(), Error> + Send + Sync,
+{
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        future::ready((self.0)(msg)).boxed()
+    }
+}
+
+/**
+A sink that writes events to stdout as newline-delimited JSON.
+*/
+#[derive(Debug, Default)]
+pub struct Stdout(());
+
+impl Stdout {
+    pub fn new() -> Self {
+        Stdout(())
+    }
+}
+
+impl Sink for Stdout {
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        let emitted = serde_json::to_string(msg)
+            .map(|clef| println!("{}", clef))
+            .map_err(Error::from);
+
+        future::ready(emitted).boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        future::ready(io::stdout().flush().map_err(Error::from)).boxed()
+    }
+}
+
+/**
+A sink that appends events to a file as newline-delimited JSON.
+*/
+pub struct File(Mutex<LineWriter<StdFile>>);
+
+impl File {
+    /**
+    Open a file for appending, creating it if it doesn't exist.
+    */
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                Error::from(e).context(format!("failed to open output file `{}`", path.display()))
+            })?;
+
+        Ok(File(Mutex::new(LineWriter::new(file))))
+    }
+}
+
+impl Sink for File {
+    fn emit<'a>(&'a self, msg: &'a clef::Message<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        let emit = || -> Result<(), Error> {
+            let clef = serde_json::to_vec(msg)?;
+
+            let mut file = self
+                .0
+                .lock()
+                .map_err(|_| anyhow!("the output file is poisoned"))?;
+
+            file.write_all(&clef)?;
+            file.write_all(b"\n")?;
+
+            Ok(())
+        };
+
+        future::ready(emit()).boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        let flush = || -> Result<(), Error> {
+            self.0
+                .lock()
+                .map_err(|_| anyhow!("the output file is poisoned"))?
+                .flush()?;
+
+            Ok(())
+        };
+
+        future::ready(flush()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        fs,
+        sync::Arc,
+    };
+
+    use crate::{
+        process::str::Str,
+        test_util::TempDir,
+    };
+
+    fn msg(message: &str) -> clef::Message {
+        clef::Message {
+            timestamp: None,
+            level: None,
+            message: Some(Str::Borrowed(message)),
+            message_template: None,
+            exception: None,
+            additional: Default::default(),
+        }
+    }
+
+    #[test]
+    fn from_fn_is_called() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = from_fn({
+            let received = received.clone();
+            move |msg| {
+                let message = msg.message.as_ref().map(|m| m.as_ref().to_owned());
+                received.lock().unwrap().push(message);
+
+                Ok(())
+            }
+        });
+
+        futures::executor::block_on(sink.emit(&msg("a"))).expect("failed to emit");
+        futures::executor::block_on(sink.emit(&msg("b"))).expect("failed to emit");
+
+        assert_eq!(
+            vec![Some("a".to_owned()), Some("b".to_owned())],
+            *received.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn file_appends_lines() {
+        let temp = TempDir::new("sink");
+        let path = temp.path().join("events.clef");
+
+        let sink = File::open(&path).expect("failed to open file");
+        futures::executor::block_on(sink.emit(&msg("a"))).expect("failed to emit");
+        futures::executor::block_on(sink.emit(&msg("b"))).expect("failed to emit");
+
+        let written = fs::read_to_string(&path).expect("failed to read file");
+
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("invalid JSON"))
+            .collect();
+
+        assert_eq!(2, lines.len());
+        assert_eq!("a", lines[0]["@m"]);
+        assert_eq!("b", lines[1]["@m"]);
+    }
+}
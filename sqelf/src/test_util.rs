@@ -0,0 +1,44 @@
+/*!
+Helpers shared by unit tests.
+*/
+
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/**
+A temporary directory that's removed when it's dropped.
+
+Keep the guard alive until after any assertions on its contents,
+so a failing test still cleans up after itself.
+*/
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /**
+    Create a new, empty directory with a unique name.
+    */
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sqelf-{}-{}", name, rand::random::<u64>()));
+
+        fs::create_dir_all(&path).expect("failed to create temp dir");
+
+        TempDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,17 @@
+use crate::support::*;
+
+pub fn test() {
+    let server = server::udp();
+
+    let (status, _) = http::get(&server, "/healthz");
+    assert_eq!(200, status);
+
+    let (status, body) = http::get(&server, "/readyz");
+    assert_eq!(200, status);
+    assert_eq!("ok", body);
+
+    let (status, _) = http::get(&server, "/not-a-path");
+    assert_eq!(404, status);
+
+    server.close();
+}
@@ -0,0 +1,38 @@
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::tcp();
+    let mut stream = tcp::stream(&server);
+
+    stream.write(net_chunks![
+        ..net_chunks!({
+            "host": "foo",
+            "short_message": "bar"
+        }),
+        ..tcp_delim()
+    ]);
+
+    server.receive(|_| {});
+
+    let (status, body) = http::get(&server, "/metrics");
+    assert_eq!(200, status);
+
+    let lines: Vec<_> = body.lines().collect();
+
+    assert!(lines.contains(&"# TYPE sqelf_server_receive_ok_total counter"));
+    assert!(lines.contains(&"sqelf_server_receive_ok_total 1"));
+    assert!(lines.contains(&"# TYPE sqelf_server_tcp_conn_active gauge"));
+    assert!(lines.contains(&"sqelf_server_tcp_conn_active 1"));
+    assert!(lines.contains(&"# TYPE sqelf_receive_msg_incomplete gauge"));
+    assert!(lines.contains(&"sqelf_receive_msg_incomplete 0"));
+    assert!(lines.contains(&"# TYPE sqelf_server_process_latency_seconds histogram"));
+    assert!(lines.contains(&"sqelf_server_process_latency_seconds_count 1"));
+    assert!(lines.contains(&"sqelf_server_process_latency_seconds_bucket{le=\"+Inf\"} 1"));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("sqelf_server_tcp_bytes_received_total ")
+            && !line.ends_with(" 0")));
+
+    stream.close();
+    server.close();
+}
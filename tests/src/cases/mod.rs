@@ -2,10 +2,14 @@ cases! {
     udp_empty,
     udp_simple,
     udp_invalid,
+    udp_invalid_as_error,
+    udp_dead_letter,
+    udp_dead_letter_invalid_as_error,
     udp_chunked_simple,
     udp_chunked_out_of_order,
     udp_chunked_overflow,
     udp_chunked_overflow_invalid_header,
+    udp_client_compressed,
 
     tcp_empty,
     tcp_simple,
@@ -21,5 +25,18 @@ cases! {
     tcp_multiple_conns_partial,
     tcp_timeout_partial,
     tcp_timeout_slow,
-    tcp_tls
+    tcp_timeout_idle,
+    tcp_timeout_min_rate,
+    tcp_shutdown_drain,
+    tcp_shutdown_idle,
+    tcp_tls,
+    tcp_client,
+
+    server_existing_runtime,
+    server_max_in_flight,
+    server_pause_resume,
+    pipeline_sinks,
+
+    http_health,
+    http_metrics
 }
@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io::{
+        Read,
+        Write,
+    },
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use sqelf::{
+    pipeline::Pipeline,
+    send,
+    server,
+    sink,
+};
+
+use crate::support::temp;
+
+pub fn test() {
+    let dir = temp::dir("pipeline-sinks");
+    let path = dir.path().join("events.clef");
+
+    // A minimal HTTP endpoint that accepts a single event
+    let endpoint = TcpListener::bind("127.0.0.1:0").expect("failed to bind endpoint");
+    let endpoint_addr = endpoint.local_addr().expect("missing endpoint address");
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut conn, _) = endpoint.accept().expect("failed to accept");
+
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+
+        let body = loop {
+            let read = conn.read(&mut buf).expect("failed to read request");
+            request.extend_from_slice(&buf[..read]);
+
+            let request = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                let content_length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .expect("missing content length")
+                    .parse()
+                    .expect("invalid content length");
+
+                if body.len() >= content_length {
+                    break (head.to_owned(), body.to_owned());
+                }
+            }
+        };
+
+        conn.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+            .expect("failed to write response");
+
+        tx.send(body).expect("failed to send request");
+    });
+
+    let pipeline = Pipeline::builder()
+        .sink(sink::File::open(&path).expect("failed to open file"))
+        .sink(
+            sink::Http::new(&format!("http://{}/api/events/raw?clef", endpoint_addr))
+                .expect("invalid URL")
+                .api_key("abc")
+                .expect("invalid API key"),
+        )
+        .build();
+
+    let mut server = pipeline
+        .serve(server::Config {
+            bind: "tcp://127.0.0.1:0".parse().expect("invalid bind"),
+            ..Default::default()
+        })
+        .expect("failed to build server");
+
+    let handle = server.take_handle().expect("no server handle");
+    let server = thread::spawn(move || server.run().expect("failed to run server"));
+
+    let addrs = futures::executor::block_on(handle.ready()).expect("failed to start server");
+
+    let mut client = send::tcp::Client::connect(addrs.gelf).expect("failed to connect");
+    client
+        .send(br#"{"host":"foo","short_message":"bar"}"#)
+        .expect("failed to send message");
+
+    // The event is posted to the HTTP endpoint
+    let (head, body) = rx
+        .recv_timeout(Duration::from_secs(3))
+        .expect("failed to receive a request");
+
+    assert!(head.starts_with("POST /api/events/raw?clef HTTP/1.1"));
+    assert!(head.contains("X-Seq-ApiKey: abc"));
+
+    let posted: Value = serde_json::from_str(&body).expect("invalid JSON");
+    assert_eq!("bar", posted["@m"]);
+
+    client.close().expect("failed to close client");
+
+    handle.close();
+    server.join().expect("failed to run server");
+
+    // The event is written to the file before it's posted
+    let written = fs::read_to_string(&path).expect("failed to read file");
+
+    let written: Value = serde_json::from_str(written.trim()).expect("invalid JSON");
+    assert_eq!("bar", written["@m"]);
+}
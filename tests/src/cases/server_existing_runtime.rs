@@ -0,0 +1,70 @@
+use sqelf::{
+    process,
+    receive,
+    send,
+    server,
+};
+
+use tokio::{
+    runtime,
+    sync::mpsc,
+};
+
+pub fn test() {
+    // Run the server on a single-threaded runtime owned by the test
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    runtime.block_on(async {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let mut server = server::build(
+            server::Config {
+                bind: "tcp://127.0.0.1:0".parse().expect("invalid bind"),
+                ..Default::default()
+            },
+            {
+                let mut receive = receive::build(receive::Config::default());
+
+                move |src| receive.decode(src)
+            },
+            {
+                let process = process::build(process::Config::default());
+
+                move |msg| {
+                    process.with_clef(msg, |clef| {
+                        tx.send(serde_json::to_value(clef)?)?;
+
+                        Ok(())
+                    })
+                }
+            },
+        )
+        .expect("failed to build server");
+
+        let handle = server.take_handle().expect("no server handle");
+        let server = tokio::spawn(async move { server.await });
+
+        let addrs = handle.ready().await.expect("failed to start server");
+        assert_eq!(Some(addrs), handle.local_addrs());
+        assert_ne!(0, addrs.gelf.port());
+
+        let mut client = send::tcp::Client::connect(addrs.gelf).expect("failed to connect");
+        client
+            .send(br#"{"host":"foo","short_message":"bar"}"#)
+            .expect("failed to send message");
+
+        let received = rx.recv().await.expect("failed to receive a message");
+        assert_eq!("bar", received["@m"]);
+
+        client.close().expect("failed to close client");
+
+        handle.close();
+        server
+            .await
+            .expect("failed to join server")
+            .expect("failed to run server");
+    });
+}
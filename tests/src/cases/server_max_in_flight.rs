@@ -0,0 +1,113 @@
+use std::{
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use sqelf::{
+    process,
+    receive,
+    send,
+    server,
+};
+
+use tokio::sync::Semaphore;
+
+fn wait_for(started: &AtomicUsize, expected: usize) {
+    let deadline = Instant::now() + Duration::from_secs(3);
+
+    while started.load(Ordering::SeqCst) < expected {
+        assert!(Instant::now() < deadline, "timed out waiting for messages to start");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub fn test() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let gate = Arc::new(Semaphore::new(0));
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    let mut server = server::build_async(
+        server::Config {
+            bind: "tcp://127.0.0.1:0".parse().expect("invalid bind"),
+            max_in_flight: 2,
+            ..Default::default()
+        },
+        {
+            let mut receive = receive::build(receive::Config::default());
+
+            move |src| receive.decode(src)
+        },
+        {
+            let process = process::build(process::Config::default());
+            let started = started.clone();
+            let gate = gate.clone();
+
+            move |msg| {
+                let processed = process.with_clef(msg, |clef| Ok(serde_json::to_value(clef)?));
+                started.fetch_add(1, Ordering::SeqCst);
+
+                let gate = gate.clone();
+                let tx = tx.clone();
+                async move {
+                    // Hold the message in-flight until the test releases it
+                    gate.acquire().await?.forget();
+                    tx.send(processed?)?;
+
+                    Ok(())
+                }
+            }
+        },
+    )
+    .expect("failed to build server");
+
+    let handle = server.take_handle().expect("no server handle");
+    let server = thread::spawn(move || server.run().expect("failed to run server"));
+
+    let addrs = futures::executor::block_on(handle.ready()).expect("failed to start server");
+
+    let mut client = send::tcp::Client::connect(addrs.gelf).expect("failed to connect");
+    for short_message in &["a", "b", "c"] {
+        client
+            .send(format!(r#"{{"host":"foo","short_message":"{}"}}"#, short_message).as_bytes())
+            .expect("failed to send message");
+    }
+
+    // Only two messages can be in-flight at once
+    wait_for(&started, 2);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(2, started.load(Ordering::SeqCst));
+
+    // Completing one message lets the next one start
+    gate.add_permits(1);
+    wait_for(&started, 3);
+
+    gate.add_permits(2);
+
+    let mut received: Vec<String> = (0..3)
+        .map(|_| {
+            let msg = rx
+                .recv_timeout(Duration::from_secs(3))
+                .expect("failed to receive a message");
+
+            msg["@m"].as_str().expect("missing message").to_owned()
+        })
+        .collect();
+    received.sort();
+
+    assert_eq!(vec!["a", "b", "c"], received);
+
+    client.close().expect("failed to close client");
+
+    handle.close();
+    server.join().expect("failed to run server");
+}
@@ -0,0 +1,63 @@
+use std::{
+    thread,
+    time::Duration,
+};
+
+use sqelf::diagnostics::{
+    self,
+    Level,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::tcp();
+
+    server.handle().pause();
+    assert!(server.handle().is_paused());
+
+    // A paused server isn't ready
+    let (status, body) = http::get(&server, "/readyz");
+    assert_eq!(503, status);
+    assert_eq!("the server is paused", body);
+
+    let mut client = tcp::client(&server);
+    client
+        .send(br#"{"host":"foo","short_message":"bar"}"#)
+        .expect("failed to send message");
+
+    // Messages aren't received while the server is paused
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(0, server.received());
+
+    let stats = server.handle().stats();
+    assert!(stats.paused);
+    assert_eq!(1, stats.max_in_flight);
+
+    // Settings can be changed while the server is running
+    server
+        .handle()
+        .set_max_in_flight(4)
+        .expect("failed to set max in-flight");
+    assert!(server.handle().set_max_in_flight(0).is_err());
+    diagnostics::set_min_level(Level::Debug);
+    diagnostics::set_max_errors_per_interval(5);
+
+    // Resuming receives the message that was waiting
+    server.handle().resume();
+
+    server.receive(|received| {
+        assert_eq!("bar", received["@m"]);
+    });
+
+    let stats = server.handle().stats();
+    assert!(!stats.paused);
+    assert_eq!(4, stats.max_in_flight);
+    assert!(stats.active_connections >= 1);
+
+    let (status, _) = http::get(&server, "/readyz");
+    assert_eq!(200, status);
+
+    client.close().expect("failed to close client");
+    server.close();
+}
@@ -2,7 +2,7 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::tcp();
-    let mut stream = tcp::stream();
+    let mut stream = tcp::stream(&server);
 
     stream.write(net_chunks![
         ..net_chunks!({
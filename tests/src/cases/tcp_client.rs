@@ -0,0 +1,30 @@
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::tcp();
+    let mut client = tcp::client(&server);
+
+    for short_message in &["bar", "baz"] {
+        client
+            .send(
+                &serde_json::to_vec(&json!({
+                    "host": "foo",
+                    "short_message": short_message,
+                }))
+                .unwrap(),
+            )
+            .expect("failed to send message");
+    }
+
+    server.receive(|received| {
+        assert_eq!("bar", received["@m"]);
+    });
+    server.receive(|received| {
+        assert_eq!("baz", received["@m"]);
+    });
+
+    assert_eq!(2, server.received());
+
+    client.close().expect("failed to close client");
+    server.close();
+}
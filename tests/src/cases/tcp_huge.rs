@@ -4,7 +4,7 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::builder().tcp_max_size_bytes(1024 * 32).tcp();
-    let mut stream = tcp::stream();
+    let mut stream = tcp::stream(&server);
 
     let short_message = str::from_utf8(&[b'a'; 1024 * 12]).unwrap();
 
@@ -2,8 +2,8 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::tcp();
-    let mut stream1 = tcp::stream();
-    let mut stream2 = tcp::stream();
+    let mut stream1 = tcp::stream(&server);
+    let mut stream2 = tcp::stream(&server);
 
     stream1.write(net_chunks![
         ..net_chunks!({
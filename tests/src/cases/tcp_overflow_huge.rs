@@ -4,7 +4,7 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::tcp();
-    let mut stream = tcp::stream();
+    let mut stream = tcp::stream(&server);
 
     let short_message = str::from_utf8(&[b'a'; 1024 * 16]).unwrap();
 
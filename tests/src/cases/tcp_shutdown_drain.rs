@@ -0,0 +1,37 @@
+use std::{
+    thread,
+    time::Duration,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::tcp();
+    let mut stream = tcp::stream(&server);
+
+    let msg = net_chunks!({
+        "host": "foo",
+        "short_message": "bar"
+    })
+    .concat();
+
+    stream.write(bytes(&msg[0..4]));
+
+    // Give the server a chance to read the partial message
+    thread::sleep(Duration::from_millis(500));
+
+    server.begin_close();
+
+    // The in-flight message should still be received while draining
+    thread::sleep(Duration::from_millis(500));
+    stream.write(net_chunks![..bytes(&msg[4..]), ..tcp_delim()]);
+
+    server.receive(|received| {
+        assert_eq!("bar", received["@m"]);
+    });
+
+    assert_eq!(1, server.received());
+
+    stream.close();
+    server.close();
+}
@@ -0,0 +1,30 @@
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::tcp();
+    let mut stream = tcp::stream(&server);
+
+    stream.write(net_chunks![
+        ..net_chunks!({
+            "host": "foo",
+            "short_message": "bar"
+        }),
+        ..tcp_delim()
+    ]);
+
+    server.receive(|_| {});
+
+    // The connection is still open, but idle
+    // so it shouldn't hold up shutting down
+    let start = Instant::now();
+    server.close();
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    stream.close();
+}
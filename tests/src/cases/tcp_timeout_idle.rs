@@ -0,0 +1,34 @@
+use std::{
+    thread,
+    time::Duration,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::builder()
+        .tcp_frame_timeout_secs(1)
+        .tcp_idle_timeout_secs(10)
+        .tcp();
+    let mut stream = tcp::stream(&server);
+
+    for _ in 0..2 {
+        stream.write(net_chunks![
+            ..net_chunks!({
+                "host": "foo",
+                "short_message": "bar"
+            }),
+            ..tcp_delim()
+        ]);
+
+        server.receive(|_| {});
+
+        // The connection is idle for longer than the frame timeout
+        thread::sleep(Duration::from_secs(2));
+    }
+
+    assert_eq!(2, server.received());
+
+    stream.close();
+    server.close();
+}
@@ -0,0 +1,32 @@
+use std::{
+    thread,
+    time::Duration,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::builder().tcp_min_bytes_per_sec(100).tcp();
+    let mut stream = tcp::stream(&server);
+
+    let msg = net_chunks!({
+        "host": "foo",
+        "short_message": "bar"
+    })
+    .concat();
+
+    // Send the first byte of the message and then stall
+    // This is well within the frame timeout, but below the minimum rate
+    stream.write(bytes(&msg[0..1]));
+
+    thread::sleep(Duration::from_secs(2));
+
+    stream.write(bytes([&msg[1..], b"\0"].concat()));
+
+    thread::sleep(Duration::from_secs(1));
+
+    assert_eq!(0, server.received());
+
+    stream.close();
+    server.close();
+}
@@ -6,8 +6,8 @@ use std::{
 use crate::support::*;
 
 pub fn test() {
-    let mut server = server::builder().tcp_keep_alive_secs(3).tcp();
-    let mut stream = tcp::stream();
+    let mut server = server::builder().tcp_frame_timeout_secs(3).tcp();
+    let mut stream = tcp::stream(&server);
 
     thread::sleep(Duration::from_secs(1));
 
@@ -6,8 +6,8 @@ use std::{
 use crate::support::*;
 
 pub fn test() {
-    let mut server = server::builder().tcp_keep_alive_secs(3).tcp();
-    let mut stream = tcp::stream();
+    let mut server = server::builder().tcp_idle_timeout_secs(3).tcp();
+    let mut stream = tcp::stream(&server);
 
     for _ in 0..5 {
         stream.write(net_chunks![
@@ -9,7 +9,7 @@ pub fn test() {
             .tcp_certificate_private_key_path("127.0.0.1+1-key.pem")
             .tcp();
 
-        let mut stream = tcp::tls_stream();
+        let mut stream = tcp::tls_stream(&server);
 
         stream.write(net_chunks![
             ..net_chunks!({
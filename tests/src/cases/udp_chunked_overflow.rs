@@ -2,7 +2,7 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::builder().udp_max_chunks(3).udp();
-    let mut sock = udp::sock();
+    let mut sock = udp::sock(&server);
 
     // Split a message into 5 chunks
     let msg_chunks = net_chunks!(5, {
@@ -0,0 +1,37 @@
+use crate::support::*;
+
+use sqelf::send::{
+    self,
+    Compression,
+};
+
+pub fn test() {
+    let mut server = server::udp();
+
+    for compression in &[Compression::None, Compression::Gzip, Compression::Zlib] {
+        let client = udp::client(&server, send::udp::Config {
+            compression: *compression,
+            max_datagram_size: 64,
+        });
+
+        let short_message = "bar".repeat(32);
+
+        client
+            .send(
+                &serde_json::to_vec(&json!({
+                    "host": "foo",
+                    "short_message": short_message,
+                }))
+                .unwrap(),
+            )
+            .expect("failed to send message");
+
+        server.receive(|received| {
+            assert_eq!(short_message, received["@m"]);
+        });
+    }
+
+    assert_eq!(3, server.received());
+
+    server.close();
+}
@@ -0,0 +1,98 @@
+use std::fs;
+
+use sqelf::{
+    capture,
+    dead_letter,
+    process,
+    receive,
+};
+
+use crate::support::*;
+
+pub fn test() {
+    let dir = temp::dir("udp-dead-letter");
+
+    let mut server = server::builder()
+        .dead_letter_path(dir.path_str())
+        .udp();
+    let mut sock = udp::sock(&server);
+
+    // A chunk that can't be received
+    sock.send(net_chunks![
+        ..udp_chunk(0, 2, 1, b"{}")
+    ]);
+
+    // A message that can't be processed
+    sock.send(net_chunks![
+        ..bytes(b"not json!")
+    ]);
+
+    sock.send(net_chunks![
+        ..net_chunks!({
+            "host": "foo",
+            "short_message": "bar"
+        })
+    ]);
+
+    server.receive(|received| {
+        assert_eq!("bar", received["@m"]);
+    });
+
+    server.close();
+
+    let files = dead_letter::files(dir.path()).expect("failed to list dead letters");
+    assert_eq!(1, files.len());
+
+    let records: Vec<dead_letter::Record> = fs::read_to_string(&files[0])
+        .expect("failed to read dead letters")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("invalid dead letter"))
+        .collect();
+
+    assert_eq!(2, records.len());
+
+    assert_eq!(dead_letter::Stage::Receive, records[0].stage);
+    assert_eq!(dead_letter::Stage::Process, records[1].stage);
+
+    for record in &records {
+        assert_eq!("udp", record.raw.protocol);
+        assert!(record
+            .raw
+            .peer
+            .as_deref()
+            .expect("missing peer")
+            .starts_with("127.0.0.1:"));
+    }
+
+    assert_eq!(
+        &b"not json!"[..],
+        &*records[1].raw.payload().expect("invalid payload")
+    );
+
+    // Dead letters can be resubmitted
+    let mut receive = receive::build(receive::Config::default());
+    let process = process::build(process::Config {
+        emit_invalid_as_errors: true,
+        ..Default::default()
+    });
+
+    let mut resubmitted = Vec::new();
+    let summary = dead_letter::resubmit(
+        capture::open(&files[0]).expect("failed to open dead letters"),
+        |src| receive.decode(src),
+        |msg| {
+            process.with_clef(msg, |clef| {
+                resubmitted.push(serde_json::to_value(clef)?);
+
+                Ok(())
+            })
+        },
+    )
+    .expect("failed to resubmit");
+
+    assert_eq!(1, summary.processed);
+    assert_eq!(1, summary.failed);
+
+    // The original peer is kept
+    assert_eq!(records[1].raw.peer.as_deref(), resubmitted[0]["peer"].as_str());
+}
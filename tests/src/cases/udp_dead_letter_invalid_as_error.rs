@@ -0,0 +1,46 @@
+use std::fs;
+
+use sqelf::dead_letter;
+
+use crate::support::*;
+
+pub fn test() {
+    let dir = temp::dir("udp-dead-letter-invalid-as-error");
+
+    let mut server = server::builder()
+        .emit_invalid_as_errors(true)
+        .dead_letter_path(dir.path_str())
+        .udp();
+    let mut sock = udp::sock(&server);
+
+    // A chunk that can't be received
+    sock.send(net_chunks![
+        ..udp_chunk(0, 2, 1, b"{}")
+    ]);
+
+    // A message that can't be parsed
+    sock.send(net_chunks![
+        ..bytes(b"not json!")
+    ]);
+
+    // The invalid message is written as an error event instead of a dead letter
+    server.receive(|received| {
+        assert_eq!("Error", received["@l"]);
+        assert_eq!("not json!", received["payload"]);
+    });
+
+    server.close();
+
+    let files = dead_letter::files(dir.path()).expect("failed to list dead letters");
+    assert_eq!(1, files.len());
+
+    let records: Vec<dead_letter::Record> = fs::read_to_string(&files[0])
+        .expect("failed to read dead letters")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("invalid dead letter"))
+        .collect();
+
+    // Only the chunk that couldn't be received is kept
+    assert_eq!(1, records.len());
+    assert_eq!(dead_letter::Stage::Receive, records[0].stage);
+}
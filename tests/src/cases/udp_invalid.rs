@@ -2,14 +2,12 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::udp();
-    let mut sock = udp::sock();
+    let mut sock = udp::sock(&server);
 
     sock.send(net_chunks![
         ..bytes(b"not json!")
     ]);
 
-    assert_eq!(0, server.received());
-
     sock.send(net_chunks![
         ..net_chunks!({
             "host": "foo",
@@ -21,5 +19,9 @@ pub fn test() {
         assert_eq!("bar", received["@m"]);
     });
 
+    // The invalid message is processed before the valid one
+    // but doesn't produce any output
+    assert_eq!(2, server.received());
+
     server.close();
 }
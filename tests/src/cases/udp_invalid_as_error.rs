@@ -0,0 +1,25 @@
+use crate::support::*;
+
+pub fn test() {
+    let mut server = server::builder().emit_invalid_as_errors(true).udp();
+    let mut sock = udp::sock(&server);
+
+    sock.send(net_chunks![
+        ..bytes(b"not json!")
+    ]);
+
+    // The invalid message is written as an error event
+    server.receive(|received| {
+        assert_eq!("Error", received["@l"]);
+        assert_eq!("not json!", received["payload"]);
+        assert!(received["error"].is_string());
+        assert!(received["peer"]
+            .as_str()
+            .expect("missing peer")
+            .starts_with("127.0.0.1:"));
+    });
+
+    assert_eq!(1, server.received());
+
+    server.close();
+}
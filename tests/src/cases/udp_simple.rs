@@ -2,7 +2,7 @@ use crate::support::*;
 
 pub fn test() {
     let mut server = server::udp();
-    let mut sock = udp::sock();
+    let mut sock = udp::sock(&server);
 
     sock.send(net_chunks![
         ..net_chunks!({
@@ -0,0 +1,37 @@
+use std::{
+    io::{
+        Read,
+        Write,
+    },
+    net::TcpStream,
+};
+
+use super::server::Server;
+
+pub fn get(server: &Server, path: &str) -> (u16, String) {
+    let mut stream =
+        TcpStream::connect(server.http_addr()).expect("failed to connect to HTTP server");
+
+    write!(stream, "GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n", path)
+        .expect("failed to send request");
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .expect("failed to read response");
+
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .expect("failed to read status");
+
+    let body = response
+        .split("\r\n\r\n")
+        .nth(1)
+        .unwrap_or_default()
+        .trim()
+        .to_owned();
+
+    (status, body)
+}
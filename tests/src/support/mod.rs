@@ -1,14 +1,13 @@
-use byteorder::{
-    BigEndian,
-    ByteOrder,
-};
+const SERVER_HOST: &str = "localhost";
 
-const SERVER_HOST: &'static str = "localhost";
-const SERVER_BIND: &'static str = "0.0.0.0:12202";
-const SERVER_ADDR: &'static str = "127.0.0.1:12202";
+// NOTE: Servers bind to any free port so tests can run concurrently
+const SERVER_BIND: &str = "127.0.0.1:0";
+const SERVER_HTTP_BIND: &str = "127.0.0.1:0";
 
+pub mod http;
 pub mod server;
 pub mod tcp;
+pub mod temp;
 pub mod udp;
 
 macro_rules! net_chunks {
@@ -39,18 +38,10 @@ pub(crate) fn udp_chunk(
     seq_total: u8,
     bytes: impl AsRef<[u8]>,
 ) -> Vec<Vec<u8>> {
-    let mut header = vec![0x1e, 0x0f];
+    let mut chunk = sqelf::send::chunk_header(id, seq_num, seq_total).to_vec();
+    chunk.extend(bytes.as_ref());
 
-    let mut idb = [0; 8];
-
-    BigEndian::write_u64(&mut idb, id);
-
-    header.extend(&idb);
-    header.push(seq_num);
-    header.push(seq_total);
-    header.extend(bytes.as_ref());
-
-    vec![header]
+    vec![chunk]
 }
 
 pub(crate) fn bytes(b: impl AsRef<[u8]>) -> Vec<Vec<u8>> {
@@ -61,7 +52,7 @@ pub(crate) fn tcp_delim() -> Vec<Vec<u8>> {
     vec![vec![b'\0']]
 }
 
-pub(crate) fn test_child(name: &str) -> bool {
+pub(crate) fn test_child(name: &str) -> std::process::Child {
     use std::{
         env,
         process::{
@@ -72,13 +63,11 @@ pub(crate) fn test_child(name: &str) -> bool {
 
     let self_bin = env::args().next().expect("missing self command");
 
-    let mut test = Command::new(self_bin)
+    Command::new(self_bin)
         .arg(name)
         .stdout(Stdio::inherit())
         .spawn()
-        .expect("failed to start child process");
-
-    test.wait().expect("test execution failed").success()
+        .expect("failed to start child process")
 }
 
 macro_rules! cases {
@@ -90,13 +79,20 @@ macro_rules! cases {
         pub(crate) fn test_all() {
             use std::process;
 
+            // Each case binds its own ports, so they can all run at once
+            let children = vec![
+                $(
+                    (stringify!($case), $crate::support::test_child(stringify!($case))),
+                )+
+            ];
+
             let mut failed = Vec::new();
 
-            $(
-                if !$crate::support::test_child(stringify!($case)) {
-                    failed.push(stringify!($case));
+            for (case, mut child) in children {
+                if !child.wait().expect("test execution failed").success() {
+                    failed.push(case);
                 }
-            )+
+            }
 
             if failed.len() > 0 {
                 eprintln!("test execution failed. Failures: {:#?}", failed);
@@ -1,4 +1,5 @@
 use std::{
+    net::SocketAddr,
     sync::{
         Arc,
         Mutex,
@@ -20,24 +21,35 @@ use sqelf::{
     server,
 };
 
-use super::SERVER_BIND;
+use super::{
+    SERVER_BIND,
+    SERVER_HTTP_BIND,
+};
 
 pub struct Builder {
     tcp_max_size_bytes: u64,
-    tcp_keep_alive_secs: u64,
+    tcp_idle_timeout_secs: u64,
+    tcp_frame_timeout_secs: u64,
+    tcp_min_bytes_per_sec: u64,
     tcp_certificate_path: Option<String>,
     tcp_certificate_private_key_path: Option<String>,
     udp_max_chunks_per_message: u8,
+    emit_invalid_as_errors: bool,
+    dead_letter_path: Option<String>,
 }
 
 impl Builder {
     fn new() -> Self {
         Builder {
             tcp_max_size_bytes: 512,
-            tcp_keep_alive_secs: 10,
+            tcp_idle_timeout_secs: 10,
+            tcp_frame_timeout_secs: 10,
+            tcp_min_bytes_per_sec: 0,
             tcp_certificate_path: None,
             tcp_certificate_private_key_path: None,
             udp_max_chunks_per_message: u8::MAX,
+            emit_invalid_as_errors: false,
+            dead_letter_path: None,
         }
     }
 
@@ -46,8 +58,18 @@ impl Builder {
         self
     }
 
-    pub fn tcp_keep_alive_secs(mut self, v: u64) -> Self {
-        self.tcp_keep_alive_secs = v;
+    pub fn tcp_idle_timeout_secs(mut self, v: u64) -> Self {
+        self.tcp_idle_timeout_secs = v;
+        self
+    }
+
+    pub fn tcp_frame_timeout_secs(mut self, v: u64) -> Self {
+        self.tcp_frame_timeout_secs = v;
+        self
+    }
+
+    pub fn tcp_min_bytes_per_sec(mut self, v: u64) -> Self {
+        self.tcp_min_bytes_per_sec = v;
         self
     }
 
@@ -66,6 +88,16 @@ impl Builder {
         self
     }
 
+    pub fn emit_invalid_as_errors(mut self, v: bool) -> Self {
+        self.emit_invalid_as_errors = v;
+        self
+    }
+
+    pub fn dead_letter_path(mut self, v: impl Into<String>) -> Self {
+        self.dead_letter_path = Some(v.into());
+        self
+    }
+
     fn build(mut self, protocol: server::Protocol) -> Server {
         Server::new(
             server::Config {
@@ -74,7 +106,9 @@ impl Builder {
                     protocol,
                 },
                 tcp_max_size_bytes: self.tcp_max_size_bytes,
-                tcp_keep_alive_secs: self.tcp_keep_alive_secs,
+                tcp_idle_timeout_secs: self.tcp_idle_timeout_secs,
+                tcp_frame_timeout_secs: self.tcp_frame_timeout_secs,
+                tcp_min_bytes_per_sec: self.tcp_min_bytes_per_sec,
                 certificate: self
                     .tcp_certificate_path
                     .take()
@@ -85,12 +119,18 @@ impl Builder {
                             .take()
                             .unwrap_or(path),
                     }),
+                http_bind: Some(SERVER_HTTP_BIND.into()),
+                dead_letter_path: self.dead_letter_path.take(),
                 ..Default::default()
             },
             receive::Config {
                 max_chunks_per_message: self.udp_max_chunks_per_message,
                 ..Default::default()
             },
+            process::Config {
+                emit_invalid_as_errors: self.emit_invalid_as_errors,
+                ..Default::default()
+            },
         )
     }
 
@@ -105,7 +145,8 @@ impl Builder {
 
 pub struct Server {
     server: thread::JoinHandle<()>,
-    handle: server::Handle,
+    handle: Option<server::Handle>,
+    local_addrs: server::LocalAddrs,
     received: Arc<Mutex<usize>>,
     rx: Receiver<Value>,
 }
@@ -123,7 +164,11 @@ pub fn tcp() -> Server {
 }
 
 impl Server {
-    fn new(server_config: server::Config, receive_config: receive::Config) -> Self {
+    fn new(
+        server_config: server::Config,
+        receive_config: receive::Config,
+        process_config: process::Config,
+    ) -> Self {
         let (tx, rx) = crossbeam_channel::unbounded();
         let received = Arc::new(Mutex::new(0));
 
@@ -135,9 +180,7 @@ impl Server {
                 move |src| receive.decode(src)
             },
             {
-                let process = process::build(process::Config {
-                    ..Default::default()
-                });
+                let process = process::build(process_config);
 
                 let received = received.clone();
                 move |msg| {
@@ -158,16 +201,30 @@ impl Server {
         let server = thread::spawn(move || server.run().expect("failed to run server"));
 
         // Wait for the server to become available
-        thread::sleep(Duration::from_secs(1));
+        let local_addrs =
+            futures::executor::block_on(handle.ready()).expect("failed to start server");
 
         Server {
-            handle,
+            handle: Some(handle),
+            local_addrs,
             server,
             rx,
             received,
         }
     }
 
+    pub fn addr(&self) -> SocketAddr {
+        self.local_addrs.gelf
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.local_addrs.http.expect("missing HTTP address")
+    }
+
+    pub fn handle(&self) -> &server::Handle {
+        self.handle.as_ref().expect("the server is closing")
+    }
+
     pub fn received(&mut self) -> usize {
         *(self.received.lock().expect("poisoned lock"))
     }
@@ -181,8 +238,14 @@ impl Server {
         f(msg)
     }
 
-    pub fn close(self) {
-        self.handle.close();
+    pub fn begin_close(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.close();
+        }
+    }
+
+    pub fn close(mut self) {
+        self.begin_close();
         self.server.join().expect("failed to run server");
     }
 }
@@ -1,52 +1,55 @@
-use crate::support::SERVER_HOST;
-use std::convert::TryInto;
 use std::sync::Arc;
-use std::{
-    io::Write,
-    net::{
-        self,
-        TcpStream,
-    },
-};
 
-use super::SERVER_ADDR;
+use sqelf::send::tcp;
+
+use super::{
+    server::Server,
+    SERVER_HOST,
+};
 
 pub struct Stream {
-    inner: TcpStream,
+    inner: tcp::Client,
+}
+
+pub fn stream(server: &Server) -> Stream {
+    Stream::new(server)
 }
 
-pub fn stream() -> Stream {
-    Stream::new()
+pub fn client(server: &Server) -> tcp::Client {
+    tcp::Client::connect(server.addr()).expect("failed to bind client stream")
 }
 
 impl Stream {
-    fn new() -> Self {
-        let stream = TcpStream::connect(SERVER_ADDR).expect("failed to bind client stream");
+    fn new(server: &Server) -> Self {
+        let stream = tcp::Client::connect(server.addr()).expect("failed to bind client stream");
 
         Stream { inner: stream }
     }
 
     pub fn write(&mut self, chunks: Vec<Vec<u8>>) {
         for chunk in chunks {
-            self.inner.write(&chunk).expect("failed to send chunk");
+            self.inner
+                .get_mut()
+                .write_all(&chunk)
+                .expect("failed to send chunk");
         }
     }
 
     pub fn close(self) {
-        let _ = self.inner.shutdown(net::Shutdown::Both);
+        let _ = self.inner.close();
     }
 }
 
 pub struct TlsStream {
-    inner: rustls::StreamOwned<rustls::ClientConnection, TcpStream>,
+    inner: tcp::Client,
 }
 
-pub fn tls_stream() -> TlsStream {
-    TlsStream::new()
+pub fn tls_stream(server: &Server) -> TlsStream {
+    TlsStream::new(server)
 }
 
 impl TlsStream {
-    fn new() -> Self {
+    fn new(server: &Server) -> Self {
         let root_store = {
             let mut root_store = rustls::RootCertStore::empty();
 
@@ -54,7 +57,7 @@ impl TlsStream {
                 .expect("failed to read native certificates")
             {
                 root_store
-                    .add(&rustls::Certificate(cert.0))
+                    .add(cert.0.into())
                     .expect("failed to add certificate");
             }
 
@@ -62,34 +65,26 @@ impl TlsStream {
         };
 
         let config = rustls::ClientConfig::builder()
-            .with_safe_defaults()
             .with_root_certificates(root_store)
             .with_no_client_auth();
 
-        let connection = rustls::ClientConnection::new(
-            Arc::new(config),
-            SERVER_HOST
-                .try_into()
-                .expect("failed to convert the server host into a server name"),
-        )
-        .expect("failed to initiate connection");
-
-        let stream = TcpStream::connect(SERVER_ADDR).unwrap();
+        let stream = tcp::Client::connect_tls(server.addr(), SERVER_HOST, Arc::new(config))
+            .expect("failed to initiate connection");
 
-        TlsStream {
-            inner: rustls::StreamOwned::new(connection, stream),
-        }
+        TlsStream { inner: stream }
     }
 
     pub fn write(&mut self, chunks: Vec<Vec<u8>>) {
+        let stream = self.inner.get_mut();
+
         for chunk in chunks {
-            self.inner.write(&chunk).expect("failed to send chunk");
+            stream.write_all(&chunk).expect("failed to send chunk");
         }
 
-        self.inner.flush().expect("failed to flush chunk");
+        stream.flush().expect("failed to flush chunk");
     }
 
     pub fn close(self) {
-        let _ = self.inner.sock.shutdown(net::Shutdown::Both);
+        let _ = self.inner.close();
     }
 }
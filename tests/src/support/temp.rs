@@ -0,0 +1,49 @@
+use std::{
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
+};
+
+/**
+A temporary directory that's removed when it's dropped.
+
+Keep the guard alive until after any assertions on its contents,
+so a failing case still cleans up after itself.
+*/
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> &str {
+        self.path.to_str().expect("invalid path")
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/**
+Create a new, empty directory for a test case.
+
+Each case runs once per process, so its name is unique within a run.
+*/
+pub fn dir(name: &str) -> TempDir {
+    let path = env::temp_dir().join(format!("sqelf-{}-{}", name, process::id()));
+
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).expect("failed to create temp dir");
+
+    TempDir { path }
+}
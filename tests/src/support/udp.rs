@@ -1,18 +1,23 @@
-use std::net::UdpSocket;
+use sqelf::send::udp;
 
-use super::SERVER_ADDR;
+use super::server::Server;
 
 pub struct Sock {
-    inner: UdpSocket,
+    inner: udp::Client,
 }
 
-pub fn sock() -> Sock {
-    Sock::new()
+pub fn sock(server: &Server) -> Sock {
+    Sock::new(server)
+}
+
+pub fn client(server: &Server, config: udp::Config) -> udp::Client {
+    udp::Client::connect(server.addr(), config).expect("failed to bind client socket")
 }
 
 impl Sock {
-    fn new() -> Sock {
-        let sock = UdpSocket::bind("127.0.0.1:0").expect("failed to bind client socket");
+    fn new(server: &Server) -> Sock {
+        let sock = udp::Client::connect(server.addr(), Default::default())
+            .expect("failed to bind client socket");
 
         Sock { inner: sock }
     }
@@ -20,7 +25,7 @@ impl Sock {
     pub fn send(&mut self, dgrams: Vec<Vec<u8>>) {
         for dgram in dgrams {
             self.inner
-                .send_to(&dgram, SERVER_ADDR)
+                .send_raw(&dgram)
                 .expect("failed to send datagram");
         }
     }